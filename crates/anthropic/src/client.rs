@@ -34,8 +34,7 @@ fn create_headers(api_key: String) -> Result<HeaderMap> {
 impl Client {
     /// Creates a new client with the given API key.
     pub fn new(api_key: String) -> Result<Self> {
-        let reqwest = ReqwestClient::builder()
-            .timeout(Duration::from_secs(300))
+        let reqwest = http_client::builder(Duration::from_secs(300))?
             .build()
             .context("can't create reqwest client")?;
 
@@ -93,6 +92,7 @@ impl Client {
     }
 
     /// Makes a POST request to the OpenAi API that returns a SSE stream.
+    /// Read its events with `http_client::next` to apply `HTTP_READ_TIMEOUT`.
     pub async fn post_stream(&self, endpoint: &str, body: String) -> Result<EventSource> {
         let mut url = self.base_url.clone();
         url.push_str(endpoint);
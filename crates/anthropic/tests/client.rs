@@ -0,0 +1,58 @@
+use anthropic::client::Client;
+use mock_provider::{fixtures, MockServer, Response};
+use reqwest_eventsource::Event;
+
+fn client(server: &MockServer) -> Client {
+    let mut client = Client::new("test-key".to_string()).unwrap();
+    client.set_base_url(server.url());
+    client
+}
+
+#[tokio::test]
+async fn post_stream() {
+    let server = MockServer::start();
+    server.mock("/v1/complete", Response::sse(fixtures::ANTHROPIC_COMPLETE));
+
+    let mut events = client(&server)
+        .post_stream("/v1/complete", r#"{"stream":true}"#.to_string())
+        .await
+        .unwrap();
+
+    let mut content = String::new();
+    while let Some(event) = http_client::next(&mut events).await {
+        match event.unwrap().unwrap() {
+            Event::Open => continue,
+            Event::Message(message) if message.event != "completion" => continue,
+            Event::Message(message) => {
+                let chunk: serde_json::Value = serde_json::from_str(&message.data).unwrap();
+                content.push_str(chunk["completion"].as_str().unwrap());
+                if !chunk["stop_reason"].is_null() {
+                    break;
+                }
+            }
+        }
+    }
+    events.close();
+
+    assert_eq!(content, "Hello world");
+    assert_eq!(server.requests()[0].header("x-api-key"), Some("test-key"));
+}
+
+#[tokio::test]
+async fn post() {
+    let server = MockServer::start();
+    server.mock(
+        "/v1/complete",
+        Response::json(200, fixtures::ANTHROPIC_COMPLETION),
+    );
+
+    let response: serde_json::Value = client(&server)
+        .post("/v1/complete", "{}".to_string())
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+
+    assert_eq!(response["completion"], "Hello world");
+}
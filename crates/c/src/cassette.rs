@@ -0,0 +1,268 @@
+use std::future::Future;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+
+use color_eyre::eyre::{bail, format_err, Report, Result};
+use eventsource_stream::Eventsource;
+use reqwest_eventsource::Event;
+use serde::{Deserialize, Serialize};
+use tokio_stream::{Stream, StreamExt};
+
+/// Stream of server-sent events, either from the network or from a cassette.
+pub type Events = Pin<Box<dyn Stream<Item = Result<Event>> + Send>>;
+
+/// Directory holding the request sent to the provider and the raw response it sent back.
+///
+/// The request body is saved as `001.request.json`, and the response as `001.response.sse` when
+/// streaming or `001.response.json` otherwise, using the same layout as `e --record`. Responses
+/// refused by the provider are saved as `001.error.json`, with their status and body.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Cassette {
+    /// Send the request and save it, together with the response, on the directory.
+    Record(PathBuf),
+    /// Read the response from the directory instead of sending the request. Fails if the request
+    /// differs from the recorded one.
+    Replay(PathBuf),
+}
+
+impl Cassette {
+    /// Creates the cassette from the `--record` and `--replay` options.
+    pub fn from_options(record: Option<String>, replay: Option<String>) -> Option<Self> {
+        match (record, replay) {
+            (Some(dir), _) => Some(Cassette::Record(dir.into())),
+            (None, Some(dir)) => Some(Cassette::Replay(dir.into())),
+            (None, None) => None,
+        }
+    }
+
+    pub fn is_replay(&self) -> bool {
+        matches!(self, Cassette::Replay(_))
+    }
+}
+
+/// Sends the request with `send` and reads its response as a stream of events, saving the body
+/// and the events when recording, or reads the events from disk when replaying.
+pub async fn stream<E>(
+    cassette: Option<&Cassette>,
+    body: &str,
+    send: impl Future<Output = std::result::Result<reqwest::Response, E>>,
+) -> Result<Events>
+where
+    Report: From<E>,
+{
+    match cassette {
+        None => Ok(events(send.await?).await?),
+        Some(Cassette::Record(dir)) => {
+            let mut file = create(dir, body, "sse")?;
+            let events = events(send.await?)
+                .await
+                .map_err(|e| save_error(dir, e))?
+                .map(move |event| {
+                    if let Ok(Event::Message(message)) = &event {
+                        let sse = to_sse(&message.event, &message.data);
+                        if let Err(e) = file.write_all(sse.as_bytes()) {
+                            tracing::event!(tracing::Level::WARN, "unable to record event: {e}");
+                        }
+                    }
+                    event
+                });
+            Ok(Box::pin(events))
+        }
+        Some(Cassette::Replay(dir)) => {
+            check_request(dir, body)?;
+            if let Some(error) = ErrorResponse::load(dir)? {
+                return Err(error.into());
+            }
+            let content = read(&dir.join("001.response.sse"))?;
+            Ok(Box::pin(tokio_stream::iter(
+                parse_sse(&content).into_iter().map(Ok),
+            )))
+        }
+    }
+}
+
+/// Sends the request with `send` and returns the response text, saving the body and the response
+/// when recording, or reads the response from disk when replaying.
+pub async fn text(
+    cassette: Option<&Cassette>,
+    body: &str,
+    send: impl Future<Output = Result<reqwest::Response>>,
+) -> Result<String> {
+    match cassette {
+        None => success(send.await?)
+            .await?
+            .text()
+            .await
+            .map_err(Report::from),
+        Some(Cassette::Record(dir)) => {
+            let mut file = create(dir, body, "json")?;
+            let response = success(send.await?).await.map_err(|e| save_error(dir, e))?;
+            let text = response.text().await?;
+            file.write_all(text.as_bytes())?;
+            Ok(text)
+        }
+        Some(Cassette::Replay(dir)) => {
+            check_request(dir, body)?;
+            if let Some(error) = ErrorResponse::load(dir)? {
+                return Err(error.into());
+            }
+            read(&dir.join("001.response.json"))
+        }
+    }
+}
+
+/// Reads the events of a streaming response.
+async fn events(response: reqwest::Response) -> Result<Events> {
+    let events = success(response)
+        .await?
+        .bytes_stream()
+        .eventsource()
+        .map(|event| match event {
+            Ok(event) => Ok(Event::Message(event)),
+            Err(e) => Err(format_err!("{e}")),
+        });
+
+    Ok(Box::pin(tokio_stream::once(Ok(Event::Open)).chain(events)))
+}
+
+/// Gives the response back if the provider accepted the request, or its status and body as an
+/// `ErrorResponse` otherwise.
+async fn success(response: reqwest::Response) -> Result<reqwest::Response> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+
+    Err(ErrorResponse {
+        status: status.as_u16(),
+        body: response.text().await.unwrap_or_default(),
+    }
+    .into())
+}
+
+/// Saves `error` on the cassette if it is an `ErrorResponse`, and gives it back.
+fn save_error(dir: &Path, error: Report) -> Report {
+    if let Some(response) = error.downcast_ref::<ErrorResponse>() {
+        if let Err(e) = response.save(dir) {
+            tracing::event!(tracing::Level::WARN, "unable to record error: {e}");
+        }
+    }
+    error
+}
+
+/// Fails unless `body` is the request recorded on the cassette.
+fn check_request(dir: &Path, body: &str) -> Result<()> {
+    let path = dir.join("001.request.json");
+    let recorded: serde_json::Value = serde_json::from_str(&read(&path)?)
+        .map_err(|e| format_err!("invalid request on {path:?}: {e}"))?;
+    let body: serde_json::Value = serde_json::from_str(body)?;
+
+    if recorded != body {
+        bail!("the request doesn't match the one recorded on {path:?}");
+    }
+
+    Ok(())
+}
+
+/// A response refused by the provider.
+#[derive(Debug, Serialize, Deserialize)]
+struct ErrorResponse {
+    status: u16,
+    body: String,
+}
+
+impl ErrorResponse {
+    fn save(&self, dir: &Path) -> Result<()> {
+        std::fs::write(dir.join("001.error.json"), serde_json::to_string(self)?)?;
+        Ok(())
+    }
+
+    fn load(dir: &Path) -> Result<Option<Self>> {
+        let path = dir.join("001.error.json");
+        if !path.exists() {
+            return Ok(None);
+        }
+        Ok(Some(serde_json::from_str(&read(&path)?)?))
+    }
+}
+
+impl std::fmt::Display for ErrorResponse {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "the provider answered {}: {}", self.status, self.body)
+    }
+}
+
+impl std::error::Error for ErrorResponse {}
+
+/// Saves the request body and creates the file for the response.
+fn create(dir: &Path, body: &str, format: &str) -> Result<std::fs::File> {
+    std::fs::create_dir_all(dir)?;
+    std::fs::write(dir.join("001.request.json"), body)?;
+    let error = dir.join("001.error.json");
+    if error.exists() {
+        std::fs::remove_file(error)?;
+    }
+
+    Ok(std::fs::File::create(
+        dir.join(format!("001.response.{format}")),
+    )?)
+}
+
+fn read(path: &Path) -> Result<String> {
+    std::fs::read_to_string(path).map_err(|e| format_err!("unable to read {path:?}: {e}"))
+}
+
+/// Writes an event in the server-sent events wire format.
+fn to_sse(event: &str, data: &str) -> String {
+    let mut sse = String::new();
+    if !event.is_empty() && event != "message" {
+        sse.push_str(&format!("event: {event}\n"));
+    }
+    for line in data.split('\n') {
+        sse.push_str(&format!("data: {line}\n"));
+    }
+    sse.push('\n');
+    sse
+}
+
+/// Parses the events saved by `to_sse`.
+fn parse_sse(content: &str) -> Vec<Event> {
+    let mut events = Vec::new();
+    let mut event = String::new();
+    let mut data: Option<String> = None;
+
+    for line in content.lines().chain(std::iter::once("")) {
+        if line.is_empty() {
+            let event = std::mem::take(&mut event);
+            if let Some(data) = data.take() {
+                events.push(Event::Message(eventsource_stream::Event {
+                    event: if event.is_empty() {
+                        "message".to_string()
+                    } else {
+                        event
+                    },
+                    data,
+                    ..Default::default()
+                }));
+            }
+            continue;
+        }
+
+        let (field, value) = line.split_once(':').unwrap_or((line, ""));
+        let value = value.strip_prefix(' ').unwrap_or(value);
+        match field {
+            "event" => event = value.to_string(),
+            "data" => match &mut data {
+                Some(data) => {
+                    data.push('\n');
+                    data.push_str(value);
+                }
+                None => data = Some(value.to_string()),
+            },
+            _ => {}
+        }
+    }
+
+    events
+}
@@ -2,4 +2,5 @@ pub mod anthropic;
 pub mod nlpcloud;
 pub mod ollama;
 pub mod openai;
+pub mod usage;
 pub mod vertex;
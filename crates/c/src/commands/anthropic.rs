@@ -6,6 +6,7 @@ use tokio::sync::mpsc;
 use tokio_stream::wrappers::ReceiverStream;
 use tokio_stream::{Stream, StreamExt};
 
+use crate::cassette::{self, Cassette};
 use crate::session::{Message, Role, Session, Vendor};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -14,6 +15,15 @@ pub struct Chunk {
     pub stop_reason: Option<String>,
     pub model: String,
     pub stop: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub usage: Option<Usage>,
+}
+
+/// Token usage, reported by the API on the last completion event.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Usage {
+    pub input_tokens: u32,
+    pub output_tokens: u32,
 }
 
 #[derive(ValueEnum, Debug, Default, Clone, Copy, Serialize, Deserialize)]
@@ -114,6 +124,8 @@ impl From<RequestOptions> for SessionOptions {
 pub struct Response {
     pub completion: String,
     pub stop_reason: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub usage: Option<Usage>,
 }
 
 #[derive(Default, Clone, Parser, Debug, Serialize, Deserialize)]
@@ -156,10 +168,34 @@ pub struct CommandOptions {
     /// Anthropic API Key to use. Will default to the environment variable `ANTHROPIC_API_KEY` if not set.
     #[arg(long, env = "ANTHROPIC_API_KEY")]
     #[serde(skip)]
-    anthropic_api_key: String,
+    anthropic_api_key: Option<String>,
+    /// Shell command that prints the Anthropic API Key (e.g. `pass show anthropic`.) Used when the key is not
+    /// set.
+    #[arg(long, env = "ANTHROPIC_API_KEY_COMMAND")]
+    #[serde(skip)]
+    anthropic_api_key_command: Option<String>,
+    /// Age encrypted file holding the Anthropic API Key. Used when the key is not set.
+    #[arg(long, env = "ANTHROPIC_API_KEY_FILE")]
+    #[serde(skip)]
+    anthropic_api_key_file: Option<String>,
+    /// Anthropic API base url. Will default to `https://api.anthropic.com` if not set.
+    #[arg(long)]
+    #[serde(skip)]
+    base_url: Option<String>,
+    /// Save the request body and the raw response on this directory.
+    #[arg(long, value_name = "DIR", conflicts_with = "replay")]
+    #[serde(skip)]
+    record: Option<String>,
+    /// Replay the response saved with `--record` on this directory instead of calling the API.
+    #[arg(long, value_name = "DIR")]
+    #[serde(skip)]
+    replay: Option<String>,
     /// Silent mode
     #[clap(short, long, action, default_value_t = false)]
     silent: bool,
+    /// Print the token usage and estimated cost of the request to stderr.
+    #[clap(long)]
+    usage: bool,
     /// Wether to incrementally stream the response using SSE.
     #[clap(long)]
     stream: bool,
@@ -242,14 +278,31 @@ pub async fn run(mut options: CommandOptions) -> Result<()> {
         session.history.push(message);
     }
 
+    let mut truncated = false;
+
     // Call the completion endpoint with the current session.
     if session.meta.stream {
         let mut acc: String = Default::default();
+        let mut usage: Option<Usage> = None;
+        let cancel = cancel::CancellationToken::on_interrupt();
         let chunks = complete_stream(&session).await?;
 
         tokio::pin!(chunks);
 
-        while let Some(chunk) = chunks.next().await {
+        loop {
+            let chunk = tokio::select! {
+                biased;
+                _ = cancel.cancelled() => {
+                    truncated = true;
+                    break;
+                }
+                chunk = chunks.next() => chunk,
+            };
+
+            let Some(chunk) = chunk else {
+                break;
+            };
+
             if chunk.is_err() {
                 color_eyre::eyre::bail!("Error streaming response: {:?}", chunk);
             }
@@ -262,20 +315,41 @@ pub async fn run(mut options: CommandOptions) -> Result<()> {
             // Stop the spinner when the stream starts.
             spinner.stop();
 
+            if chunk.usage.is_some() {
+                usage = chunk.usage;
+            }
+
             // Print the response output.
             print!("{}", chunk.completion);
 
             acc.push_str(&chunk.completion);
         }
+
+        if truncated {
+            spinner.stop();
+            cancel::restore_terminal();
+        }
+
         // Add a new line at the end to make sure the prompt is on a new line.
         println!();
 
-        // Save the response to the session.
-        session.history.push(Message::new(
-            acc.trim().to_string(),
-            Role::Assistant,
-            session.meta.pin,
-        ));
+        record_usage(&session, usage, &acc)?;
+
+        // Save the response to the session. Interrupted responses are kept, marked as
+        // truncated, unless nothing arrived before the interruption.
+        if truncated && acc.trim().is_empty() {
+            color_eyre::eyre::bail!("Interrupted");
+        } else if truncated {
+            session
+                .history
+                .push(Message::truncated(acc.trim().to_string(), session.meta.pin));
+        } else {
+            session.history.push(Message::new(
+                acc.trim().to_string(),
+                Role::Assistant,
+                session.meta.pin,
+            ));
+        }
     } else {
         let response = complete(&session).await?;
 
@@ -285,6 +359,8 @@ pub async fn run(mut options: CommandOptions) -> Result<()> {
         // Print the response output.
         print_output(&session.meta.format, &response)?;
 
+        record_usage(&session, response.usage.clone(), &response.completion)?;
+
         // Save the response to the session.
         session.history.push(Message::new(
             response.completion.trim().to_string(),
@@ -303,6 +379,10 @@ pub async fn run(mut options: CommandOptions) -> Result<()> {
         session.save()?;
     }
 
+    if truncated {
+        color_eyre::eyre::bail!("Interrupted");
+    }
+
     Ok(())
 }
 
@@ -397,9 +477,30 @@ pub fn merge_options(
     }
 
     session.meta.save = !options.nosave;
-    session.meta.key = options.anthropic_api_key;
+    session.meta.base_url = options.base_url;
+    session.meta.cassette = Cassette::from_options(options.record, options.replay);
+    let key = crate::utils::api_key(
+        options.anthropic_api_key,
+        options.anthropic_api_key_command,
+        options.anthropic_api_key_file,
+        "ANTHROPIC_API_KEY",
+    );
+    session.meta.key = match key {
+        // Replayed responses are read from disk, so the key is never sent.
+        Err(_)
+            if session
+                .meta
+                .cassette
+                .as_ref()
+                .is_some_and(Cassette::is_replay) =>
+        {
+            String::new()
+        }
+        key => key?,
+    };
     session.meta.stream = options.stream;
     session.meta.silent = options.silent;
+    session.meta.usage = options.usage;
     session.meta.pin = options.pin;
 
     Ok(session)
@@ -413,16 +514,33 @@ async fn complete_stream(
     tracing::event!(tracing::Level::INFO, "body: {:?}", body);
 
     tracing::event!(tracing::Level::INFO, "Creating client...");
-    let client = Client::new(session.meta.key.clone())?;
+    let mut client = Client::new(session.meta.key.clone())?;
+    if let Some(base_url) = &session.meta.base_url {
+        client.set_base_url(base_url.clone());
+    }
 
-    let mut event_source = client.post_stream("/v1/complete", body).await?;
+    let mut event_source = cassette::stream(
+        session.meta.cassette.as_ref(),
+        &body,
+        client.post("/v1/complete", body.clone()),
+    )
+    .await?;
 
     let (tx, rx) = mpsc::channel(100);
     tracing::event!(tracing::Level::INFO, "Streaming output...");
     tokio::spawn(async move {
-        while let Some(ev) = event_source.next().await {
+        while let Some(ev) = http_client::next(&mut event_source).await {
             match ev {
                 Err(e) => {
+                    tracing::event!(tracing::Level::ERROR, "e: {e}");
+                    let _ = tx
+                        .send(Err(color_eyre::eyre::format_err!(
+                            "Error streaming response: {e}"
+                        )))
+                        .await;
+                    return;
+                }
+                Ok(Err(e)) => {
                     tracing::event!(tracing::Level::ERROR, "e: {e}");
                     if tx
                         .send(Err(color_eyre::eyre::format_err!(
@@ -434,7 +552,7 @@ async fn complete_stream(
                         return;
                     }
                 }
-                Ok(event) => match event {
+                Ok(Ok(event)) => match event {
                     reqwest_eventsource::Event::Open { .. } => {
                         tracing::event!(tracing::Level::DEBUG, "Open SSE stream...");
                     }
@@ -478,10 +596,12 @@ async fn complete_stream(
                                 tracing::event!(
                                     tracing::Level::INFO,
                                     "Found stop sequence: {}",
-                                    &chunk.stop.unwrap()
+                                    chunk.stop.as_deref().unwrap_or_default()
                                 );
                             }
 
+                            // The last event carries the usage of the request, if any.
+                            let _ = tx.send(Ok(chunk)).await;
                             break;
                         }
 
@@ -504,12 +624,18 @@ async fn complete(session: &Session<SessionOptions>) -> Result<Response> {
     tracing::event!(tracing::Level::INFO, "body: {:?}", body);
 
     tracing::event!(tracing::Level::INFO, "Creating client...");
-    let client = Client::new(session.meta.key.clone())?;
+    let mut client = Client::new(session.meta.key.clone())?;
+    if let Some(base_url) = &session.meta.base_url {
+        client.set_base_url(base_url.clone());
+    }
 
-    let res = client.post("/v1/complete", body.clone()).await?;
-    tracing::event!(tracing::Level::INFO, "res: {:?}", res);
+    let text = cassette::text(session.meta.cassette.as_ref(), &body, async {
+        let res = client.post("/v1/complete", body.clone()).await?;
+        tracing::event!(tracing::Level::INFO, "res: {:?}", res);
 
-    let text = res.text().await?;
+        Ok(res)
+    })
+    .await?;
     tracing::event!(tracing::Level::INFO, "text: {:?}", text);
 
     let response: Response = serde_json::from_str(&text).map_err(|e| {
@@ -523,6 +649,30 @@ async fn complete(session: &Session<SessionOptions>) -> Result<Response> {
     Ok(response)
 }
 
+/// Appends the usage of a completion to the usage ledger. The legacy completion API doesn't
+/// always report it, so the token counts are estimated from the prompt and the completion.
+fn record_usage(
+    session: &Session<SessionOptions>,
+    usage: Option<Usage>,
+    completion: &str,
+) -> Result<()> {
+    let (input_tokens, output_tokens) = match usage {
+        Some(usage) => (usage.input_tokens, usage.output_tokens),
+        None => (
+            crate::usage::estimate_tokens(&join_messages(&session.history)),
+            crate::usage::estimate_tokens(completion),
+        ),
+    };
+
+    crate::usage::record(
+        Vendor::Anthropic,
+        session.options.model.unwrap_or_default().as_str(),
+        Some(input_tokens),
+        Some(output_tokens),
+        session.meta.usage,
+    )
+}
+
 /// Prints the Response output according to the user options.
 pub fn print_output(format: &crate::Output, response: &Response) -> Result<()> {
     match format {
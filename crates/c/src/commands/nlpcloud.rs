@@ -3,7 +3,6 @@ use std::time::Duration;
 use clap::{Parser, ValueEnum};
 use color_eyre::eyre::Result;
 use reqwest::header::{HeaderMap, HeaderValue};
-use reqwest::Client as ReqwestClient;
 use serde::{Deserialize, Serialize};
 
 use crate::session::{Message, Role, Session, Vendor};
@@ -81,10 +80,22 @@ pub struct CommandOptions {
     /// NLP Cloud Key to use. Will default to the environment variable `NLPCLOUD_API_KEY` if not set.
     #[arg(long, env = "NLPCLOUD_API_KEY")]
     #[serde(skip)]
-    nlpcloud_api_key: String,
+    nlpcloud_api_key: Option<String>,
+    /// Shell command that prints the NLP Cloud Key (e.g. `pass show nlpcloud`.) Used when the key is not
+    /// set.
+    #[arg(long, env = "NLPCLOUD_API_KEY_COMMAND")]
+    #[serde(skip)]
+    nlpcloud_api_key_command: Option<String>,
+    /// Age encrypted file holding the NLP Cloud Key. Used when the key is not set.
+    #[arg(long, env = "NLPCLOUD_API_KEY_FILE")]
+    #[serde(skip)]
+    nlpcloud_api_key_file: Option<String>,
     /// Silent mode
     #[clap(short, long, action, default_value_t = false)]
     silent: bool,
+    /// Print the token usage and estimated cost of the request to stderr.
+    #[clap(long)]
+    usage: bool,
     /// Wether to pin this message to the message history.
     #[clap(long)]
     pin: bool,
@@ -168,6 +179,23 @@ pub async fn run(mut options: CommandOptions) -> Result<()> {
     // Print the response output.
     print_output(&session.meta.format, &response)?;
 
+    // NLP Cloud doesn't report the token usage, so it is estimated from the conversation.
+    let history = session
+        .history
+        .iter()
+        .map(|m| m.content.as_str())
+        .collect::<Vec<&str>>()
+        .join("\n");
+    crate::usage::record(
+        Vendor::NLPCloud,
+        session.options.model.unwrap_or_default().as_str(),
+        Some(crate::usage::estimate_tokens(&format!(
+            "{history}\n{prompt}"
+        ))),
+        Some(crate::usage::estimate_tokens(&response.response)),
+        session.meta.usage,
+    )?;
+
     // Save the input and the response to the session.
     session
         .history
@@ -209,8 +237,14 @@ pub fn merge_options(
     }
 
     session.meta.save = !options.nosave;
-    session.meta.key = options.nlpcloud_api_key;
+    session.meta.key = crate::utils::api_key(
+        options.nlpcloud_api_key,
+        options.nlpcloud_api_key_command,
+        options.nlpcloud_api_key_file,
+        "NLPCLOUD_API_KEY",
+    )?;
     session.meta.silent = options.silent;
+    session.meta.usage = options.usage;
     session.meta.pin = options.pin;
     session.meta.reverse = options.reverse;
 
@@ -241,9 +275,7 @@ async fn complete(session: &Session<SessionOptions>, input: String) -> Result<Re
     let body = create_body(session, input)?;
     tracing::event!(tracing::Level::INFO, "body: {:?}", body);
 
-    let reqwest = ReqwestClient::builder()
-        .timeout(Duration::from_secs(300))
-        .build()?;
+    let reqwest = http_client::builder(Duration::from_secs(300))?.build()?;
     tracing::event!(tracing::Level::INFO, "Created HTTP client...");
 
     let mut headers = HeaderMap::new();
@@ -13,6 +13,10 @@ use crate::session::{Message, Role, Session, Vendor};
 pub struct Chunk {
     pub response: String,
     pub done: Option<bool>,
+    /// Number of tokens in the prompt, sent with the last chunk.
+    pub prompt_eval_count: Option<u32>,
+    /// Number of tokens in the response, sent with the last chunk.
+    pub eval_count: Option<u32>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Default, Clone)]
@@ -72,6 +76,9 @@ pub struct CommandOptions {
     /// Silent mode
     #[clap(short, long, action, default_value_t = false)]
     silent: bool,
+    /// Print the token usage and estimated cost of the request to stderr.
+    #[clap(long)]
+    usage: bool,
     /// Wether to pin this message to the message history.
     #[clap(long)]
     pin: bool,
@@ -124,13 +131,13 @@ pub async fn run(mut options: CommandOptions) -> Result<()> {
         } else {
             tracing::event!(tracing::Level::INFO, "Session does not exist, creating...");
             let session: Session<SessionOptions> =
-                Session::new(session, Vendor::Anthropic, session_options, 16000);
+                Session::new(session, Vendor::Ollama, session_options, 16000);
             session
         }
     } else {
         tracing::event!(tracing::Level::INFO, "Creating anonymous session...");
         let session: Session<SessionOptions> =
-            Session::anonymous(Vendor::Anthropic, session_options, 16000);
+            Session::anonymous(Vendor::Ollama, session_options, 16000);
         session
     };
 
@@ -145,11 +152,28 @@ pub async fn run(mut options: CommandOptions) -> Result<()> {
     }
 
     let mut acc: String = Default::default();
+    let cancel = cancel::CancellationToken::on_interrupt();
     let chunks = complete_stream(&session).await?;
 
     tokio::pin!(chunks);
 
-    while let Some(chunk) = chunks.next().await {
+    let mut truncated = false;
+    let mut counts: (Option<u32>, Option<u32>) = (None, None);
+
+    loop {
+        let chunk = tokio::select! {
+            biased;
+            _ = cancel.cancelled() => {
+                truncated = true;
+                break;
+            }
+            chunk = chunks.next() => chunk,
+        };
+
+        let Some(chunk) = chunk else {
+            break;
+        };
+
         if chunk.is_err() {
             color_eyre::eyre::bail!("Error streaming response: {:?}", chunk);
         }
@@ -162,20 +186,47 @@ pub async fn run(mut options: CommandOptions) -> Result<()> {
         // Stop the spinner when the stream starts.
         spinner.stop();
 
+        if chunk.done == Some(true) {
+            counts = (chunk.prompt_eval_count, chunk.eval_count);
+        }
+
         // Print the response output.
         print!("{}", chunk.response);
 
         acc.push_str(&chunk.response);
     }
+
+    if truncated {
+        spinner.stop();
+        cancel::restore_terminal();
+    }
+
     // Add a new line at the end to make sure the prompt is on a new line.
     println!();
 
-    // Save the response to the session.
-    session.history.push(Message::new(
-        acc.trim().to_string(),
-        Role::Assistant,
-        session.meta.pin,
-    ));
+    crate::usage::record(
+        Vendor::Ollama,
+        &session.options.model,
+        counts.0,
+        counts.1,
+        session.meta.usage,
+    )?;
+
+    // Save the response to the session. Interrupted responses are kept, marked as
+    // truncated, unless nothing arrived before the interruption.
+    if truncated && acc.trim().is_empty() {
+        color_eyre::eyre::bail!("Interrupted");
+    } else if truncated {
+        session
+            .history
+            .push(Message::truncated(acc.trim().to_string(), session.meta.pin));
+    } else {
+        session.history.push(Message::new(
+            acc.trim().to_string(),
+            Role::Assistant,
+            session.meta.pin,
+        ));
+    }
 
     // Save the session to a file.
     if session.meta.save {
@@ -187,6 +238,10 @@ pub async fn run(mut options: CommandOptions) -> Result<()> {
         session.save()?;
     }
 
+    if truncated {
+        color_eyre::eyre::bail!("Interrupted");
+    }
+
     Ok(())
 }
 
@@ -266,6 +321,7 @@ pub fn merge_options(
 
     session.meta.save = !options.nosave;
     session.meta.silent = options.silent;
+    session.meta.usage = options.usage;
     session.meta.pin = options.pin;
 
     Ok(session)
@@ -279,9 +335,7 @@ async fn complete_stream(
     tracing::event!(tracing::Level::INFO, "body: {:?}", body);
 
     tracing::event!(tracing::Level::INFO, "Creating client...");
-    let client = reqwest::Client::builder()
-        .timeout(Duration::from_secs(60))
-        .build()?;
+    let client = http_client::builder(Duration::from_secs(60))?.build()?;
 
     tracing::event!(tracing::Level::INFO, "Creating API client headers...");
     let mut headers = reqwest::header::HeaderMap::new();
@@ -300,9 +354,18 @@ async fn complete_stream(
     let (tx, rx) = mpsc::channel(100);
     tracing::event!(tracing::Level::INFO, "Streaming output...");
     tokio::spawn(async move {
-        while let Some(ev) = event_source.next().await {
+        while let Some(ev) = http_client::next(&mut event_source).await {
             match ev {
                 Err(e) => {
+                    tracing::event!(tracing::Level::ERROR, "e: {e}");
+                    let _ = tx
+                        .send(Err(color_eyre::eyre::format_err!(
+                            "Error streaming response: {e}"
+                        )))
+                        .await;
+                    return;
+                }
+                Ok(Err(e)) => {
                     tracing::event!(tracing::Level::ERROR, "e: {e}");
                     if tx
                         .send(Err(color_eyre::eyre::format_err!(
@@ -314,7 +377,7 @@ async fn complete_stream(
                         return;
                     }
                 }
-                Ok(event) => match event {
+                Ok(Ok(event)) => match event {
                     reqwest_eventsource::Event::Open { .. } => {
                         tracing::event!(tracing::Level::DEBUG, "Open SSE stream...");
                     }
@@ -347,6 +410,8 @@ async fn complete_stream(
                                 tracing::Level::INFO,
                                 "Stopping stream due to stop_reason",
                             );
+                            // The last chunk carries the token counts of the request.
+                            let _ = tx.send(Ok(chunk)).await;
                             break;
                         }
 
@@ -6,6 +6,7 @@ use tokio::sync::mpsc;
 use tokio_stream::wrappers::ReceiverStream;
 use tokio_stream::{Stream, StreamExt};
 
+use crate::cassette::{self, Cassette};
 use crate::session::{Message, Role, Session, Vendor};
 
 /// Stores a message.
@@ -95,6 +96,8 @@ struct RequestOptions {
     #[serde(skip_serializing_if = "std::ops::Not::not")]
     stream: bool,
     #[serde(skip_serializing_if = "Option::is_none")]
+    stream_options: Option<StreamOptions>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     stop: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     presence_penalty: Option<f32>,
@@ -108,6 +111,13 @@ struct RequestOptions {
     max_tokens: Option<u32>,
 }
 
+/// Options for streaming responses.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct StreamOptions {
+    /// Streams an additional chunk with the token usage of the request.
+    include_usage: bool,
+}
+
 #[derive(Default, Clone, Parser, Debug, Serialize, Deserialize)]
 pub struct CommandOptions {
     /// The content of the message to be sent to the chatbot. You can also populate this value
@@ -170,10 +180,34 @@ pub struct CommandOptions {
     /// OpenAI API Key to use. Will default to the environment variable `OPENAI_API_KEY` if not set.
     #[arg(long, env = "OPENAI_API_KEY")]
     #[serde(skip)]
-    openai_api_key: String,
+    openai_api_key: Option<String>,
+    /// Shell command that prints the OpenAI API Key (e.g. `pass show openai`.) Used when the key is not
+    /// set.
+    #[arg(long, env = "OPENAI_API_KEY_COMMAND")]
+    #[serde(skip)]
+    openai_api_key_command: Option<String>,
+    /// Age encrypted file holding the OpenAI API Key. Used when the key is not set.
+    #[arg(long, env = "OPENAI_API_KEY_FILE")]
+    #[serde(skip)]
+    openai_api_key_file: Option<String>,
+    /// OpenAI API base url. Will default to `https://api.openai.com` if not set.
+    #[arg(long)]
+    #[serde(skip)]
+    base_url: Option<String>,
+    /// Save the request body and the raw response on this directory.
+    #[arg(long, value_name = "DIR", conflicts_with = "replay")]
+    #[serde(skip)]
+    record: Option<String>,
+    /// Replay the response saved with `--record` on this directory instead of calling the API.
+    #[arg(long, value_name = "DIR")]
+    #[serde(skip)]
+    replay: Option<String>,
     /// Silent mode
     #[clap(short, long, action, default_value_t = false)]
     silent: bool,
+    /// Print the token usage and estimated cost of the request to stderr.
+    #[clap(long)]
+    usage: bool,
     /// Whether to incrementally stream the response using SSE.
     #[clap(long)]
     stream: bool,
@@ -207,6 +241,7 @@ impl From<CommandOptions> for RequestOptions {
             max_tokens: Some(options.max_tokens.unwrap_or(1000)),
             stop: options.stop,
             stream: options.stream,
+            stream_options: None,
             temperature: options.temperature,
             top_p: options.top_p,
             n: options.n,
@@ -277,6 +312,8 @@ pub struct Chunk {
     pub choices: Vec<ChunkChoice>,
     #[serde(default)]
     pub finish_reason: Option<String>,
+    #[serde(default)]
+    pub usage: Option<ChatUsage>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Default)]
@@ -370,20 +407,41 @@ pub async fn run(mut options: CommandOptions) -> Result<()> {
         session.history.push(message);
     }
 
+    let mut truncated = false;
+
     // Call the completion endpoint with the current session.
     if session.meta.stream {
         let mut acc: String = String::new();
+        let mut usage: Option<ChatUsage> = None;
+        let cancel = cancel::CancellationToken::on_interrupt();
         let chunks = complete_stream(&session).await?;
 
         tokio::pin!(chunks);
 
-        while let Some(chunk) = chunks.next().await {
+        loop {
+            let chunk = tokio::select! {
+                biased;
+                _ = cancel.cancelled() => {
+                    truncated = true;
+                    break;
+                }
+                chunk = chunks.next() => chunk,
+            };
+
+            let Some(chunk) = chunk else {
+                break;
+            };
+
             if chunk.is_err() {
                 color_eyre::eyre::bail!("Error streaming response: {:?}", chunk);
             }
 
             let chunk = chunk.unwrap();
 
+            if chunk.usage.is_some() {
+                usage = chunk.usage;
+            }
+
             if let Some(choice) = &chunk.choices.get(0) {
                 if let Some(delta) = &choice.delta {
                     if let Some(content) = &delta.content {
@@ -397,13 +455,35 @@ pub async fn run(mut options: CommandOptions) -> Result<()> {
             }
         }
 
+        if truncated {
+            spinner.stop();
+            cancel::restore_terminal();
+        }
+
         // Add a new line at the end to make sure the prompt is on a new line.
         println!();
 
-        // Save the response to the session.
-        session
-            .history
-            .push(Message::new(acc, Role::Assistant, session.meta.pin));
+        crate::usage::record(
+            Vendor::OpenAI,
+            &session.options.model.clone().unwrap_or_default(),
+            usage.as_ref().map(|usage| usage.prompt_tokens),
+            usage.as_ref().map(|usage| usage.completion_tokens),
+            session.meta.usage,
+        )?;
+
+        // Save the response to the session. Interrupted responses are kept, marked as
+        // truncated, unless nothing arrived before the interruption.
+        if truncated && acc.is_empty() {
+            color_eyre::eyre::bail!("Interrupted");
+        } else if truncated {
+            session
+                .history
+                .push(Message::truncated(acc, session.meta.pin));
+        } else {
+            session
+                .history
+                .push(Message::new(acc, Role::Assistant, session.meta.pin));
+        }
     } else {
         let response = complete(&session).await?;
 
@@ -413,6 +493,14 @@ pub async fn run(mut options: CommandOptions) -> Result<()> {
         // Print the response output.
         print_output(&session.meta.format, &response)?;
 
+        crate::usage::record(
+            Vendor::OpenAI,
+            &session.options.model.clone().unwrap_or_default(),
+            Some(response.usage.prompt_tokens),
+            Some(response.usage.completion_tokens),
+            session.meta.usage,
+        )?;
+
         // Save the response to the session
         session.history.push(Message::new(
             response.choices.first().unwrap().message.content.clone(),
@@ -431,6 +519,10 @@ pub async fn run(mut options: CommandOptions) -> Result<()> {
         session.save()?;
     }
 
+    if truncated {
+        color_eyre::eyre::bail!("Interrupted");
+    }
+
     Ok(())
 }
 
@@ -442,16 +534,33 @@ async fn complete_stream(
     tracing::event!(tracing::Level::INFO, "body: {:?}", body);
 
     tracing::event!(tracing::Level::INFO, "Creating client...");
-    let client = Client::new(session.meta.key.clone())?;
+    let mut client = Client::new(session.meta.key.clone())?;
+    if let Some(base_url) = &session.meta.base_url {
+        client.set_base_url(base_url.clone());
+    }
 
-    let mut event_source = client.post_stream("/v1/chat/completions", body).await?;
+    let mut event_source = cassette::stream(
+        session.meta.cassette.as_ref(),
+        &body,
+        client.post("/v1/chat/completions", body.clone()),
+    )
+    .await?;
 
     let (tx, rx) = mpsc::channel(100);
 
     tokio::spawn(async move {
-        while let Some(ev) = event_source.next().await {
+        while let Some(ev) = http_client::next(&mut event_source).await {
             match ev {
                 Err(e) => {
+                    tracing::event!(tracing::Level::ERROR, "e: {e}");
+                    let _ = tx
+                        .send(Err(color_eyre::eyre::format_err!(
+                            "Error streaming response: {e}"
+                        )))
+                        .await;
+                    return;
+                }
+                Ok(Err(e)) => {
                     tracing::event!(tracing::Level::ERROR, "e: {e}");
                     if tx
                         .send(Err(color_eyre::eyre::format_err!(
@@ -463,7 +572,7 @@ async fn complete_stream(
                         return;
                     }
                 }
-                Ok(event) => match event {
+                Ok(Ok(event)) => match event {
                     reqwest_eventsource::Event::Open { .. } => {
                         tracing::event!(tracing::Level::INFO, "Open SSE stream...");
                     }
@@ -509,12 +618,18 @@ async fn complete(session: &Session<SessionOptions>) -> Result<Response> {
     let body = create_body(session)?;
 
     tracing::event!(tracing::Level::INFO, "Creating client...");
-    let client = Client::new(session.meta.key.clone())?;
+    let mut client = Client::new(session.meta.key.clone())?;
+    if let Some(base_url) = &session.meta.base_url {
+        client.set_base_url(base_url.clone());
+    }
 
-    let res = client.post("/v1/chat/completions", body.clone()).await?;
-    tracing::event!(tracing::Level::INFO, "res: {:?}", res);
+    let text = cassette::text(session.meta.cassette.as_ref(), &body, async {
+        let res = client.post("/v1/chat/completions", body.clone()).await?;
+        tracing::event!(tracing::Level::INFO, "res: {:?}", res);
 
-    let text = res.text().await?;
+        Ok(res)
+    })
+    .await?;
     tracing::event!(tracing::Level::INFO, "text: {:?}", text);
 
     let response: Response = serde_json::from_str(&text).map_err(|e| {
@@ -607,14 +722,9 @@ pub fn merge_options(
                 m.content = options.system.unwrap();
             }
         } else {
-            session.history.insert(
-                0,
-                Message {
-                    content: options.system.unwrap(),
-                    role: Role::System,
-                    pin: true,
-                },
-            );
+            session
+                .history
+                .insert(0, Message::new(options.system.unwrap(), Role::System, true));
         }
     }
 
@@ -623,10 +733,31 @@ pub fn merge_options(
     }
 
     session.meta.save = !options.nosave;
-    session.meta.key = options.openai_api_key;
+    session.meta.base_url = options.base_url;
+    session.meta.cassette = Cassette::from_options(options.record, options.replay);
+    let key = crate::utils::api_key(
+        options.openai_api_key,
+        options.openai_api_key_command,
+        options.openai_api_key_file,
+        "OPENAI_API_KEY",
+    );
+    session.meta.key = match key {
+        // Replayed responses are read from disk, so the key is never sent.
+        Err(_)
+            if session
+                .meta
+                .cassette
+                .as_ref()
+                .is_some_and(Cassette::is_replay) =>
+        {
+            String::new()
+        }
+        key => key?,
+    };
     session.meta.stream = options.stream;
     session.meta.silent = options.silent;
     session.meta.pin = options.pin;
+    session.meta.usage = options.usage;
 
     Ok(session)
 }
@@ -701,6 +832,9 @@ impl From<&Session<SessionOptions>> for RequestOptions {
             n: session.options.n,
             logit_bias: session.options.logit_bias.clone(),
             stream: session.meta.stream,
+            stream_options: session.meta.stream.then_some(StreamOptions {
+                include_usage: true,
+            }),
             frequency_penalty: session.options.frequency_penalty,
             presence_penalty: session.options.presence_penalty,
             user: session.options.user.clone(),
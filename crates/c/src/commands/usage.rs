@@ -0,0 +1,36 @@
+use clap::{Parser, Subcommand};
+use color_eyre::eyre::Result;
+
+use usage::GroupBy;
+
+#[derive(Clone, Parser, Debug)]
+pub struct CommandOptions {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Clone, Subcommand, Debug)]
+enum Command {
+    /// Print the recorded requests, tokens and estimated cost totals.
+    Report {
+        /// Columns used to group the totals.
+        #[clap(
+            long,
+            value_enum,
+            value_delimiter = ',',
+            default_value = "day,model,preset"
+        )]
+        group_by: Vec<GroupBy>,
+    },
+}
+
+/// Runs the `usage` command.
+pub async fn run(options: CommandOptions) -> Result<()> {
+    match options.command {
+        Command::Report { group_by } => {
+            usage::report(&usage::load(&usage::ledger_path(None)?)?, &group_by)
+        }
+    }
+
+    Ok(())
+}
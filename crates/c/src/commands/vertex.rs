@@ -263,6 +263,9 @@ pub struct CommandOptions {
     /// Silent mode
     #[clap(short, long, action, default_value_t = false)]
     silent: bool,
+    /// Print the token usage and estimated cost of the request to stderr.
+    #[clap(long)]
+    usage: bool,
     /// Wether to incrementally stream the response using SSE.
     #[clap(long)]
     stream: bool,
@@ -407,6 +410,26 @@ pub async fn run(mut options: CommandOptions) -> Result<()> {
     // Print the response output.
     print_output(&session.meta.format, &response)?;
 
+    crate::usage::record(
+        Vendor::Google,
+        session.options.model.unwrap_or_default().as_str(),
+        Some(
+            response
+                .metadata
+                .token_metadata
+                .input_token_count
+                .total_tokens,
+        ),
+        Some(
+            response
+                .metadata
+                .token_metadata
+                .output_token_count
+                .total_tokens,
+        ),
+        session.meta.usage,
+    )?;
+
     // Save the response to the session.
     session.history.push(Message::new(
         response
@@ -497,6 +520,7 @@ pub fn merge_options(
     session.meta.stream = false;
     session.meta.silent = options.silent;
     session.meta.pin = options.pin;
+    session.meta.usage = options.usage;
 
     Ok(session)
 }
@@ -515,9 +539,7 @@ async fn complete(session: &Session<SessionOptions>) -> Result<Response> {
     tracing::event!(tracing::Level::INFO, "body: {:?}", body);
 
     tracing::event!(tracing::Level::INFO, "Creating client...");
-    let client = reqwest::Client::builder()
-        .timeout(Duration::from_secs(60))
-        .build()?;
+    let client = http_client::builder(Duration::from_secs(60))?.build()?;
 
     tracing::event!(tracing::Level::INFO, "Creating API client headers...");
     let mut headers = reqwest::header::HeaderMap::new();
@@ -2,8 +2,10 @@ use clap::{Parser, Subcommand, ValueEnum};
 
 use serde::{Deserialize, Serialize};
 
+pub mod cassette;
 pub mod commands;
 pub mod session;
+pub mod usage;
 pub mod utils;
 
 #[derive(Debug, Parser)]
@@ -31,6 +33,9 @@ pub enum Commands {
     /// Ollama AI Chat Bot API
     #[clap(name = "ollama", alias = "l")]
     Ollama(commands::ollama::CommandOptions),
+    /// Token usage ledger
+    #[clap(name = "usage", alias = "u")]
+    Usage(commands::usage::CommandOptions),
 }
 
 #[derive(Default, ValueEnum, Debug, Clone, Serialize, Deserialize)]
@@ -22,6 +22,7 @@ async fn run() -> color_eyre::eyre::Result<()> {
         Some(c::Commands::Vertex(options)) => c::commands::vertex::run(options).await?,
         Some(c::Commands::NLPCloud(options)) => c::commands::nlpcloud::run(options).await?,
         Some(c::Commands::Ollama(options)) => c::commands::ollama::run(options).await?,
+        Some(c::Commands::Usage(options)) => c::commands::usage::run(options).await?,
         None => {
             color_eyre::eyre::bail!(
                 "No subcommand provided. Use --help to see available subcommands."
@@ -13,6 +13,7 @@ pub enum Vendor {
     Anthropic,
     Google,
     NLPCloud,
+    Ollama,
 }
 
 /// Chat LLM Role
@@ -32,12 +33,28 @@ pub struct Message {
     pub content: String,
     pub role: Role,
     pub pin: bool,
+    /// The message was interrupted before the model finished it.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub truncated: bool,
 }
 
 impl Message {
     /// Creates a new message
     pub fn new(content: String, role: Role, pin: bool) -> Self {
-        Self { content, role, pin }
+        Self {
+            content,
+            role,
+            pin,
+            truncated: false,
+        }
+    }
+
+    /// Creates an assistant message that was interrupted before the model finished it.
+    pub fn truncated(content: String, pin: bool) -> Self {
+        Self {
+            truncated: true,
+            ..Self::new(content, Role::Assistant, pin)
+        }
     }
 }
 
@@ -47,12 +64,16 @@ pub struct Meta {
     path: String,
     pub format: crate::Output,
     pub key: String,
+    pub base_url: Option<String>,
+    #[serde(skip)]
+    pub cassette: Option<crate::cassette::Cassette>,
     pub pin: bool,
     pub reverse: bool,
     pub history_size: Option<usize>,
     pub save: bool,
     pub silent: bool,
     pub stream: bool,
+    pub usage: bool,
 }
 
 /// Represents a chat session
@@ -0,0 +1,44 @@
+use std::path::PathBuf;
+
+use color_eyre::eyre::Result;
+
+use crate::session::Vendor;
+
+/// Config file of `e`, whose `[pricing]` table holds the prices of the models.
+const PRICING_FILE: &str = ".config/e.toml";
+
+/// Appends a request to the usage ledger shared with `e`, printing a summary to `stderr` when
+/// `print` is set. The estimated cost uses the `[pricing]` table of the `e` config file.
+pub fn record(
+    vendor: Vendor,
+    model: &str,
+    input_tokens: Option<u32>,
+    output_tokens: Option<u32>,
+    print: bool,
+) -> Result<()> {
+    let home = PathBuf::from(std::env::var("HOME")?);
+    let pricing = usage::pricing(&home.join(PRICING_FILE))?;
+
+    let record = usage::Record::new(
+        format!("{vendor:?}").to_lowercase(),
+        model,
+        None,
+        input_tokens,
+        output_tokens,
+        &pricing,
+    );
+
+    if print {
+        eprintln!("{}", record.summary());
+    }
+
+    usage::append(&usage::ledger_path(None)?, &record)?;
+
+    Ok(())
+}
+
+/// Estimates the number of tokens of a text, for the APIs that don't report their usage.
+pub fn estimate_tokens(text: &str) -> u32 {
+    // Estimate the total tokens by multiplying words by 4/3
+    (text.split_whitespace().count() * 4 / 3) as u32
+}
@@ -1,6 +1,8 @@
 use std::io::Read;
 use std::ops::RangeInclusive;
 
+use color_eyre::eyre::Result;
+
 /// Reads stdin and retusn a string with its content.
 pub fn read_from_stdin() -> Result<String, std::io::Error> {
     let mut stdin = Vec::new();
@@ -169,3 +171,24 @@ pub fn filter_history(
 
     pinned
 }
+
+/// Gets the api key from `key`, the output of `key_command`, or the age encrypted `key_file`.
+/// `env` is only used to report where the key was looked for.
+pub fn api_key(
+    key: Option<String>,
+    key_command: Option<String>,
+    key_file: Option<String>,
+    env: &str,
+) -> Result<String> {
+    let mut providers: Vec<credentials::Provider> = Vec::new();
+    providers.extend(key.map(credentials::Provider::Key));
+    providers.extend(key_command.map(credentials::Provider::Command));
+    if let Some(path) = key_file {
+        providers.push(credentials::Provider::File(credentials::expand_home(
+            &path,
+        )?));
+    }
+    providers.push(credentials::Provider::Env(env.to_string()));
+
+    Ok(credentials::resolve(&providers)?)
+}
@@ -0,0 +1,260 @@
+use std::path::PathBuf;
+use std::process::{Command, Output, Stdio};
+
+use mock_provider::{fixtures, MockServer, Response};
+
+/// Empty `C_ROOT` directory, so the tests don't touch the user sessions.
+fn root(name: &str) -> PathBuf {
+    let root = std::env::temp_dir().join(format!("c-cli-{}-{name}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&root);
+    std::fs::create_dir_all(&root).unwrap();
+    root
+}
+
+fn c(name: &str, args: &[&str]) -> Output {
+    let root = root(name);
+    Command::new(env!("CARGO_BIN_EXE_c"))
+        .args(args)
+        .env("C_ROOT", &root)
+        .env("HOME", &root)
+        .env_remove("OPENAI_API_KEY")
+        .env_remove("ANTHROPIC_API_KEY")
+        .stdin(Stdio::null())
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn openai() {
+    let server = MockServer::start();
+    server.mock(
+        "/v1/chat/completions",
+        Response::json(200, fixtures::OPENAI_COMPLETION),
+    );
+
+    let url = server.url();
+    let args = ["openai", "--base-url", &url, "--openai-api-key", "test-key"];
+    let output = c("openai", &[&args[..], &["Hi"]].concat());
+
+    assert!(output.status.success(), "{output:?}");
+    assert!(String::from_utf8_lossy(&output.stdout).contains("Hello world"));
+
+    let request = &server.requests()[0];
+    assert_eq!(request.header("authorization"), Some("Bearer test-key"));
+    assert_eq!(request.json()["messages"][0]["content"], "Hi");
+}
+
+#[test]
+fn openai_stream() {
+    let server = MockServer::start();
+    server.mock("/v1/chat/completions", Response::sse(fixtures::OPENAI));
+
+    let url = server.url();
+    let args = ["openai", "--base-url", &url, "--openai-api-key", "test-key"];
+    let output = c("openai-stream", &[&args[..], &["--stream", "Hi"]].concat());
+
+    assert!(output.status.success(), "{output:?}");
+    assert!(String::from_utf8_lossy(&output.stdout).contains("Hello world"));
+    assert_eq!(server.requests()[0].json()["stream"], true);
+}
+
+#[test]
+fn usage_ledger() {
+    let server = MockServer::start();
+    server.mock(
+        "/v1/chat/completions",
+        Response::json(200, fixtures::OPENAI_COMPLETION),
+    );
+
+    let root = root("usage");
+    std::fs::create_dir_all(root.join(".config")).unwrap();
+    std::fs::write(
+        root.join(".config/e.toml"),
+        "[pricing]\n\"gpt-4o\" = { input = 1000.0, output = 1000.0 }\n",
+    )
+    .unwrap();
+    let c = |args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_c"))
+            .args(args)
+            .env("C_ROOT", &root)
+            .env("HOME", &root)
+            .stdin(Stdio::null())
+            .output()
+            .unwrap()
+    };
+
+    let url = server.url();
+    let output = c(&[
+        "openai",
+        "--base-url",
+        &url,
+        "--openai-api-key",
+        "test-key",
+        "Hi",
+    ]);
+    assert!(output.status.success(), "{output:?}");
+    // The ledger is shared with `e`.
+    assert!(root.join(".e/usage.jsonl").is_file());
+
+    let output = c(&["usage", "report", "--group-by", "api,model"]);
+    assert!(output.status.success(), "{output:?}");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("openai  gpt-4o"), "{stdout}");
+    assert!(stdout.contains("$0.0110"), "{stdout}");
+}
+
+#[test]
+fn blank_key_falls_back_to_key_command() {
+    let server = MockServer::start();
+    server.mock(
+        "/v1/chat/completions",
+        Response::json(200, fixtures::OPENAI_COMPLETION),
+    );
+
+    let output = Command::new(env!("CARGO_BIN_EXE_c"))
+        .args(["openai", "--base-url", &server.url(), "Hi"])
+        .env("C_ROOT", root("blank-key"))
+        .env("HOME", root("blank-key-home"))
+        .env("OPENAI_API_KEY", "")
+        .env("OPENAI_API_KEY_COMMAND", "echo command-key")
+        .stdin(Stdio::null())
+        .output()
+        .unwrap();
+
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(
+        server.requests()[0].header("authorization"),
+        Some("Bearer command-key")
+    );
+}
+
+#[test]
+fn anthropic() {
+    let server = MockServer::start();
+    server.mock(
+        "/v1/complete",
+        Response::json(200, fixtures::ANTHROPIC_COMPLETION),
+    );
+
+    let url = server.url();
+    let args = [
+        "anthropic",
+        "--base-url",
+        &url,
+        "--anthropic-api-key",
+        "test-key",
+    ];
+    let output = c("anthropic", &[&args[..], &["Hi"]].concat());
+
+    assert!(output.status.success(), "{output:?}");
+    assert!(String::from_utf8_lossy(&output.stdout).contains("Hello world"));
+    assert_eq!(server.requests()[0].header("x-api-key"), Some("test-key"));
+}
+
+#[test]
+fn anthropic_stream() {
+    let server = MockServer::start();
+    server.mock("/v1/complete", Response::sse(fixtures::ANTHROPIC_COMPLETE));
+
+    let url = server.url();
+    let args = [
+        "anthropic",
+        "--base-url",
+        &url,
+        "--anthropic-api-key",
+        "test-key",
+    ];
+    let output = c(
+        "anthropic-stream",
+        &[&args[..], &["--stream", "Hi"]].concat(),
+    );
+
+    assert!(output.status.success(), "{output:?}");
+    assert!(String::from_utf8_lossy(&output.stdout).contains("Hello world"));
+}
+
+#[test]
+fn anthropic_usage() {
+    let server = MockServer::start();
+    server.mock("/v1/complete", Response::sse(fixtures::ANTHROPIC_COMPLETE));
+
+    let url = server.url();
+    let args = [
+        "anthropic",
+        "--base-url",
+        &url,
+        "--anthropic-api-key",
+        "test-key",
+    ];
+    let output = c(
+        "anthropic-usage",
+        &[&args[..], &["--stream", "--usage", "Hi"]].concat(),
+    );
+
+    assert!(output.status.success(), "{output:?}");
+    // The completion API doesn't report the usage, so the token counts are estimated.
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("input tokens: 2,"), "{stderr}");
+    assert!(stderr.contains("output tokens: 2,"), "{stderr}");
+}
+
+#[test]
+fn record_and_replay() {
+    let cassette = root("cassette").join("cassette");
+    let cassette = cassette.to_str().unwrap();
+
+    let server = MockServer::start();
+    server.mock("/v1/chat/completions", Response::sse(fixtures::OPENAI));
+
+    let url = server.url();
+    let args = ["openai", "--base-url", &url, "--openai-api-key", "test-key"];
+    let output = c(
+        "record",
+        &[&args[..], &["--stream", "--record", cassette, "Hi"]].concat(),
+    );
+    assert!(output.status.success(), "{output:?}");
+    drop(server);
+
+    let output = c(
+        "replay",
+        &["openai", "--stream", "--replay", cassette, "Hi"],
+    );
+
+    assert!(output.status.success(), "{output:?}");
+    assert!(String::from_utf8_lossy(&output.stdout).contains("Hello world"));
+}
+
+#[test]
+fn record_and_replay_errors() {
+    let cassette = root("cassette-error").join("cassette");
+    let cassette = cassette.to_str().unwrap();
+
+    let server = MockServer::start();
+    server.mock(
+        "/v1/chat/completions",
+        Response::json(429, r#"{"error":{"message":"Rate limit reached"}}"#),
+    );
+
+    let url = server.url();
+    let args = ["openai", "--base-url", &url, "--openai-api-key", "test-key"];
+    let output = c(
+        "record-error",
+        &[&args[..], &["--stream", "--record", cassette, "Hi"]].concat(),
+    );
+    assert!(!output.status.success(), "{output:?}");
+    drop(server);
+
+    let output = c(
+        "replay-error",
+        &["openai", "--stream", "--replay", cassette, "Hi"],
+    );
+    assert!(!output.status.success(), "{output:?}");
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Rate limit reached"));
+
+    let output = c(
+        "replay-other",
+        &["openai", "--stream", "--replay", cassette, "Bye"],
+    );
+    assert!(!output.status.success(), "{output:?}");
+    assert!(String::from_utf8_lossy(&output.stderr).contains("doesn't match"));
+}
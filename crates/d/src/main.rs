@@ -39,8 +39,11 @@ async fn main() -> color_eyre::eyre::Result<()> {
     color_eyre::install()?;
     env_logger::init();
 
-    // Load the OpenAI API Key from the OPENAI_API_KEY environment variable.
-    openai::set_key(std::env::var("OPENAI_API_KEY")?);
+    // Load the OpenAI API Key from the OPENAI_API_KEY environment variable, the output of the
+    // OPENAI_API_KEY_COMMAND command, or the age encrypted OPENAI_API_KEY_FILE file.
+    openai::set_key(credentials::resolve(&credentials::from_env(
+        "OPENAI_API_KEY",
+    )?)?);
 
     // Create the shutdown handler
     let shutdown = shutdown::Shutdown::new()?;
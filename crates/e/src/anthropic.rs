@@ -1,65 +1,30 @@
 use es_stream::anthropic;
+use es_stream::options::WithOptions;
 
 use crate::prelude::*;
 
 const DEFAULT_URL: &str = "https://api.anthropic.com/v1";
-const DEFAULT_MODEL: &str = "claude-3-5-sonnet-20240620";
-const DEFAULT_ENV: &str = "ANTHROPIC_API_KEY";
+pub const DEFAULT_MODEL: &str = "claude-3-5-sonnet-20240620";
+pub const DEFAULT_ENV: &str = "ANTHROPIC_API_KEY";
 
-pub async fn run(prompt: String, args: Args) -> Result<()> {
-    let key = match args.globals.api_key {
-        Some(key) => key,
-        None => {
-            let environment_variable = match args.globals.api_env {
-                Some(env) => env,
-                None => DEFAULT_ENV.to_string(),
-            };
-            std::env::var(environment_variable)?
-        }
-    };
-    log::info!("key: {}", key);
+/// Creates an Anthropic client from the global arguments.
+pub fn client(globals: &Globals) -> Result<anthropic::Client> {
+    let key = api_key(globals, DEFAULT_ENV)?;
 
-    let url = match args.globals.api_base_url {
-        Some(url) => url,
+    let url = match &globals.api_base_url {
+        Some(url) => url.clone(),
         None => DEFAULT_URL.to_string(),
     };
     log::info!("url: {}", url);
 
-    let auth = anthropic::Auth::new(key, args.globals.api_version);
-
+    let auth = anthropic::Auth::new(key, globals.api_version.clone());
     log::info!("auth: {:#?}", auth);
 
-    let client = anthropic::Client::new(auth, url);
-
+    let client = anthropic::Client::new(auth, url)
+        .with_retry_policy(retry_policy(globals))
+        .with_http_options(http_options(globals)?)
+        .with_cassette(cassette(globals));
     log::info!("client: {:#?}", client);
 
-    let messages = vec![anthropic::Message {
-        role: anthropic::Role::User,
-        content: prompt,
-    }];
-
-    let mut body = anthropic::MessageBody::new(
-        args.globals
-            .model
-            .unwrap_or(DEFAULT_MODEL.to_string())
-            .as_ref(),
-        messages,
-        args.globals.max_tokens.unwrap_or(4096),
-    );
-
-    body.system = args.globals.system;
-    body.temperature = args.globals.temperature;
-    body.top_p = args.globals.top_p;
-    body.top_k = args.globals.top_k;
-
-    log::info!("body: {:#?}", body);
-
-    let stream = client.delta(&body)?;
-
-    handle_stream(
-        stream,
-        args.globals.quiet.unwrap_or(false),
-        args.globals.language,
-    )
-    .await
+    Ok(client)
 }
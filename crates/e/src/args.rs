@@ -1,16 +1,18 @@
-use clap::{Parser, ValueEnum};
+use clap::{Parser, Subcommand, ValueEnum};
 use clap_stdin::MaybeStdin;
+use es_stream::secret::Secret;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use std::str::FromStr;
+pub use usage::GroupBy;
 
 use crate::prelude::*;
 
-#[derive(Debug, clap::Args)]
+#[derive(Debug, Clone, clap::Args)]
 pub struct Globals {
     /// Hidden prompt to support prompting from stdin and as an argument
-    #[clap(default_value = "-", hide = true)]
-    pub stdin: MaybeStdin<String>,
+    #[clap(hide = true)]
+    pub stdin: Option<MaybeStdin<String>>,
 
     /// The user message prompt
     #[clap(default_value = "", hide = true)]
@@ -42,7 +44,7 @@ pub struct Globals {
 
     /// The api key to use (will override the value of the environment variable.)
     #[clap(long)]
-    pub api_key: Option<String>,
+    pub api_key: Option<Secret>,
 
     /// The api base url.
     #[clap(long)]
@@ -68,7 +70,7 @@ pub struct Globals {
     #[clap(long)]
     pub top_k: Option<u32>,
 
-    /// Config file
+    /// User config file, merged on top of `/etc/e.toml` and below the `.e.toml` of the project.
     #[clap(long, default_value = "~/.config/e.toml")]
     pub config_file: String,
 
@@ -95,6 +97,129 @@ pub struct Globals {
     /// Prints the rendered template instead of calling the LLM.
     #[clap(long, default_value = "false")]
     pub print_template: bool,
+
+    /// Conversation file (JSON or YAML) with the previous turns to send before the prompt.
+    #[clap(long, conflicts_with_all = ["continue_conversation", "session"])]
+    pub conversation: Option<String>,
+
+    /// Continue the last conversation.
+    #[clap(
+        short,
+        long = "continue",
+        default_value = "false",
+        conflicts_with = "session"
+    )]
+    pub continue_conversation: bool,
+
+    /// Chat session name. Previous turns are loaded from the session and the new exchange is
+    /// saved to it.
+    #[clap(short, long)]
+    pub session: Option<String>,
+
+    /// Directory where sessions are stored (defaults to `~/.e/sessions`.)
+    #[clap(long)]
+    pub sessions_dir: Option<String>,
+
+    /// Comma separated list of the configured tools the model may call.
+    #[clap(long, value_delimiter = ',')]
+    pub tools: Option<Vec<String>>,
+
+    /// Attach a file or image to the prompt (can be used multiple times.)
+    #[clap(long = "attach", value_name = "FILE")]
+    pub attachments: Vec<String>,
+
+    /// JSON schema file the response must follow. The response is validated before printing it.
+    #[clap(long, value_name = "FILE")]
+    pub json_schema: Option<String>,
+
+    /// Print the token usage and estimated cost of the request to stderr.
+    #[clap(long, default_value = "false")]
+    pub usage: bool,
+
+    /// Maximum number of times a request refused by the provider (e.g. rate limited) is retried.
+    #[clap(long)]
+    pub max_retries: Option<u32>,
+
+    /// Save the request bodies and the raw responses of the provider on this directory.
+    #[clap(long, value_name = "DIR", conflicts_with = "replay")]
+    pub record: Option<String>,
+
+    /// Replay the responses saved with `--record` on this directory instead of calling the api.
+    #[clap(long, value_name = "DIR")]
+    pub replay: Option<String>,
+
+    /// Shell command that prints the api key, from the preset or the config file.
+    #[clap(skip)]
+    pub key_command: Option<String>,
+
+    /// Age encrypted file holding the api key, from the preset or the config file.
+    #[clap(skip)]
+    pub key_file: Option<String>,
+
+    /// Order in which the api key sources are tried, from the preset or the config file.
+    #[clap(skip)]
+    pub credentials: Option<Vec<crate::config::KeySource>>,
+
+    /// Retry policy from the preset or the config file.
+    #[clap(skip)]
+    pub retry: Option<crate::config::Retry>,
+
+    /// Proxy, CA certificates and timeouts from the config file.
+    #[clap(skip)]
+    pub http: Option<crate::config::Http>,
+}
+
+impl Globals {
+    /// Fills the settings that aren't set on the command line with the ones of `config`, taking
+    /// them out of it. The model is left alone, since it may be an alias that also sets the api.
+    pub fn merge(&mut self, config: &mut Config) {
+        if self.top_p.is_none() {
+            self.top_p = config.top_p.take();
+        }
+        if self.top_k.is_none() {
+            self.top_k = config.top_k.take();
+        }
+        if self.temperature.is_none() {
+            self.temperature = config.temperature.take();
+        }
+        if self.system.is_none() {
+            self.system = config.system.take();
+        }
+        if self.max_tokens.is_none() {
+            self.max_tokens = config.max_tokens.take();
+        }
+        if self.api_version.is_none() {
+            self.api_version = config.version.take();
+        }
+        if self.api_env.is_none() {
+            self.api_env = config.env.take();
+        }
+        if self.api_key.is_none() {
+            self.api_key = config.key.take();
+        }
+        if self.key_command.is_none() {
+            self.key_command = config.key_command.take();
+        }
+        if self.key_file.is_none() {
+            self.key_file = config.key_file.take();
+        }
+        if self.credentials.is_none() {
+            self.credentials = config.credentials.take();
+        }
+        if self.api_base_url.is_none() {
+            self.api_base_url = config.base_url.take();
+        }
+        if self.quiet.is_none() {
+            self.quiet = config.quiet.take();
+        }
+        if self.sessions_dir.is_none() {
+            self.sessions_dir = config.sessions_dir.take();
+        }
+        if self.retry.is_none() {
+            self.retry = config.retry.take();
+        }
+        self.http = config.http.take();
+    }
 }
 
 /// Custom parser function for JSON values
@@ -102,7 +227,7 @@ fn parse_json(s: &str) -> std::result::Result<Value, serde_json::Error> {
     serde_json::from_str(s)
 }
 
-#[derive(ValueEnum, Debug, Default, Clone, Copy, Serialize, Deserialize)]
+#[derive(ValueEnum, Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "lowercase")]
 pub enum Api {
     OpenAi,
@@ -111,6 +236,37 @@ pub enum Api {
     Google,
     Mistral,
     MistralFim,
+    Ollama,
+    Copilot,
+}
+
+impl Api {
+    /// The model used when none is set.
+    pub fn default_model(self) -> &'static str {
+        match self {
+            Api::OpenAi => crate::openai::DEFAULT_MODEL,
+            Api::Anthropic => crate::anthropic::DEFAULT_MODEL,
+            Api::Google => crate::google::DEFAULT_MODEL,
+            Api::Mistral => crate::mistral::DEFAULT_MODEL,
+            Api::MistralFim => crate::mistral_fim::DEFAULT_MODEL,
+            Api::Ollama => crate::ollama::DEFAULT_MODEL,
+            Api::Copilot => crate::copilot::DEFAULT_MODEL,
+        }
+    }
+
+    /// The environment variable holding the api key by default, for the apis that can't be used
+    /// without one.
+    pub fn key_env(self) -> Option<&'static str> {
+        match self {
+            Api::OpenAi => Some(crate::openai::DEFAULT_ENV),
+            Api::Anthropic => Some(crate::anthropic::DEFAULT_ENV),
+            Api::Google => Some(crate::google::DEFAULT_ENV),
+            Api::Mistral => Some(crate::mistral::DEFAULT_ENV),
+            Api::MistralFim => Some(crate::mistral_fim::DEFAULT_ENV),
+            // The key is optional for Ollama, and Copilot falls back to the editor plugins token.
+            Api::Ollama | Api::Copilot => None,
+        }
+    }
 }
 
 // From string to API enum
@@ -137,6 +293,10 @@ impl FromStr for Api {
             "Mistral_FIM" => Ok(Api::MistralFim),
             "Mistral_Fim" => Ok(Api::MistralFim),
             "MistralFIM" => Ok(Api::MistralFim),
+            "ollama" => Ok(Api::Ollama),
+            "Ollama" => Ok(Api::Ollama),
+            "copilot" => Ok(Api::Copilot),
+            "Copilot" => Ok(Api::Copilot),
             _ => Err(Error::InvalidAPI),
         }
     }
@@ -148,7 +308,7 @@ impl FromStr for Api {
 #[command(
     long_about = "This Rust-based CLI enables users to interact with various Large Language Models
 (LLMs) directly from the terminal. Through this tool, you can send prompts to different
-APIs, such as OpenAI, Anthropic, Google, Mistral, and Mistral FIM, and receive and handle
+APIs, such as OpenAI, Anthropic, Google, Mistral, Mistral FIM, Ollama, and GitHub Copilot, and receive and handle
 responses from these models.
 
 The tool offers extensive configuration options, allowing you
@@ -162,7 +322,77 @@ highlight the model's responses using syntax highlighting, making it easier to r
 output in the terminal. It also includes functionality to handle streaming responses
 efficiently, ensuring a smooth user experience when interacting with the LLMs."
 )]
+#[command(args_conflicts_with_subcommands = true)]
 pub struct Args {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
     #[clap(flatten)]
     pub globals: Globals,
 }
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Inspect the token usage ledger.
+    #[command(subcommand)]
+    Usage(UsageCommand),
+    /// Inspect and check the prompt templates.
+    #[command(subcommand)]
+    Templates(TemplatesCommand),
+    /// Inspect the merged configuration.
+    #[command(subcommand)]
+    Config(ConfigCommand),
+}
+
+#[derive(Debug, Subcommand)]
+pub enum UsageCommand {
+    /// Print the recorded requests, tokens and estimated cost totals.
+    Report {
+        /// Columns used to group the totals.
+        #[clap(
+            long,
+            value_enum,
+            value_delimiter = ',',
+            default_value = "day,model,preset"
+        )]
+        group_by: Vec<GroupBy>,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum TemplatesCommand {
+    /// List the templates from the config file and the templates directory.
+    List,
+    /// Print a template with its description, default variables and system prompt.
+    Show {
+        /// Name of the template.
+        name: String,
+    },
+    /// Check that every template can be parsed and rendered with its default variables.
+    Validate,
+    /// Render a template without calling the LLM.
+    Render {
+        /// Name of the template.
+        name: String,
+
+        /// The user message prompt.
+        #[clap(default_value = "")]
+        prompt: String,
+
+        /// Additional variables in JSON format
+        #[clap(long, default_value = "{}", value_parser = parse_json)]
+        vars: Value,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum ConfigCommand {
+    /// Print the configuration merged from the config files and the `E_*` environment variables.
+    Show {
+        /// Print the file or environment variable each setting came from.
+        #[clap(long, default_value = "false")]
+        origin: bool,
+    },
+    /// Check that every preset can be resolved and has an api key.
+    Validate,
+}
@@ -1,29 +1,34 @@
-use serde::Deserialize;
+use es_stream::http::HttpOptions;
+use es_stream::retry::RetryPolicy;
+use es_stream::secret::Secret;
+use serde::{Deserialize, Serialize};
 use serde_json::Value;
+use std::collections::HashMap;
+use std::time::Duration;
 
+/// A named set of settings selected with `--preset`. Besides `tools`, presets take the same
+/// settings as the config file, and they are merged on top of it when selected. A preset can
+/// inherit the settings of another one by setting its name on `extends`.
 #[derive(Debug, Default, Deserialize)]
 pub struct Preset {
     pub name: String,
 
-    // Api
-    pub api: crate::args::Api,
-    pub env: Option<String>,
-    pub key: Option<String>,
-    pub base_url: Option<String>,
-
-    // Model
-    pub model: Option<String>,
+    // Tools
+    pub tools: Option<Vec<String>>,
+}
 
-    // Model Configuration
-    pub system: Option<String>,
-    pub max_tokens: Option<u32>,
-    pub version: Option<String>,
-    pub temperature: Option<f32>,
-    pub top_p: Option<f32>,
-    pub top_k: Option<u32>,
+/// A short name for a model, e.g. `fast` or `smart`, usable anywhere a model is expected.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Alias {
+    pub api: crate::args::Api,
+    pub model: String,
 }
 
-#[derive(Debug, Default, Deserialize)]
+/// The author of a conversation turn. `Model` and `Human` are accepted as aliases of `Assistant`
+/// and `User` to support conversations exported from other providers.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum Role {
     Assistant,
     Model,
@@ -42,22 +47,157 @@ pub struct Template {
     pub system: Option<String>,
 }
 
+/// Built-in tool implementations.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Builtin {
+    /// Reads a file. Takes a `path` argument.
+    ReadFile,
+    /// Lists the entries of a directory. Takes a `path` argument.
+    ListFiles,
+}
+
+/// A tool the model may call, run either as a shell command or as a built-in.
+#[derive(Debug, Default, Clone, Deserialize)]
+pub struct Tool {
+    pub name: String,
+    pub description: Option<String>,
+    /// JSON schema of the tool arguments.
+    pub parameters: Option<Value>,
+    /// Shell command to run. The arguments are sent as JSON on `stdin` and each top-level
+    /// argument is also exported as an environment variable of the same name.
+    pub command: Option<String>,
+    pub builtin: Option<Builtin>,
+    /// Tools marked as safe run without asking for confirmation.
+    #[serde(default)]
+    pub safe: bool,
+}
+
+/// Places where the api key is looked up. They are tried in the order set on `credentials`,
+/// which defaults to `["key", "key_command", "key_file", "env"]`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum KeySource {
+    /// The `--api-key` argument or the `key` setting.
+    Key,
+    /// The first line of the output of the `key_command` shell command.
+    KeyCommand,
+    /// The age encrypted file set on `key_file`.
+    KeyFile,
+    /// The environment variable set on `--api-env`, `env`, or the default one of the api.
+    Env,
+}
+
+impl KeySource {
+    pub const DEFAULT_ORDER: [KeySource; 4] = [
+        KeySource::Key,
+        KeySource::KeyCommand,
+        KeySource::KeyFile,
+        KeySource::Env,
+    ];
+}
+
+/// Retry policy for requests refused by the provider because of rate limits or overloaded
+/// servers. Delays are in seconds, and unset fields keep their default value.
+#[derive(Debug, Default, Clone, Copy, Deserialize)]
+pub struct Retry {
+    pub max_retries: Option<u32>,
+    pub initial_delay: Option<f64>,
+    pub backoff_factor: Option<u32>,
+    pub max_delay: Option<f64>,
+}
+
+impl Retry {
+    pub fn policy(&self) -> RetryPolicy {
+        let default = RetryPolicy::default();
+        let seconds = |secs: f64| Duration::from_secs_f64(secs.max(0.0));
+
+        RetryPolicy {
+            max_retries: self.max_retries.unwrap_or(default.max_retries),
+            initial_delay: self.initial_delay.map_or(default.initial_delay, seconds),
+            backoff_factor: self.backoff_factor.unwrap_or(default.backoff_factor),
+            max_delay: self.max_delay.map_or(default.max_delay, seconds),
+        }
+    }
+}
+
+/// Proxy, CA certificates and timeouts used by every request. `proxy` is used for every url.
+/// Unset fields fall back to the `HTTP_PROXY`, `HTTPS_PROXY`, `ALL_PROXY`, `NO_PROXY`,
+/// `SSL_CERT_FILE`, `HTTP_CONNECT_TIMEOUT` and `HTTP_READ_TIMEOUT` environment variables.
+/// Timeouts are in seconds.
+#[derive(Debug, Default, Clone, Deserialize)]
+pub struct Http {
+    pub proxy: Option<Secret>,
+    pub no_proxy: Option<Vec<String>>,
+    pub ca_bundle: Option<String>,
+    pub connect_timeout: Option<f64>,
+    pub read_timeout: Option<f64>,
+}
+
+impl Http {
+    pub fn options(&self) -> Result<HttpOptions, std::env::VarError> {
+        let mut options = HttpOptions::from_env();
+        let seconds = |secs: f64| Duration::from_secs_f64(secs.max(0.0));
+
+        if let Some(proxy) = &self.proxy {
+            options = options.with_proxy(proxy.clone());
+        }
+        if let Some(no_proxy) = &self.no_proxy {
+            options.no_proxy = no_proxy.clone();
+        }
+        if let Some(ca_bundle) = &self.ca_bundle {
+            options.ca_bundle = Some(ca_bundle.replace('~', &std::env::var("HOME")?).into());
+        }
+        if let Some(timeout) = self.connect_timeout {
+            options.connect_timeout = Some(seconds(timeout));
+        }
+        if let Some(timeout) = self.read_timeout {
+            options.read_timeout = Some(seconds(timeout));
+        }
+
+        Ok(options)
+    }
+}
+
 #[derive(Debug, Default, Deserialize)]
 pub struct Config {
     // Api
     pub api: Option<crate::args::Api>,
     pub base_url: Option<String>,
     pub env: Option<String>,
-    pub key: Option<String>,
+    pub key: Option<Secret>,
+    pub key_command: Option<String>,
+    pub key_file: Option<String>,
+    pub credentials: Option<Vec<KeySource>>,
 
     // Presets
     pub presets: Option<Vec<Preset>>,
+    pub aliases: Option<HashMap<String, Alias>>,
+    /// Presets the request is sent to, in order, when the model fails before responding.
+    pub fallbacks: Option<Vec<String>>,
 
     // Templates
     pub templates: Option<Vec<Template>>,
+    pub templates_dir: Option<String>,
+    /// Commands templates may run with the `cmd` function, e.g. `["git log", "cargo tree"]`.
+    pub template_commands: Option<Vec<String>>,
+
+    // Tools
+    pub tools: Option<Vec<Tool>>,
 
     // Global
     pub quiet: Option<bool>,
+    pub sessions_dir: Option<String>,
+    pub usage_file: Option<String>,
+
+    // Pricing, keyed by model name.
+    pub pricing: Option<HashMap<String, usage::Price>>,
+
+    // Retries
+    pub retry: Option<Retry>,
+
+    // Proxy, CA certificates and timeouts
+    pub http: Option<Http>,
 
     // Model
     pub model: Option<String>,
@@ -70,3 +210,10 @@ pub struct Config {
     pub top_p: Option<f32>,
     pub top_k: Option<u32>,
 }
+
+impl Config {
+    /// Finds the alias called `model`.
+    pub fn alias(&self, model: &str) -> Option<&Alias> {
+        self.aliases.as_ref()?.get(model)
+    }
+}
@@ -0,0 +1,124 @@
+use es_stream::provider::{self, Message};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+use crate::config::Role;
+use crate::prelude::*;
+
+// File where the last exchange is stored to support `--continue`.
+const LAST_CONVERSATION: &str = "last.json";
+// Directory where sessions are stored when `sessions_dir` is not configured.
+pub const DEFAULT_SESSIONS_DIR: &str = "~/.e/sessions";
+
+/// A single conversation turn.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Turn {
+    pub role: Role,
+    pub content: String,
+    /// The turn was interrupted before the model finished it.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub truncated: bool,
+}
+
+/// A list of conversation turns, stored as JSON or YAML.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Conversation {
+    pub turns: Vec<Turn>,
+}
+
+impl Conversation {
+    /// Loads a conversation from a JSON or YAML file. The format is picked from the file
+    /// extension, defaulting to JSON.
+    pub fn load(path: &Path) -> Result<Self> {
+        log::info!("loading conversation from {:?}", path);
+
+        let content = std::fs::read_to_string(path)?;
+
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("yaml") | Some("yml") => Ok(serde_yaml::from_str(&content)?),
+            _ => Ok(serde_json::from_str(&content)?),
+        }
+    }
+
+    /// Saves the conversation as JSON or YAML, following the same rules as `load`, and creating
+    /// the parent directories if needed.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        log::info!("saving conversation to {:?}", path);
+
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        let content = match path.extension().and_then(|e| e.to_str()) {
+            Some("yaml") | Some("yml") => serde_yaml::to_string(self)?,
+            _ => serde_json::to_string_pretty(self)?,
+        };
+
+        std::fs::write(path, content)?;
+
+        Ok(())
+    }
+
+    /// Path of the last exchange made with `e`.
+    pub fn last_path() -> Result<PathBuf> {
+        Ok(cache_dir()?.join(LAST_CONVERSATION))
+    }
+
+    /// Path of the session `name` stored inside `sessions_dir`.
+    pub fn session_path(sessions_dir: &str, name: &str) -> Result<PathBuf> {
+        let home = std::env::var("HOME")?;
+
+        Ok(PathBuf::from(sessions_dir.replace('~', &home)).join(format!("{name}.yaml")))
+    }
+
+    /// Appends a new turn to the conversation.
+    pub fn push(&mut self, role: Role, content: impl Into<String>) {
+        self.turns.push(Turn {
+            role,
+            content: content.into(),
+            truncated: false,
+        });
+    }
+
+    /// Appends an assistant turn that was interrupted before the model finished it.
+    pub fn push_truncated(&mut self, content: impl Into<String>) {
+        self.turns.push(Turn {
+            role: Role::Assistant,
+            content: content.into(),
+            truncated: true,
+        });
+    }
+
+    /// Joins every system turn into a single system prompt.
+    pub fn system(&self) -> Option<String> {
+        let system = self
+            .turns
+            .iter()
+            .filter(|turn| turn.role == Role::System)
+            .map(|turn| turn.content.as_str())
+            .collect::<Vec<_>>();
+
+        if system.is_empty() {
+            None
+        } else {
+            Some(system.join("\n\n"))
+        }
+    }
+
+    /// Maps the user and assistant turns into provider messages. System turns are skipped since
+    /// they are sent separately.
+    pub fn messages(&self) -> Vec<Message> {
+        self.turns
+            .iter()
+            .filter_map(|turn| {
+                let role = match turn.role {
+                    Role::Assistant | Role::Model => provider::Role::Assistant,
+                    Role::User | Role::Human => provider::Role::User,
+                    Role::System => return None,
+                };
+                Some(Message::new(role, turn.content.clone()))
+            })
+            .collect()
+    }
+}
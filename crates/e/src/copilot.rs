@@ -0,0 +1,34 @@
+use es_stream::copilot;
+use es_stream::options::WithOptions;
+
+use crate::prelude::*;
+
+const DEFAULT_URL: &str = "https://api.githubcopilot.com";
+pub const DEFAULT_MODEL: &str = "gpt-4o";
+const DEFAULT_ENV: &str = "GITHUB_COPILOT_TOKEN";
+
+/// Creates a GitHub Copilot client from the global arguments. The OAuth token is read from the
+/// Copilot editor plugins configuration when it isn't provided, and the session token is cached
+/// under `~/.cache/e`.
+pub fn client(globals: &Globals) -> Result<copilot::Client> {
+    let auth = match api_key(globals, DEFAULT_ENV) {
+        Ok(key) => copilot::Auth::new(key),
+        Err(_) => copilot::Auth::from_copilot_config()?,
+    };
+    log::info!("auth: {:#?}", auth);
+
+    let url = match &globals.api_base_url {
+        Some(url) => url.clone(),
+        None => DEFAULT_URL.to_string(),
+    };
+    log::info!("url: {}", url);
+
+    let client = copilot::Client::new(auth, url)
+        .with_retry_policy(retry_policy(globals))
+        .with_http_options(http_options(globals)?)
+        .with_cassette(cassette(globals))
+        .with_token_cache(cache_dir()?.join("copilot-token.json"));
+    log::info!("client: {:#?}", client);
+
+    Ok(client)
+}
@@ -6,6 +6,10 @@ pub enum Error {
     EsStream(#[from] es_stream::error::Error),
     #[error("unable to get value from environment variable")]
     EnvVar(#[from] std::env::VarError),
+    #[error("unable to get the api key")]
+    Credentials(#[from] credentials::Error),
+    #[error("unable to read or write the usage ledger")]
+    Usage(#[from] usage::Error),
     #[error("invalid api")]
     InvalidAPI,
     #[error("unable to print with bat")]
@@ -14,14 +18,48 @@ pub enum Error {
     TryFrom(#[from] std::num::TryFromIntError),
     #[error("api not specified")]
     ApiNotSpecified,
-    #[error("config file error")]
-    ConfigFile(#[from] config_file::ConfigFileError),
+    #[error("unable to parse the config file {0:?}")]
+    ConfigFile(std::path::PathBuf, #[source] toml::de::Error),
+    #[error("invalid configuration")]
+    Config(#[from] toml::de::Error),
+    #[error("preset not found: {0}")]
+    PresetNotFound(String),
+    #[error("invalid presets:\n{}", .0.join("\n"))]
+    InvalidPresets(Vec<String>),
+    #[error("the configuration has {0} errors")]
+    InvalidConfig(usize),
+    #[error("no api key for {0:?}: set `key`, `key_command`, `key_file` or {1}")]
+    MissingApiKey(crate::args::Api, String),
+    #[error("unable to print the configuration")]
+    ConfigShow(#[from] toml::ser::Error),
     #[error("infallible error")]
     Infallible(#[from] std::convert::Infallible),
-    #[error("template not found")]
-    TemplateNotFound,
+    #[error("template not found: {0}")]
+    TemplateNotFound(String),
+    #[error("invalid front-matter on {0:?}: {1}")]
+    FrontMatter(std::path::PathBuf, String),
+    #[error("{0} templates failed to validate")]
+    InvalidTemplates(usize),
     #[error("tera error")]
     Tera(#[from] tera::Error),
+    #[error("json error")]
+    Json(#[from] serde_json::Error),
+    #[error("yaml error")]
+    Yaml(#[from] serde_yaml::Error),
+    #[error("tool not found: {0}")]
+    ToolNotFound(String),
+    #[error("the model kept calling tools after {0} rounds")]
+    ToolRounds(usize),
+    #[error("unable to attach file: {0}")]
+    Attachment(String, #[source] es_stream::error::Error),
+    #[error("invalid json schema: {0}")]
+    JsonSchema(String),
+    #[error("the response doesn't match the json schema:\n{0}")]
+    SchemaValidation(String),
+    #[error("interrupted")]
+    Interrupted,
+    #[error("the model failed before responding")]
+    NoResponse(#[source] Box<Error>),
 }
 
 pub(crate) fn format_error(
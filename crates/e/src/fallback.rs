@@ -0,0 +1,129 @@
+use es_stream::provider::ChatProvider;
+
+use crate::layers::Layers;
+use crate::prelude::*;
+
+/// A model the request can be sent to: the one set by the arguments, the preset and the config
+/// file, or one of the presets set on `fallbacks`.
+#[derive(Debug)]
+pub struct Candidate {
+    pub api: Api,
+    pub globals: Globals,
+}
+
+impl Candidate {
+    pub fn new(api: Api, globals: Globals) -> Self {
+        Self { api, globals }
+    }
+
+    /// Creates the candidate for the fallback preset `name`. Its settings are the ones of the
+    /// config files with the preset applied, overridden by the flags set on the command line,
+    /// `cli`. The model always comes from the preset, and the credentials and base url of
+    /// `primary` are only kept if the preset uses the same api.
+    pub fn fallback(
+        layers: &Layers,
+        name: &str,
+        cli: &Globals,
+        primary: &Candidate,
+    ) -> Result<Self> {
+        let preset = layers.preset_config(name)?;
+        let mut merged = layers.clone();
+        merged.apply_preset(name)?;
+        let mut config = merged.config()?;
+        let previous = &primary.globals;
+
+        let mut globals = cli.clone();
+        globals.preset = Some(name.to_string());
+        // The tools are selected once, for every candidate.
+        globals.tools = previous.tools.clone();
+        globals.model = config.model.take();
+
+        let mut api = config.api.ok_or(Error::ApiNotSpecified)?;
+        if let Some(alias) = globals
+            .model
+            .as_deref()
+            .and_then(|model| config.alias(model))
+        {
+            api = alias.api;
+            globals.model = Some(alias.model.clone());
+        }
+
+        globals.merge(&mut config);
+
+        if api == primary.api {
+            globals.api_key = preset.key.or(previous.api_key.clone());
+            globals.key_command = preset.key_command.or(previous.key_command.clone());
+            globals.key_file = preset.key_file.or(previous.key_file.clone());
+            globals.credentials = preset.credentials.or(previous.credentials.clone());
+            globals.api_env = preset.env.or(previous.api_env.clone());
+            globals.api_base_url = preset.base_url.or(previous.api_base_url.clone());
+            globals.api_version = preset.version.or(previous.api_version.clone());
+        } else {
+            globals.api_key = preset.key;
+            globals.key_command = preset.key_command;
+            globals.key_file = preset.key_file;
+            globals.credentials = preset.credentials;
+            globals.api_env = preset.env;
+            globals.api_base_url = preset.base_url;
+            globals.api_version = preset.version;
+        }
+
+        Ok(Self { api, globals })
+    }
+
+    /// Fails if the api needs a key and none of the `credentials` sources has one.
+    pub fn check_key(&self) -> Result<()> {
+        let Some(default_env) = self.api.key_env() else {
+            return Ok(());
+        };
+        if has_api_key(&self.globals, default_env) {
+            return Ok(());
+        }
+
+        let env = self.globals.api_env.as_deref().unwrap_or(default_env);
+        Err(Error::MissingApiKey(self.api, env.to_string()))
+    }
+
+    /// Fails with the missing key of this candidate unless it, or one of the `fallbacks` presets
+    /// it would fall back to, has an api key.
+    pub fn check_keys(&self, layers: &Layers, fallbacks: &[String], cli: &Globals) -> Result<()> {
+        let Err(error) = self.check_key() else {
+            return Ok(());
+        };
+
+        let mut previous: Option<Candidate> = None;
+        for name in fallbacks {
+            let candidate =
+                Candidate::fallback(layers, name, cli, previous.as_ref().unwrap_or(self))?;
+            if candidate.check_key().is_ok() {
+                return Ok(());
+            }
+            previous = Some(candidate);
+        }
+
+        Err(error)
+    }
+
+    /// The model to request.
+    pub fn model(&self) -> String {
+        self.globals
+            .model
+            .clone()
+            .unwrap_or_else(|| self.api.default_model().to_string())
+    }
+
+    /// Creates the client of the api.
+    pub fn provider(&self) -> Result<Box<dyn ChatProvider>> {
+        let globals = &self.globals;
+
+        Ok(match self.api {
+            Api::OpenAi => Box::new(crate::openai::client(globals)?),
+            Api::Anthropic => Box::new(crate::anthropic::client(globals)?),
+            Api::Google => Box::new(crate::google::client(globals)?),
+            Api::Mistral => Box::new(crate::mistral::client(globals)?),
+            Api::MistralFim => Box::new(crate::mistral_fim::client(globals)?),
+            Api::Copilot => Box::new(crate::copilot::client(globals)?),
+            Api::Ollama => Box::new(crate::ollama::client(globals)?),
+        })
+    }
+}
@@ -1,26 +1,18 @@
 use es_stream::google;
+use es_stream::options::WithOptions;
 
 use crate::prelude::*;
 
 const DEFAULT_URL: &str = "https://generativelanguage.googleapis.com/v1beta";
-const DEFAULT_MODEL: &str = "gemini-1.5-pro";
-const DEFAULT_ENV: &str = "GOOGLE_API_KEY";
+pub const DEFAULT_MODEL: &str = "gemini-1.5-pro";
+pub const DEFAULT_ENV: &str = "GOOGLE_API_KEY";
 
-pub async fn run(prompt: String, args: Args) -> Result<()> {
-    let key = match args.globals.api_key {
-        Some(key) => key,
-        None => {
-            let environment_variable = match args.globals.api_env {
-                Some(env) => env,
-                None => DEFAULT_ENV.to_string(),
-            };
-            std::env::var(environment_variable)?
-        }
-    };
-    log::info!("key: {}", key);
+/// Creates a Gemini client from the global arguments.
+pub fn client(globals: &Globals) -> Result<google::Client> {
+    let key = api_key(globals, DEFAULT_ENV)?;
 
-    let url = match args.globals.api_base_url {
-        Some(url) => url,
+    let url = match &globals.api_base_url {
+        Some(url) => url.clone(),
         None => DEFAULT_URL.to_string(),
     };
     log::info!("url: {}", url);
@@ -28,47 +20,11 @@ pub async fn run(prompt: String, args: Args) -> Result<()> {
     let auth = google::Auth::new(key);
     log::info!("auth: {:#?}", auth);
 
-    let client = google::Client::new(auth, url);
+    let client = google::Client::new(auth, url)
+        .with_retry_policy(retry_policy(globals))
+        .with_http_options(http_options(globals)?)
+        .with_cassette(cassette(globals));
     log::info!("client: {:#?}", client);
 
-    let contents = vec![google::Content {
-        parts: vec![google::Part { text: prompt }],
-        role: google::Role::User,
-    }];
-
-    let mut body = google::MessageBody::new(
-        args.globals
-            .model
-            .unwrap_or(DEFAULT_MODEL.to_string())
-            .as_ref(),
-        contents,
-    );
-
-    if let Some(system) = args.globals.system {
-        let system_message = google::Content {
-            parts: vec![google::Part { text: system }],
-            role: google::Role::User,
-        };
-
-        body.contents.insert(0, system_message);
-    }
-
-    body.generation_config = Some(google::GenerationConfig {
-        max_output_tokens: Some(args.globals.max_tokens.unwrap_or(4096)),
-        temperature: args.globals.temperature,
-        top_p: args.globals.top_p,
-        top_k: args.globals.top_k,
-        ..Default::default()
-    });
-
-    log::info!("body: {:#?}", body);
-
-    let stream = client.delta(&body)?;
-
-    handle_stream(
-        stream,
-        args.globals.quiet.unwrap_or(false),
-        args.globals.language,
-    )
-    .await
+    Ok(client)
 }
@@ -0,0 +1,568 @@
+use clap::Parser;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use toml::map::Entry;
+use toml::value::{Table, Value};
+
+use crate::args::ConfigCommand;
+use crate::prelude::*;
+
+/// Config file shared by every user of the machine.
+pub const SYSTEM_CONFIG_FILE: &str = "/etc/e.toml";
+
+/// Config file of a project, looked up on the current directory and its parents.
+pub const PROJECT_CONFIG_FILE: &str = ".e.toml";
+
+/// Prefix of the environment variables that override the config files. Nested settings are
+/// separated by `__`, e.g. `E_HTTP__PROXY`.
+const ENV_PREFIX: &str = "E_";
+
+/// Settings of the model that project config files and their presets can set. The others can run
+/// commands or send the api key somewhere else, so they are ignored unless the user config lists
+/// the project on `trusted_projects`.
+const MODEL_SETTINGS: [&str; 8] = [
+    "api",
+    "model",
+    "system",
+    "max_tokens",
+    "version",
+    "temperature",
+    "top_p",
+    "top_k",
+];
+
+/// Lists of a project config file merged besides the `MODEL_SETTINGS`.
+const PROJECT_LISTS: [&str; 4] = ["aliases", "fallbacks", "presets", "templates"];
+
+/// Settings of the presets of a project config file besides the `MODEL_SETTINGS`.
+const PROJECT_PRESET_SETTINGS: [&str; 3] = ["name", "extends", "tools"];
+
+/// Settings holding an api key, or the credentials of a proxy, redacted by `config show`.
+const SECRET_SETTINGS: [&str; 4] = ["key", "key_command", "credentials", "proxy"];
+
+/// Where a setting came from.
+#[derive(Debug, Clone)]
+pub enum Origin {
+    System(PathBuf),
+    User(PathBuf),
+    Project(PathBuf),
+    Env(String),
+    Preset(String),
+}
+
+impl std::fmt::Display for Origin {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Origin::System(path) => write!(f, "system {}", path.display()),
+            Origin::User(path) => write!(f, "user {}", path.display()),
+            Origin::Project(path) => write!(f, "project {}", path.display()),
+            Origin::Env(name) => write!(f, "env {name}"),
+            Origin::Preset(name) => write!(f, "preset {name}"),
+        }
+    }
+}
+
+/// The configuration merged from the system, user and project config files, and the `E_*`
+/// environment variables, in that order.
+///
+/// Tables are merged key by key, and lists of named tables, like `presets`, `templates` and
+/// `tools`, are merged by `name`, so a project can add its own presets and override the ones of
+/// the user. Any other value replaces the one from the previous layers.
+///
+/// Project config files can only set the model settings, presets and templates, unless the
+/// project is on the `trusted_projects` of the previous layers.
+///
+/// Once merged, each preset inherits the settings of the preset set on its `extends`.
+#[derive(Debug, Default, Clone)]
+pub struct Layers {
+    table: Table,
+    origins: BTreeMap<String, Origin>,
+    /// Presets extending unknown presets or themselves, with the reason.
+    pub problems: BTreeMap<String, String>,
+}
+
+impl Layers {
+    /// Loads the layers, using `user_file` as the user config file. Missing files are skipped.
+    pub fn load(user_file: &Path) -> Result<Self> {
+        let mut layers = Self::default();
+
+        layers.merge_file(Origin::System(SYSTEM_CONFIG_FILE.into()))?;
+        layers.merge_file(Origin::User(user_file.to_path_buf()))?;
+
+        let project = std::env::current_dir()?
+            .ancestors()
+            .map(|dir| dir.join(PROJECT_CONFIG_FILE))
+            .find(|path| path.is_file());
+        if let Some(path) = project {
+            layers.merge_file(Origin::Project(path))?;
+        }
+
+        let mut vars: Vec<(String, String)> = std::env::vars()
+            .filter(|(name, _)| name.starts_with(ENV_PREFIX))
+            .collect();
+        vars.sort();
+        for (name, value) in vars {
+            let path = name[ENV_PREFIX.len()..].to_lowercase();
+            if path.is_empty() {
+                continue;
+            }
+            let path: Vec<&str> = path.split("__").collect();
+            let table = nest(&path, parse_env(&value));
+            layers.merge(table, Origin::Env(name));
+        }
+
+        layers.extend_presets();
+
+        Ok(layers)
+    }
+
+    /// Fails if any preset extends an unknown preset or itself.
+    pub fn check(&self) -> Result<()> {
+        if self.problems.is_empty() {
+            Ok(())
+        } else {
+            let problems = self
+                .problems
+                .iter()
+                .map(|(preset, problem)| format!("preset {preset:?}: {problem}"))
+                .collect();
+            Err(Error::InvalidPresets(problems))
+        }
+    }
+
+    /// Names of the presets.
+    pub fn presets(&self) -> Vec<String> {
+        self.presets_table()
+            .iter()
+            .filter_map(|preset| name(preset))
+            .map(ToString::to_string)
+            .collect()
+    }
+
+    /// Applies the settings of the preset on top of the other layers. The `tools` of a preset
+    /// select the tools to use instead of defining them, so they are left out.
+    pub fn apply_preset(&mut self, name: &str) -> Result<()> {
+        let Some(mut preset) = self.preset(name).cloned() else {
+            return Err(Error::PresetNotFound(name.to_string()));
+        };
+
+        preset.remove("name");
+        preset.remove("extends");
+        preset.remove("tools");
+        self.merge(preset, Origin::Preset(name.to_string()));
+
+        Ok(())
+    }
+
+    /// The settings of the preset alone, with the ones it inherits, but without the ones of the
+    /// config files.
+    pub fn preset_config(&self, name: &str) -> Result<Config> {
+        let preset = self
+            .preset(name)
+            .ok_or_else(|| Error::PresetNotFound(name.to_string()))?;
+
+        Ok(Value::Table(preset.clone()).try_into()?)
+    }
+
+    pub fn config(&self) -> Result<Config> {
+        Ok(Value::Table(self.table.clone()).try_into()?)
+    }
+
+    /// Prints the merged configuration, with the origin of each setting if `origin` is set. Api
+    /// keys, the commands that print them and proxies are redacted.
+    pub fn show(&self, origin: bool) -> Result<()> {
+        if !origin {
+            let table = Value::Table(redact(self.table.clone(), ""));
+            print!("{}", toml::to_string(&table)?);
+            return Ok(());
+        }
+
+        let lines: Vec<(String, String)> = flatten(&self.table)
+            .into_iter()
+            .map(|(path, value)| {
+                let value = if is_secret(&path) {
+                    "\"***\"".to_string()
+                } else {
+                    value.to_string()
+                };
+                let origin = self
+                    .origin(&path)
+                    .map(ToString::to_string)
+                    .unwrap_or_default();
+                (format!("{path} = {value}"), origin)
+            })
+            .collect();
+
+        let width = lines.iter().map(|(line, _)| line.len()).max().unwrap_or(0);
+        for (line, origin) in lines {
+            println!("{line:width$}  # {origin}");
+        }
+
+        Ok(())
+    }
+
+    /// Finds the layer that set the value on `path`, or the closest of its parents.
+    fn origin(&self, path: &str) -> Option<&Origin> {
+        let mut path = path;
+        loop {
+            if let Some(origin) = self.origins.get(path) {
+                return Some(origin);
+            }
+            path = &path[..path.rfind('.')?];
+        }
+    }
+
+    fn presets_table(&self) -> &[Value] {
+        self.table
+            .get("presets")
+            .and_then(Value::as_array)
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+
+    fn preset(&self, preset: &str) -> Option<&Table> {
+        self.presets_table()
+            .iter()
+            .find(|value| name(value) == Some(preset))
+            .and_then(Value::as_table)
+    }
+
+    /// Merges the settings of the parents of every preset into it, starting from the farthest
+    /// one. Presets with an unknown parent or a cycle are left as they are.
+    fn extend_presets(&mut self) {
+        let mut presets = Vec::new();
+
+        for value in self.presets_table().to_vec() {
+            let Some(preset) = name(&value).map(ToString::to_string) else {
+                presets.push(value);
+                continue;
+            };
+
+            let mut lineage = match self.parents(&preset) {
+                Ok(parents) => parents,
+                Err(problem) => {
+                    self.problems.insert(preset, problem);
+                    presets.push(value);
+                    continue;
+                }
+            };
+            lineage.reverse();
+            lineage.push(preset.clone());
+
+            // Inherited settings keep the origin of the preset that set them.
+            let mut table = Table::new();
+            let mut origins = Vec::new();
+            for ancestor in &lineage {
+                let Some(settings) = self.preset(ancestor) else {
+                    continue;
+                };
+                for (path, _) in flatten(settings) {
+                    if let Some(origin) = self.origin(&format!("presets.{ancestor}.{path}")) {
+                        origins.push((format!("presets.{preset}.{path}"), origin.clone()));
+                    }
+                }
+                merge(&mut table, settings.clone());
+            }
+
+            self.origins.extend(origins);
+            presets.push(Value::Table(table));
+        }
+
+        if !presets.is_empty() {
+            self.table.insert("presets".into(), Value::Array(presets));
+        }
+    }
+
+    /// Lists the presets `preset` extends, from its parent to the farthest one.
+    fn parents(&self, preset: &str) -> std::result::Result<Vec<String>, String> {
+        let mut chain = vec![preset.to_string()];
+
+        loop {
+            let current = &chain[chain.len() - 1];
+            let Some(extends) = self.preset(current).and_then(|table| table.get("extends")) else {
+                break;
+            };
+            let Some(parent) = extends.as_str() else {
+                return Err(format!("{current:?}: `extends` must be a preset name"));
+            };
+
+            let unknown = self.preset(parent).is_none();
+            let cycle = chain.iter().any(|name| name == parent);
+            chain.push(parent.to_string());
+            if unknown {
+                let chain = chain.join(" -> ");
+                return Err(format!("unknown preset {parent:?} on `extends`: {chain}"));
+            }
+            if cycle {
+                return Err(format!("cycle on `extends`: {}", chain.join(" -> ")));
+            }
+        }
+
+        chain.remove(0);
+        Ok(chain)
+    }
+
+    fn merge_file(&mut self, origin: Origin) -> Result<()> {
+        let (Origin::System(path) | Origin::User(path) | Origin::Project(path)) = &origin else {
+            return Ok(());
+        };
+        if !path.is_file() {
+            return Ok(());
+        }
+
+        log::info!("loading config file: {:?}", path);
+        let content = std::fs::read_to_string(path)?;
+        let mut table = toml::from_str(&content).map_err(|e| Error::ConfigFile(path.clone(), e))?;
+        if matches!(origin, Origin::Project(_)) && !self.is_trusted(path) {
+            table = untrusted(table, path);
+        }
+        self.merge(table, origin);
+
+        Ok(())
+    }
+
+    /// Checks if the directory of the project config file is on `trusted_projects`.
+    fn is_trusted(&self, file: &Path) -> bool {
+        let Some(dir) = file.parent().and_then(|dir| dir.canonicalize().ok()) else {
+            return false;
+        };
+        let home = std::env::var("HOME").unwrap_or_default();
+
+        self.table
+            .get("trusted_projects")
+            .and_then(Value::as_array)
+            .into_iter()
+            .flatten()
+            .filter_map(Value::as_str)
+            .filter_map(|trusted| Path::new(&trusted.replace('~', &home)).canonicalize().ok())
+            .any(|trusted| trusted == dir)
+    }
+
+    fn merge(&mut self, layer: Table, origin: Origin) {
+        for (path, _) in flatten(&layer) {
+            self.origins.insert(path, origin.clone());
+        }
+        merge(&mut self.table, layer);
+    }
+}
+
+/// Runs the `config` subcommands.
+pub fn run(layers: &Layers, command: &ConfigCommand) -> Result<()> {
+    match command {
+        ConfigCommand::Show { origin } => layers.show(*origin),
+        ConfigCommand::Validate => {
+            let mut failed = 0;
+            let presets = layers.presets().into_iter().map(Some);
+            for preset in std::iter::once(None).chain(presets) {
+                let name = preset.as_deref().unwrap_or("(config)");
+                if let Some(problem) = layers.problems.get(name) {
+                    failed += 1;
+                    println!("error  {}: {}", name, problem);
+                    continue;
+                }
+                match validate(layers.clone(), preset.as_deref()) {
+                    Ok(()) => println!("ok     {}", name),
+                    Err(e) => {
+                        failed += 1;
+                        println!("error  {}: {:?}", name, e);
+                    }
+                }
+            }
+
+            if failed > 0 {
+                return Err(Error::InvalidConfig(failed));
+            }
+            Ok(())
+        }
+    }
+}
+
+/// Checks that the configuration, with `preset` applied, can be loaded, has an api key, and its
+/// fallbacks exist.
+fn validate(mut layers: Layers, preset: Option<&str>) -> Result<()> {
+    if let Some(preset) = preset {
+        layers.apply_preset(preset)?;
+    }
+    let config = layers.config()?;
+
+    for fallback in config.fallbacks.iter().flatten() {
+        if layers.preset(fallback).is_none() {
+            return Err(Error::PresetNotFound(fallback.clone()));
+        }
+    }
+
+    let alias = config.model.as_ref().and_then(|model| config.alias(model));
+    let Some(api) = alias.map(|alias| alias.api).or(config.api) else {
+        return Ok(());
+    };
+    let Some(default_env) = api.key_env() else {
+        return Ok(());
+    };
+
+    let mut globals = Args::parse_from(["e"]).globals;
+    globals.api_key = config.key;
+    globals.key_command = config.key_command;
+    globals.key_file = config.key_file;
+    globals.api_env = config.env;
+    globals.credentials = config.credentials;
+
+    if has_api_key(&globals, default_env) {
+        Ok(())
+    } else {
+        let env = globals.api_env.as_deref().unwrap_or(default_env);
+        Err(Error::MissingApiKey(api, env.to_string()))
+    }
+}
+
+fn merge(base: &mut Table, layer: Table) {
+    for (key, value) in layer {
+        match base.entry(key) {
+            Entry::Occupied(mut entry) => merge_value(entry.get_mut(), value),
+            Entry::Vacant(entry) => {
+                entry.insert(value);
+            }
+        }
+    }
+}
+
+fn merge_value(base: &mut Value, value: Value) {
+    match (base, value) {
+        (Value::Table(base), Value::Table(value)) => merge(base, value),
+        (Value::Array(base), Value::Array(value)) if is_named(base) && is_named(&value) => {
+            for item in value {
+                match base.iter_mut().find(|other| name(other) == name(&item)) {
+                    Some(other) => *other = item,
+                    None => base.push(item),
+                }
+            }
+        }
+        (base, value) => *base = value,
+    }
+}
+
+fn name(value: &Value) -> Option<&str> {
+    value.get("name").and_then(Value::as_str)
+}
+
+/// Checks if every item of the list is a table with a `name`.
+fn is_named(values: &[Value]) -> bool {
+    !values.is_empty() && values.iter().all(|value| name(value).is_some())
+}
+
+/// Lists the values of the table by their dotted path. Items of lists of named tables use their
+/// name as the key, e.g. `presets.fast.model`.
+fn flatten(table: &Table) -> Vec<(String, Value)> {
+    let mut values = Vec::new();
+    for (key, value) in table {
+        flatten_value(key.clone(), value, &mut values);
+    }
+    values
+}
+
+fn flatten_value(path: String, value: &Value, values: &mut Vec<(String, Value)>) {
+    match value {
+        Value::Table(table) => {
+            for (key, value) in table {
+                flatten_value(format!("{path}.{key}"), value, values);
+            }
+        }
+        Value::Array(items) if is_named(items) => {
+            for item in items {
+                let name = name(item).unwrap_or_default();
+                flatten_value(format!("{path}.{name}"), item, values);
+            }
+        }
+        value => values.push((path, value.clone())),
+    }
+}
+
+/// Drops the settings of a project config file that aren't model settings, on the file and on its
+/// presets.
+fn untrusted(table: Table, file: &Path) -> Table {
+    let allowed = |path: &str, key: &str, settings: &[&str]| {
+        let allowed = MODEL_SETTINGS.contains(&key) || settings.contains(&key);
+        if !allowed {
+            log::warn!(
+                "ignoring `{}` on {:?}: add the project to `trusted_projects` to allow it",
+                path,
+                file
+            );
+        }
+        allowed
+    };
+
+    let mut table: Table = table
+        .into_iter()
+        .filter(|(key, _)| allowed(key, key, &PROJECT_LISTS))
+        .collect();
+
+    if let Some(Value::Array(presets)) = table.get_mut("presets") {
+        for preset in presets {
+            let path = format!("presets.{}", name(preset).unwrap_or_default());
+            if let Value::Table(settings) = preset {
+                *settings = std::mem::take(settings)
+                    .into_iter()
+                    .filter(|(key, _)| {
+                        allowed(&format!("{path}.{key}"), key, &PROJECT_PRESET_SETTINGS)
+                    })
+                    .collect();
+            }
+        }
+    }
+
+    table
+}
+
+fn is_secret(path: &str) -> bool {
+    let key = path.rsplit('.').next().unwrap_or(path);
+    SECRET_SETTINGS.contains(&key)
+}
+
+/// Replaces the values of the settings on `is_secret` with `***`. `path` is the dotted path of
+/// the table, as listed by `flatten`.
+fn redact(mut table: Table, path: &str) -> Table {
+    for (key, value) in table.iter_mut() {
+        let path = if path.is_empty() {
+            key.clone()
+        } else {
+            format!("{path}.{key}")
+        };
+        match value {
+            value if is_secret(&path) => *value = Value::String("***".into()),
+            Value::Table(inner) => *inner = redact(std::mem::take(inner), &path),
+            Value::Array(items) => {
+                for item in items {
+                    let path = format!("{path}.{}", name(item).unwrap_or_default());
+                    if let Value::Table(inner) = item {
+                        *inner = redact(std::mem::take(inner), &path);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    table
+}
+
+/// Builds the table holding `value` on the given path.
+fn nest(path: &[&str], value: Value) -> Table {
+    let (last, parents) = path.split_last().unwrap_or((&"", &[]));
+
+    let mut table = Table::new();
+    table.insert(last.to_string(), value);
+    for key in parents.iter().rev() {
+        let mut parent = Table::new();
+        parent.insert(key.to_string(), Value::Table(table));
+        table = parent;
+    }
+    table
+}
+
+/// Parses the value of an environment variable as a TOML value, so `E_TEMPERATURE=0.2` sets a
+/// number and `E_CREDENTIALS='["env"]'` a list. Anything else is taken as a string.
+fn parse_env(value: &str) -> Value {
+    toml::from_str::<Table>(&format!("value = {value}"))
+        .ok()
+        .and_then(|mut table| table.remove("value"))
+        .unwrap_or_else(|| Value::String(value.to_string()))
+}
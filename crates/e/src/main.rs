@@ -1,27 +1,35 @@
 use clap::Parser;
-use config_file::FromConfigFile;
 
 mod anthropic;
 mod args;
 mod config;
+mod conversation;
+mod copilot;
 mod error;
+mod fallback;
 mod google;
+mod layers;
 mod mistral;
 mod mistral_fim;
+mod ollama;
 mod openai;
 mod prelude;
 mod printer;
+mod schema;
+mod template_functions;
+mod templates;
+mod tools;
 
+use crate::fallback::Candidate;
 use crate::prelude::*;
 
-const SYSTEM_TEMPLATE: &str = "system";
-const PROMPT_TEMPLATE: &str = "prompt";
-
 #[tokio::main]
 async fn main() -> Result<()> {
     env_logger::init();
 
     let mut args = Args::parse();
+    // The flags set on the command line override the settings of the fallback presets too.
+    let mut cli = args.globals.clone();
 
     let mut api: Option<Api> = if let Some(api) = args.globals.api.clone() {
         Some(api.parse()?)
@@ -29,15 +37,6 @@ async fn main() -> Result<()> {
         None
     };
 
-    let mut prompt = args.globals.prompt.to_string();
-    let mut stdin = args.globals.stdin.to_string();
-
-    // Turn them around if there's nothing coming from `stdin`.
-    if prompt.is_empty() && !stdin.is_empty() {
-        stdin = args.globals.prompt.to_string();
-        prompt = args.globals.stdin.to_string();
-    }
-
     log::info!("info: {:#?}", args.globals);
 
     let home = std::env::var("HOME")?;
@@ -45,109 +44,114 @@ async fn main() -> Result<()> {
 
     log::info!("path: {:#?}", path);
 
-    // Check if `path` exists
-    let config = if !std::path::Path::new(&path).exists() {
-        Config::default()
-    } else {
-        Config::from_config_file(path)?
-    };
+    let mut layers = layers::Layers::load(std::path::Path::new(&path))?;
 
-    log::info!("config: {:#?}", config);
+    if let Some(Command::Config(command)) = &args.command {
+        return layers::run(&layers, command);
+    }
 
-    if let Some(preset) = args.globals.preset.clone() {
-        let p = config
-            .presets
-            .unwrap_or_default()
-            .into_iter()
-            .find(|p| p.name == preset);
+    layers.check()?;
+    // Fallback presets are applied on the config files alone.
+    let base_layers = layers.clone();
+    if let Some(preset) = &args.globals.preset {
+        layers.apply_preset(preset)?;
+    }
 
-        if let Some(p) = p {
-            api = Some(p.api);
+    let mut config = layers.config()?;
 
-            if args.globals.top_p.is_none() {
-                args.globals.top_p = p.top_p;
-            }
-            if args.globals.top_k.is_none() {
-                args.globals.top_k = p.top_k;
-            }
-            if args.globals.temperature.is_none() {
-                args.globals.temperature = p.temperature;
-            }
-            if args.globals.system.is_none() {
-                args.globals.system = p.system;
-            }
-            if args.globals.max_tokens.is_none() {
-                args.globals.max_tokens = p.max_tokens;
-            }
-            if args.globals.api_version.is_none() {
-                args.globals.api_version = p.version;
-            }
-            if args.globals.api_env.is_none() {
-                args.globals.api_env = p.env;
-            }
-            if args.globals.api_key.is_none() {
-                args.globals.api_key = p.key;
-            }
-            if args.globals.api_base_url.is_none() {
-                args.globals.api_base_url = p.base_url;
-            }
-            if args.globals.model.is_none() {
-                args.globals.model = p.model;
-            }
-        }
-    };
+    log::info!("config: {:#?}", config);
 
-    if args.globals.top_p.is_none() {
-        args.globals.top_p = config.top_p;
-    }
-    if args.globals.top_k.is_none() {
-        args.globals.top_k = config.top_k;
-    }
-    if args.globals.temperature.is_none() {
-        args.globals.temperature = config.temperature;
-    }
-    if args.globals.system.is_none() {
-        args.globals.system = config.system;
-    }
-    if args.globals.max_tokens.is_none() {
-        args.globals.max_tokens = config.max_tokens;
-    }
-    if args.globals.api_version.is_none() {
-        args.globals.api_version = config.version;
-    }
-    if args.globals.api_env.is_none() {
-        args.globals.api_env = config.env;
+    if let Some(Command::Usage(UsageCommand::Report { group_by })) = &args.command {
+        let path = usage::ledger_path(config.usage_file.as_deref())?;
+        usage::report(&usage::load(&path)?, group_by);
+        return Ok(());
     }
-    if args.globals.api_key.is_none() {
-        args.globals.api_key = config.key;
+
+    let templates_dir = config
+        .templates_dir
+        .as_deref()
+        .unwrap_or(templates::DEFAULT_TEMPLATES_DIR)
+        .replace('~', &home);
+
+    if let Some(Command::Templates(command)) = &args.command {
+        let library = templates::Library::load(
+            config.templates.unwrap_or_default(),
+            Some(std::path::Path::new(&templates_dir)),
+            config.template_commands.clone().unwrap_or_default(),
+        )?;
+        return templates::run(library, command);
     }
-    if args.globals.api_base_url.is_none() {
-        args.globals.api_base_url = config.base_url;
+
+    // The other settings of the preset are already merged on `config`.
+    if args.globals.tools.is_none() {
+        args.globals.tools = args.globals.preset.as_ref().and_then(|name| {
+            config
+                .presets
+                .as_ref()?
+                .iter()
+                .find(|p| &p.name == name)?
+                .tools
+                .clone()
+        });
     }
+
     if args.globals.model.is_none() {
-        args.globals.model = config.model;
-    }
-    if args.globals.quiet.is_none() {
-        args.globals.quiet = config.quiet;
+        args.globals.model = config.model.take();
     }
+    // Aliases set both the api and the model. Look it up before the settings below are moved out
+    // of `config`.
+    let alias = args
+        .globals
+        .model
+        .as_deref()
+        .and_then(|model| config.alias(model))
+        .cloned();
+
+    args.globals.merge(&mut config);
     if api.is_none() {
         api = config.api;
     }
 
+    if let Some(alias) = alias {
+        log::info!("alias: {:#?}", alias);
+        api = Some(alias.api);
+        args.globals.model = Some(alias.model);
+    }
+
+    // Report a missing api key before waiting for the prompt, unless one of the fallbacks has
+    // one. Replays and rendered templates don't need one.
+    let needs_key = args.globals.replay.is_none() && !args.globals.print_template;
+    if let Some(api) = api.filter(|_| needs_key) {
+        Candidate::new(api, args.globals.clone()).check_keys(
+            &base_layers,
+            config.fallbacks.as_deref().unwrap_or_default(),
+            &cli,
+        )?;
+    }
+
+    let mut prompt = args.globals.prompt.to_string();
+    let mut stdin = match &args.globals.stdin {
+        Some(stdin) => stdin.to_string(),
+        // Read `stdin` only after handling the subcommands, so they don't wait for it.
+        None => std::io::read_to_string(std::io::stdin())?
+            .trim()
+            .to_string(),
+    };
+
+    // Turn them around if there's nothing coming from `stdin`.
+    if prompt.is_empty() && !stdin.is_empty() {
+        std::mem::swap(&mut prompt, &mut stdin);
+    }
+
     log::info!("globals: {:#?}", args.globals);
 
     let prompt: String = if let Some(ref template) = args.globals.template {
-        let t = config
-            .templates
-            .unwrap_or_default()
-            .into_iter()
-            .find(|t| t.name == *template);
-
-        if t.is_none() {
-            return Err(Error::TemplateNotFound);
-        }
-
-        let t = t.unwrap();
+        let library = templates::Library::load(
+            config.templates.unwrap_or_default(),
+            Some(std::path::Path::new(&templates_dir)),
+            config.template_commands.clone().unwrap_or_default(),
+        )?;
+        let t = library.get(template)?;
 
         log::info!("template: {:#?}", t);
 
@@ -155,34 +159,16 @@ async fn main() -> Result<()> {
         let suffix = args.globals.suffix.clone().unwrap_or_default().to_string();
         let language = args.globals.language.clone();
 
-        let mut default_vars = t.default_vars.unwrap_or_default();
+        let builtin = templates::builtin_vars(&prompt, &system, &stdin, &suffix, &language);
         let vars = args.globals.vars.take().unwrap_or_default();
-        merge(&mut default_vars, vars);
-
-        let mut value = serde_json::json!({
-            "prompt": prompt,
-            "system": system,
-            "stdin": stdin,
-            "suffix": suffix,
-            "language": language,
-        });
-
-        merge(&mut value, default_vars);
-
-        let context = tera::Context::from_value(value)?;
+        let rendered = library.render(t, builtin, vars)?;
 
-        log::info!("context: {:#?}", context);
-
-        let mut tera = tera::Tera::default();
-
-        if let Some(system) = t.system {
-            tera.add_raw_template(SYSTEM_TEMPLATE, &system)?;
-            args.globals.system = Some(tera.render(SYSTEM_TEMPLATE, &context)?);
+        if rendered.system.is_some() {
+            cli.system = rendered.system.clone();
+            args.globals.system = rendered.system;
         }
 
-        tera.add_raw_template(PROMPT_TEMPLATE, t.template.as_ref())?;
-
-        tera.render(PROMPT_TEMPLATE, &context)?
+        rendered.prompt
     } else if !stdin.is_empty() {
         format!("{}\n{}", stdin, prompt)
     } else {
@@ -190,22 +176,145 @@ async fn main() -> Result<()> {
     };
 
     if args.globals.print_template {
-        if args.globals.system.is_some() {
-            println!(
-                "System Prompt:\n{}\n---\n",
-                args.globals.system.clone().unwrap()
-            );
-        }
-        println!("Prompt:\n{}", prompt);
+        templates::print_rendered(args.globals.system.as_deref(), &prompt);
         return Ok(());
     }
 
-    match api {
-        Some(Api::OpenAi) => openai::run(prompt, args).await,
-        Some(Api::Anthropic) => anthropic::run(prompt, args).await,
-        Some(Api::Google) => google::run(prompt, args).await,
-        Some(Api::Mistral) => mistral::run(prompt, args).await,
-        Some(Api::MistralFim) => mistral_fim::run(prompt, args).await,
-        None => Err(Error::ApiNotSpecified),
+    let tools = tools::select(
+        config.tools.unwrap_or_default(),
+        args.globals.tools.as_deref().unwrap_or_default(),
+    )?;
+
+    let api = api.ok_or(Error::ApiNotSpecified)?;
+
+    let session_path = match &args.globals.session {
+        Some(name) => Some(Conversation::session_path(
+            args.globals
+                .sessions_dir
+                .as_deref()
+                .unwrap_or(conversation::DEFAULT_SESSIONS_DIR),
+            name,
+        )?),
+        None => None,
+    };
+
+    let mut conversation = if let Some(path) = &session_path {
+        if path.exists() {
+            Conversation::load(path)?
+        } else {
+            Conversation::default()
+        }
+    } else if args.globals.continue_conversation {
+        let path = Conversation::last_path()?;
+        if path.exists() {
+            Conversation::load(&path)?
+        } else {
+            Conversation::default()
+        }
+    } else if let Some(path) = &args.globals.conversation {
+        Conversation::load(std::path::Path::new(&path.replace('~', &home)))?
+    } else {
+        Conversation::default()
+    };
+
+    // The conversation file may already end with the user turn.
+    if !prompt.is_empty() || conversation.turns.is_empty() {
+        conversation.push(config::Role::User, prompt);
+    }
+
+    let print_usage = args.globals.usage;
+    let replay = args.globals.replay.is_some();
+
+    // Listen for Ctrl+C only once `stdin` has been read, so it still exits while waiting for it.
+    let cancel = CancellationToken::on_interrupt();
+
+    // Send the request to the `fallbacks` presets, in order, while the models fail before
+    // responding.
+    let mut fallbacks = config.fallbacks.take().unwrap_or_default().into_iter();
+    let mut candidate = Candidate::new(api, args.globals);
+    let mut fell_back = false;
+
+    let output = loop {
+        // A missing api key moves on to the next fallback too.
+        let provider = if needs_key {
+            candidate.check_key().and_then(|_| candidate.provider())
+        } else {
+            candidate.provider()
+        };
+        let output = match provider {
+            Ok(provider) => {
+                let model = candidate.model();
+                run(
+                    provider.as_ref(),
+                    &model,
+                    &conversation,
+                    &tools,
+                    &cancel,
+                    &candidate.globals,
+                )
+                .await
+            }
+            Err(e) => Err(Error::NoResponse(Box::new(e))),
+        };
+
+        match output {
+            Err(Error::NoResponse(e)) => {
+                let Some(next) = fallbacks.next() else {
+                    return Err(*e);
+                };
+                eprintln!(
+                    "{} failed, falling back to the {:?} preset: {}",
+                    candidate.model(),
+                    next,
+                    e
+                );
+                candidate = Candidate::fallback(&base_layers, &next, &cli, &candidate)?;
+                fell_back = true;
+            }
+            output => break output?,
+        }
+    };
+
+    let model = output.model.clone().unwrap_or_else(|| candidate.model());
+    if fell_back {
+        eprintln!("\nanswered by {}", model);
+    }
+
+    let record = usage::Record::new(
+        format!("{:?}", candidate.api).to_lowercase(),
+        model,
+        candidate.globals.preset.clone(),
+        output.usage.input_tokens,
+        output.usage.output_tokens,
+        &config.pricing.unwrap_or_default(),
+    );
+    if print_usage {
+        eprintln!("\n{}", record.summary());
+    }
+    // Replayed responses didn't cost anything.
+    if !replay {
+        usage::append(&usage::ledger_path(config.usage_file.as_deref())?, &record)?;
     }
+
+    if output.truncated {
+        // Nothing to recover if the stream was interrupted before any text arrived.
+        if output.content.is_empty() {
+            return Err(Error::Interrupted);
+        }
+        conversation.push_truncated(output.content);
+    } else {
+        conversation.push(config::Role::Assistant, output.content);
+    }
+
+    conversation.save(&Conversation::last_path()?)?;
+
+    if let Some(path) = &session_path {
+        conversation.save(path)?;
+    }
+
+    if output.truncated {
+        return Err(Error::Interrupted);
+    }
+
+    Ok(())
 }
@@ -1,78 +1,30 @@
 use es_stream::mistral;
+use es_stream::options::WithOptions;
 
 use crate::prelude::*;
 
 const DEFAULT_URL: &str = "https://api.mistral.ai/v1";
-const DEFAULT_MODEL: &str = "mistral-small-latest";
-const DEFAULT_ENV: &str = "MISTRAL_API_KEY";
+pub const DEFAULT_MODEL: &str = "mistral-small-latest";
+pub const DEFAULT_ENV: &str = "MISTRAL_API_KEY";
 
-pub async fn run(prompt: String, args: Args) -> Result<()> {
-    let key = match args.globals.api_key {
-        Some(key) => key,
-        None => {
-            let environment_variable = match args.globals.api_env {
-                Some(env) => env,
-                None => DEFAULT_ENV.to_string(),
-            };
-            std::env::var(environment_variable)?
-        }
-    };
-    log::info!("key: {}", key);
+/// Creates a Mistral client from the global arguments.
+pub fn client(globals: &Globals) -> Result<mistral::Client> {
+    let key = api_key(globals, DEFAULT_ENV)?;
 
-    let url = match args.globals.api_base_url {
-        Some(url) => url,
+    let url = match &globals.api_base_url {
+        Some(url) => url.clone(),
         None => DEFAULT_URL.to_string(),
     };
-
     log::info!("url: {}", url);
 
     let auth = mistral::Auth::new(key);
-
     log::info!("auth: {:#?}", auth);
 
-    let client = mistral::Client::new(auth, url);
-
+    let client = mistral::Client::new(auth, url)
+        .with_retry_policy(retry_policy(globals))
+        .with_http_options(http_options(globals)?)
+        .with_cassette(cassette(globals));
     log::info!("client: {:#?}", client);
 
-    let messages = vec![mistral::Message {
-        role: mistral::Role::User,
-        content: prompt,
-    }];
-
-    let mut body = mistral::MessageBody::new(
-        args.globals
-            .model
-            .unwrap_or(DEFAULT_MODEL.to_string())
-            .as_ref(),
-        messages,
-    );
-
-    if let Some(system) = args.globals.system {
-        let system_message = mistral::Message {
-            role: mistral::Role::System,
-            content: system,
-        };
-
-        body.messages.insert(0, system_message);
-    }
-
-    body.temperature = args.globals.temperature;
-    body.top_p = args.globals.top_p;
-    if let Some(max_tokens) = args.globals.max_tokens {
-        body.max_tokens = Some(max_tokens);
-    };
-    if let Some(min_tokens) = args.globals.min_tokens {
-        body.min_tokens = Some(min_tokens);
-    };
-
-    log::info!("body: {:#?}", body);
-
-    let stream = client.delta(&body)?;
-
-    handle_stream(
-        stream,
-        args.globals.quiet.unwrap_or(false),
-        args.globals.language,
-    )
-    .await
+    Ok(client)
 }
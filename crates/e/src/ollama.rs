@@ -0,0 +1,30 @@
+use es_stream::ollama;
+use es_stream::options::WithOptions;
+
+use crate::prelude::*;
+
+const DEFAULT_URL: &str = "http://localhost:11434";
+pub const DEFAULT_MODEL: &str = "llama3.1";
+const DEFAULT_ENV: &str = "OLLAMA_API_KEY";
+
+/// Creates an Ollama client from the global arguments. The api key is optional.
+pub fn client(globals: &Globals) -> Result<ollama::Client> {
+    let key = api_key(globals, DEFAULT_ENV).ok();
+
+    let url = match &globals.api_base_url {
+        Some(url) => url.clone(),
+        None => DEFAULT_URL.to_string(),
+    };
+    log::info!("url: {}", url);
+
+    let auth = ollama::Auth::new(key);
+    log::info!("auth: {:#?}", auth);
+
+    let client = ollama::Client::new(auth, url)
+        .with_retry_policy(retry_policy(globals))
+        .with_http_options(http_options(globals)?)
+        .with_cassette(cassette(globals));
+    log::info!("client: {:#?}", client);
+
+    Ok(client)
+}
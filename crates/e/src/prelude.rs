@@ -1,19 +1,337 @@
+use credentials::Provider;
+use es_stream::cassette::Cassette;
+use es_stream::event::{StreamEvent, TokenUsage};
+use es_stream::http::HttpOptions;
+use es_stream::provider::{Attachment, ChatProvider, ChatRequest, Message, Role};
+use es_stream::retry::RetryPolicy;
+use es_stream::secret::Secret;
+use es_stream::tool::ToolCall;
 use futures::stream::{Stream, TryStreamExt};
 use serde_json::Value;
 use std::io::Write;
+use std::path::PathBuf;
 
-pub use crate::args::{Api, Args};
+use crate::config::KeySource;
+
+pub use crate::args::{Api, Args, Command, Globals, UsageCommand};
 pub use crate::config::Config;
+pub use crate::conversation::Conversation;
 pub use crate::error::Error;
+pub use cancel::CancellationToken;
 
 pub type Result<T> = std::result::Result<T, Error>;
 
+// Maximum number of tool call rounds before giving up on getting a final answer from the model.
+const MAX_TOOL_ROUNDS: usize = 16;
+
+// Number of times the model is asked to fix a response that doesn't match the JSON schema.
+const MAX_SCHEMA_RETRIES: usize = 2;
+
+/// Summary of a streamed response.
+#[derive(Debug, Default)]
+pub struct StreamOutput {
+    /// The model that handled the request, as reported by the provider.
+    pub model: Option<String>,
+    /// The full text of the response.
+    pub content: String,
+    /// The reason the model stopped generating tokens.
+    pub stop_reason: Option<String>,
+    /// Token usage reported by the provider.
+    pub usage: TokenUsage,
+    /// Tool calls requested by the model.
+    pub tool_calls: Vec<ToolCall>,
+    /// The user interrupted the stream before the model finished the response.
+    pub truncated: bool,
+}
+
+/// Checks if any of the sources set on `credentials` has an api key, without running the
+/// `key_command` or decrypting the `key_file`, so a missing key is reported before reading the
+/// prompt.
+pub fn has_api_key(globals: &Globals, default_env: &str) -> bool {
+    let order = globals
+        .credentials
+        .as_deref()
+        .unwrap_or(&KeySource::DEFAULT_ORDER);
+
+    order.iter().any(|source| match source {
+        KeySource::Key => globals.api_key.is_some(),
+        KeySource::KeyCommand => globals.key_command.is_some(),
+        KeySource::KeyFile => globals.key_file.is_some(),
+        KeySource::Env => std::env::var(globals.api_env.as_deref().unwrap_or(default_env))
+            .is_ok_and(|key| !key.is_empty()),
+    })
+}
+
+/// Gets the api key from the first source that has one, in the order set on `credentials`: the
+/// `--api-key` argument or `key` setting, the `key_command` output, the `key_file` age encrypted
+/// file, and the environment variable set on `api_env` or `default_env`.
+pub fn api_key(globals: &Globals, default_env: &str) -> Result<Secret> {
+    let order = globals
+        .credentials
+        .as_deref()
+        .unwrap_or(&KeySource::DEFAULT_ORDER);
+
+    let mut providers = Vec::new();
+    for source in order {
+        let provider = match source {
+            KeySource::Key => globals
+                .api_key
+                .as_ref()
+                .map(|key| Provider::Key(key.expose().to_string())),
+            KeySource::KeyCommand => globals.key_command.clone().map(Provider::Command),
+            KeySource::KeyFile => match &globals.key_file {
+                Some(path) => Some(Provider::File(credentials::expand_home(path)?)),
+                None => None,
+            },
+            KeySource::Env => Some(Provider::Env(
+                globals
+                    .api_env
+                    .as_deref()
+                    .unwrap_or(default_env)
+                    .to_string(),
+            )),
+        };
+        providers.extend(provider);
+    }
+
+    match credentials::resolve(&providers) {
+        Ok(key) => Ok(key.into()),
+        // Replayed responses are read from disk, so the key is never sent.
+        Err(_) if globals.replay.is_some() => Ok(Secret::default()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Gets the retry policy from the preset or the config file, letting `--max-retries` override
+/// the number of retries. Replayed requests are never retried, since they would only read the
+/// same recorded error again.
+pub fn retry_policy(globals: &Globals) -> RetryPolicy {
+    if globals.replay.is_some() {
+        return RetryPolicy::none();
+    }
+
+    let mut policy = globals
+        .retry
+        .map(|retry| retry.policy())
+        .unwrap_or_default();
+
+    if let Some(max_retries) = globals.max_retries {
+        policy.max_retries = max_retries;
+    }
+
+    policy
+}
+
+/// Gets the proxy, CA certificates and timeouts from the config file and the environment.
+pub fn http_options(globals: &Globals) -> Result<HttpOptions> {
+    match &globals.http {
+        Some(http) => Ok(http.options()?),
+        None => Ok(HttpOptions::from_env()),
+    }
+}
+
+/// Gets the cassette used to record the requests with `--record`, or to replay them with
+/// `--replay`.
+pub fn cassette(globals: &Globals) -> Option<Cassette> {
+    match (&globals.record, &globals.replay) {
+        (Some(dir), _) => Some(Cassette::record(dir)),
+        (None, Some(dir)) => Some(Cassette::replay(dir)),
+        (None, None) => None,
+    }
+}
+
+/// Directory where `e` stores its cache files (`$XDG_CACHE_HOME/e` or `~/.cache/e`.)
+pub fn cache_dir() -> Result<PathBuf> {
+    let cache_dir = match std::env::var("XDG_CACHE_HOME") {
+        Ok(dir) => PathBuf::from(dir),
+        Err(_) => PathBuf::from(std::env::var("HOME")?).join(".cache"),
+    };
+
+    Ok(cache_dir.join("e"))
+}
+
+/// Sends the conversation to the provider and prints the streamed response. When the model calls
+/// any of the `tools`, they are run locally and their results are sent back until the model
+/// produces a final answer.
+///
+/// With `--json-schema`, the response is buffered and validated before printing it. Invalid
+/// responses are sent back to the model with the validation errors, up to `MAX_SCHEMA_RETRIES`
+/// times.
+///
+/// When `cancel` is cancelled, the partial response is returned right away with `truncated` set.
+pub async fn run(
+    provider: &dyn ChatProvider,
+    default_model: &str,
+    conversation: &Conversation,
+    tools: &[crate::config::Tool],
+    cancel: &CancellationToken,
+    globals: &Globals,
+) -> Result<StreamOutput> {
+    let mut request = ChatRequest::new(
+        globals
+            .model
+            .clone()
+            .unwrap_or_else(|| default_model.to_string()),
+        conversation.messages(),
+    );
+
+    if let Some(message) = request.messages.last_mut() {
+        for path in &globals.attachments {
+            let attachment =
+                Attachment::from_path(path).map_err(|e| Error::Attachment(path.clone(), e))?;
+            message.attachments.push(attachment);
+        }
+    }
+
+    request.system = globals.system.clone().or_else(|| conversation.system());
+    request.max_tokens = globals.max_tokens;
+    request.min_tokens = globals.min_tokens;
+    request.temperature = globals.temperature;
+    request.top_p = globals.top_p;
+    request.top_k = globals.top_k;
+    request.suffix = globals.suffix.clone();
+
+    if !tools.is_empty() {
+        request.tools = Some(tools.iter().map(|tool| tool.definition()).collect());
+    }
+
+    let schema = globals
+        .json_schema
+        .as_deref()
+        .map(crate::schema::Schema::load)
+        .transpose()?;
+    request.response_schema = schema.as_ref().map(|schema| schema.value.clone());
+
+    log::info!("request: {:#?}", request);
+
+    let quiet = globals.quiet.unwrap_or(false);
+    let mut usage = TokenUsage::default();
+    let mut schema_retries = 0;
+
+    for round in 0..MAX_TOOL_ROUNDS {
+        let output = match provider.stream(&request) {
+            Ok(stream) => {
+                let language = globals.language.clone();
+                handle_stream(stream, quiet, schema.is_some(), language, cancel).await
+            }
+            Err(e) => Err(Error::NoResponse(Box::new(e.into()))),
+        };
+        let mut output = match output {
+            // Only the first request can be sent to another model, the later ones continue the
+            // answer of this one.
+            Err(Error::NoResponse(e)) if round > 0 => return Err(*e),
+            output => output?,
+        };
+
+        add_usage(&mut usage, output.usage);
+
+        if output.truncated {
+            output.usage = usage;
+            return Ok(output);
+        }
+
+        if output.tool_calls.is_empty() {
+            let Some(schema) = &schema else {
+                output.usage = usage;
+                return Ok(output);
+            };
+
+            match schema.validate(&output.content) {
+                Ok(value) => {
+                    output.content = serde_json::to_string_pretty(&value)?;
+                    output.usage = usage;
+                    print_json(&output.content)?;
+                    return Ok(output);
+                }
+                Err(errors) if schema_retries < MAX_SCHEMA_RETRIES => {
+                    log::warn!("the response doesn't match the json schema:\n{}", errors);
+                    schema_retries += 1;
+                    request
+                        .messages
+                        .push(Message::new(Role::Assistant, output.content));
+                    request.messages.push(Message::new(
+                        Role::User,
+                        format!(
+                            "The response doesn't match the JSON schema:\n{errors}\n\nRespond again with only the corrected JSON."
+                        ),
+                    ));
+                    continue;
+                }
+                Err(errors) => return Err(Error::SchemaValidation(errors)),
+            }
+        }
+
+        println!();
+
+        let results = output
+            .tool_calls
+            .iter()
+            .map(|call| crate::tools::execute(tools, call))
+            .collect::<Result<Vec<_>>>()?;
+
+        let mut message = Message::new(Role::Assistant, output.content);
+        message.tool_calls = output.tool_calls;
+        request.messages.push(message);
+        request.messages.push(Message::tool_results(results));
+    }
+
+    Err(Error::ToolRounds(MAX_TOOL_ROUNDS))
+}
+
+/// Adds the token counters of `usage` to `total`.
+fn add_usage(total: &mut TokenUsage, usage: TokenUsage) {
+    if let Some(input_tokens) = usage.input_tokens {
+        total.input_tokens = Some(total.input_tokens.unwrap_or_default() + input_tokens);
+    }
+    if let Some(output_tokens) = usage.output_tokens {
+        total.output_tokens = Some(total.output_tokens.unwrap_or_default() + output_tokens);
+    }
+}
+
+/// Stops the spinner, if any, and cleans it from the terminal.
+fn stop_spinner(sp: Option<spinners::Spinner>) -> Result<()> {
+    if let Some(mut sp) = sp {
+        // TODO: Find a better way to clean the spinner from the terminal.
+        sp.stop();
+        std::io::stdout().flush()?;
+        crossterm::execute!(std::io::stdout(), crossterm::cursor::MoveToColumn(0))?;
+        print!("                      ");
+        crossterm::execute!(std::io::stdout(), crossterm::cursor::MoveToColumn(0))?;
+    }
+
+    Ok(())
+}
+
+/// Prints a validated JSON response, highlighting it when `stdout` is a terminal.
+fn print_json(content: &str) -> Result<()> {
+    if atty::is(atty::Stream::Stdout) {
+        print!(
+            "{}",
+            crate::printer::CustomPrinter::new("json")?
+                .input_from_bytes(content.as_bytes())
+                .print()?
+        );
+    } else {
+        print!("{content}");
+    }
+    std::io::stdout().flush()?;
+
+    Ok(())
+}
+
+/// Consumes the stream, printing the text as it arrives unless `buffer` is set.
+///
+/// If `cancel` is cancelled the stream is dropped, which closes the connection, and whatever text
+/// arrived is kept on the output. Buffered text is printed as is since it can't be validated.
 pub async fn handle_stream(
-    mut stream: impl Stream<Item = std::result::Result<String, es_stream::error::Error>>
+    mut stream: impl Stream<Item = std::result::Result<StreamEvent, es_stream::error::Error>>
         + std::marker::Unpin,
     quiet: bool,
+    buffer: bool,
     language: String,
-) -> Result<()> {
+    cancel: &CancellationToken,
+) -> Result<StreamOutput> {
+    let mut output = StreamOutput::default();
     let mut previous_output = String::new();
     let mut accumulated_content_bytes: Vec<u8> = Vec::new();
 
@@ -28,16 +346,59 @@ pub async fn handle_stream(
         None
     };
 
-    while let Ok(Some(text)) = stream.try_next().await {
-        if is_terminal && sp.is_some() {
-            // TODO: Find a better way to clean the spinner from the terminal.
-            sp.take().unwrap().stop();
-            std::io::stdout().flush()?;
-            crossterm::execute!(std::io::stdout(), crossterm::cursor::MoveToColumn(0))?;
-            print!("                      ");
-            crossterm::execute!(std::io::stdout(), crossterm::cursor::MoveToColumn(0))?;
+    loop {
+        let next = tokio::select! {
+            biased;
+            _ = cancel.cancelled() => {
+                output.truncated = true;
+                break;
+            }
+            next = stream.try_next() => next,
+        };
+
+        let event = match next {
+            Ok(Some(event)) => event,
+            Ok(None) => break,
+            Err(e) => {
+                stop_spinner(sp.take())?;
+                if output.content.is_empty() && output.tool_calls.is_empty() {
+                    return Err(Error::NoResponse(Box::new(e.into())));
+                }
+                return Err(e.into());
+            }
+        };
+
+        let text = match event {
+            StreamEvent::TextDelta(text) => text,
+            StreamEvent::MessageStart { id, model } => {
+                log::info!("message_start: id={:?} model={:?}", id, model);
+                output.model = model;
+                continue;
+            }
+            StreamEvent::ToolCall(tool_call) => {
+                log::info!("tool_call: {:?}", tool_call);
+                output.tool_calls.push(tool_call);
+                continue;
+            }
+            StreamEvent::StopReason(stop_reason) => {
+                output.stop_reason = Some(stop_reason);
+                continue;
+            }
+            StreamEvent::Usage(usage) => {
+                output.usage.merge(usage);
+                continue;
+            }
+            StreamEvent::Done => break,
+        };
+
+        output.content.push_str(&text);
+
+        if buffer {
+            continue;
         }
 
+        stop_spinner(sp.take())?;
+
         if !is_terminal {
             // If not a terminal, print each instance of `text` directly to `stdout`
             print!("{}", text);
@@ -47,11 +408,11 @@ pub async fn handle_stream(
 
         accumulated_content_bytes.extend_from_slice(text.as_bytes());
 
-        let output = crate::printer::CustomPrinter::new(&language)?
+        let highlighted = crate::printer::CustomPrinter::new(&language)?
             .input_from_bytes(&accumulated_content_bytes)
             .print()?;
 
-        let unprinted_lines = output
+        let unprinted_lines = highlighted
             .lines()
             .skip(if previous_output.lines().count() == 0 {
                 0
@@ -66,10 +427,28 @@ pub async fn handle_stream(
         std::io::stdout().flush()?;
 
         // Update the previous output
-        previous_output = output;
+        previous_output = highlighted;
     }
 
-    Ok(())
+    stop_spinner(sp.take())?;
+
+    if output.truncated {
+        if buffer {
+            print!("{}", output.content);
+        }
+        std::io::stdout().flush()?;
+        cancel::restore_terminal();
+        println!();
+    }
+
+    log::info!(
+        "model: {:?}, stop_reason: {:?}, usage: {:?}",
+        output.model,
+        output.stop_reason,
+        output.usage
+    );
+
+    Ok(output)
 }
 
 // Merges two JSON objects defined as `serde_json::Value`.
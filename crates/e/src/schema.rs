@@ -0,0 +1,53 @@
+use jsonschema::JSONSchema;
+use serde_json::Value;
+
+use crate::prelude::*;
+
+/// JSON schema the response of the model must follow.
+pub struct Schema {
+    /// The raw schema, sent to the provider.
+    pub value: Value,
+    compiled: JSONSchema,
+}
+
+impl Schema {
+    /// Loads and compiles the JSON schema stored on `path`.
+    pub fn load(path: &str) -> Result<Self> {
+        let value: Value = serde_json::from_str(&std::fs::read_to_string(path)?)?;
+        let compiled = JSONSchema::compile(&value).map_err(|e| Error::JsonSchema(e.to_string()))?;
+
+        Ok(Self { value, compiled })
+    }
+
+    /// Parses the response of the model and validates it against the schema. On failure, the
+    /// returned message lists every problem found so it can be sent back to the model.
+    pub fn validate(&self, content: &str) -> std::result::Result<Value, String> {
+        let instance: Value = serde_json::from_str(strip_code_fence(content))
+            .map_err(|e| format!("The response is not valid JSON: {e}"))?;
+
+        if let Err(errors) = self.compiled.validate(&instance) {
+            return Err(errors
+                .map(|e| match e.instance_path.to_string() {
+                    path if path.is_empty() => format!("- /: {e}"),
+                    path => format!("- {path}: {e}"),
+                })
+                .collect::<Vec<_>>()
+                .join("\n"));
+        }
+
+        Ok(instance)
+    }
+}
+
+/// Some models wrap the JSON response in a markdown code block even when asked not to.
+fn strip_code_fence(content: &str) -> &str {
+    let content = content.trim();
+
+    match content.strip_prefix("```") {
+        Some(fenced) => fenced
+            .trim_start_matches("json")
+            .trim_end_matches("```")
+            .trim(),
+        None => content,
+    }
+}
@@ -0,0 +1,183 @@
+use serde_json::Value;
+use std::collections::HashMap;
+use std::path::Path;
+use std::process::Command;
+
+/// Registers the functions templates use to pull their own context:
+///
+/// - `file(path)`: the content of a file.
+/// - `glob(pattern)`: the content of every file matching the pattern, each one after a header
+///   with its path.
+/// - `git_diff(rev, staged)`: the output of `git diff`, against `rev` if set, or of the staged
+///   changes if `staged` is `true`.
+/// - `cmd(command)`: the output of a command. Only the commands starting with one of the
+///   `allowed_commands` can run.
+///
+/// Relative paths are resolved from the current directory.
+pub fn register(tera: &mut tera::Tera, allowed_commands: Vec<String>) {
+    tera.register_function("file", file);
+    tera.register_function("glob", glob);
+    tera.register_function("git_diff", git_diff);
+    tera.register_function("cmd", move |args: &HashMap<String, Value>| {
+        cmd(args, &allowed_commands)
+    });
+}
+
+/// Registers functions with the same names and arguments that return an empty string instead of
+/// reading files or running commands, so templates can be rendered without side effects.
+pub fn register_stubs(tera: &mut tera::Tera) {
+    tera.register_function("file", |args: &HashMap<String, Value>| {
+        stub("file", args, "path")
+    });
+    tera.register_function("glob", |args: &HashMap<String, Value>| {
+        stub("glob", args, "pattern")
+    });
+    tera.register_function("git_diff", |_: &HashMap<String, Value>| {
+        Ok(Value::String(String::new()))
+    });
+    tera.register_function("cmd", |args: &HashMap<String, Value>| {
+        stub("cmd", args, "command")
+    });
+}
+
+fn stub(function: &str, args: &HashMap<String, Value>, name: &str) -> tera::Result<Value> {
+    string_arg(function, args, name)?;
+    Ok(Value::String(String::new()))
+}
+
+fn file(args: &HashMap<String, Value>) -> tera::Result<Value> {
+    let path = string_arg("file", args, "path")?;
+
+    std::fs::read_to_string(path)
+        .map(Value::String)
+        .map_err(|e| format!("file: unable to read {path}: {e}").into())
+}
+
+fn glob(args: &HashMap<String, Value>) -> tera::Result<Value> {
+    let pattern = string_arg("glob", args, "pattern")?;
+    let paths = glob::glob(pattern).map_err(|e| format!("glob: invalid pattern {pattern}: {e}"))?;
+
+    let mut output = String::new();
+    for path in paths {
+        let path = path.map_err(|e| format!("glob: {e}"))?;
+        if !path.is_file() {
+            continue;
+        }
+
+        // Binary files can't be added to the prompt.
+        let Ok(content) = std::fs::read_to_string(&path) else {
+            log::warn!("glob: skipping non utf-8 file {:?}", path);
+            continue;
+        };
+
+        output.push_str(&fence(&path, &content));
+    }
+
+    Ok(Value::String(output))
+}
+
+fn git_diff(args: &HashMap<String, Value>) -> tera::Result<Value> {
+    let mut command = vec!["git".to_string(), "diff".to_string()];
+
+    if args
+        .get("staged")
+        .and_then(Value::as_bool)
+        .unwrap_or_default()
+    {
+        command.push("--staged".into());
+    }
+    if let Some(rev) = args.get("rev") {
+        let rev = rev.as_str().ok_or("git_diff: `rev` must be a string")?;
+        // Keep `rev` from being taken as an option, like `--output`.
+        if rev.starts_with('-') {
+            return Err(format!("git_diff: invalid revision {rev}").into());
+        }
+        command.push(rev.into());
+    }
+
+    run("git_diff", &command)
+}
+
+fn cmd(args: &HashMap<String, Value>, allowed_commands: &[String]) -> tera::Result<Value> {
+    let command = string_arg("cmd", args, "command")?;
+    let words =
+        shell_words::split(command).map_err(|e| format!("cmd: invalid command {command}: {e}"))?;
+
+    if words.is_empty() {
+        return Err("cmd: empty command".into());
+    }
+    if !is_allowed(&words, allowed_commands) {
+        return Err(format!(
+            "cmd: `{command}` is not allowed, add it to the `template_commands` setting"
+        )
+        .into());
+    }
+
+    run("cmd", &words)
+}
+
+/// Checks if the words of the command start with the words of one of the allowed commands, and
+/// the words after them aren't options, so allowing `git log` allows `git log main` but not
+/// `git push` or `git log --output=notes.txt`.
+fn is_allowed(words: &[String], allowed_commands: &[String]) -> bool {
+    allowed_commands.iter().any(|allowed| {
+        shell_words::split(allowed).is_ok_and(|allowed| {
+            !allowed.is_empty()
+                && words.starts_with(allowed.as_slice())
+                && !words[allowed.len()..]
+                    .iter()
+                    .any(|word| word.starts_with('-'))
+        })
+    })
+}
+
+/// Runs the command without a shell and returns its output.
+fn run(function: &str, words: &[String]) -> tera::Result<Value> {
+    log::info!("{}: running {:?}", function, words);
+
+    let output = Command::new(&words[0])
+        .args(&words[1..])
+        .output()
+        .map_err(|e| format!("{function}: unable to run {}: {e}", words[0]))?;
+
+    if !output.status.success() {
+        return Err(format!(
+            "{function}: `{}` failed with {}\n{}",
+            words.join(" "),
+            output.status,
+            String::from_utf8_lossy(&output.stderr)
+        )
+        .into());
+    }
+
+    Ok(Value::String(
+        String::from_utf8_lossy(&output.stdout).to_string(),
+    ))
+}
+
+fn string_arg<'a>(
+    function: &str,
+    args: &'a HashMap<String, Value>,
+    name: &str,
+) -> tera::Result<&'a str> {
+    args.get(name)
+        .and_then(Value::as_str)
+        .ok_or_else(|| format!("{function}: missing `{name}` string argument").into())
+}
+
+/// Writes the content of a file on a code block, after a header with its path.
+fn fence(path: &Path, content: &str) -> String {
+    let language = path
+        .extension()
+        .map(|ext| ext.to_string_lossy())
+        .unwrap_or_default();
+    let newline = if content.ends_with('\n') { "" } else { "\n" };
+
+    format!(
+        "{}:\n```{}\n{}{}```\n\n",
+        path.display(),
+        language,
+        content,
+        newline
+    )
+}
@@ -0,0 +1,299 @@
+use serde::Deserialize;
+use serde_json::Value;
+use std::path::{Path, PathBuf};
+
+use crate::args::TemplatesCommand;
+use crate::config::Template;
+use crate::prelude::*;
+
+pub const DEFAULT_TEMPLATES_DIR: &str = "~/.config/e/templates";
+
+// Extension of the template files.
+const EXTENSION: &str = "tera";
+
+// Delimiter of the TOML front-matter at the top of the template files.
+const FRONT_MATTER: &str = "+++";
+
+/// Settings at the top of a template file, between `+++` lines.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FrontMatter {
+    name: Option<String>,
+    description: Option<String>,
+    default_vars: Option<Value>,
+    system: Option<String>,
+}
+
+/// A template loaded on the library.
+#[derive(Debug)]
+pub struct Entry {
+    pub template: Template,
+    /// Name of the template on Tera, used by `include`, `import` and `extends`: the path relative
+    /// to the templates directory, or the name of the templates set on the config file.
+    pub key: String,
+    /// File the template was loaded from.
+    pub path: Option<PathBuf>,
+}
+
+/// The prompt and system message rendered from a template.
+#[derive(Debug)]
+pub struct Rendered {
+    pub system: Option<String>,
+    pub prompt: String,
+}
+
+/// Templates from the `templates` setting of the config file and from the `.tera` files of the
+/// templates directory.
+///
+/// Every template is loaded on the same Tera instance, so they can `include` each other and
+/// `import` macros. Files whose name starts with `_` (e.g. `_macros.tera`) are only available to
+/// other templates and are not listed.
+#[derive(Debug, Default)]
+pub struct Library {
+    pub entries: Vec<Entry>,
+    tera: tera::Tera,
+}
+
+impl Library {
+    /// Loads the templates set on the config file and the ones stored on `dir`. A missing
+    /// directory is ignored. The `cmd` function only runs the `allowed_commands`.
+    pub fn load(
+        templates: Vec<Template>,
+        dir: Option<&Path>,
+        allowed_commands: Vec<String>,
+    ) -> Result<Self> {
+        let mut entries: Vec<Entry> = templates
+            .into_iter()
+            .map(|template| Entry {
+                key: template.name.clone(),
+                template,
+                path: None,
+            })
+            .collect();
+        let mut partials = Vec::new();
+
+        if let Some(dir) = dir.filter(|dir| dir.is_dir()) {
+            for path in files(dir)? {
+                let key = path
+                    .strip_prefix(dir)
+                    .unwrap_or(&path)
+                    .to_string_lossy()
+                    .replace(std::path::MAIN_SEPARATOR, "/");
+                let content = std::fs::read_to_string(&path)?;
+
+                let is_partial = path
+                    .file_name()
+                    .is_some_and(|name| name.to_string_lossy().starts_with('_'));
+                if is_partial {
+                    partials.push((key, content));
+                    continue;
+                }
+
+                let (front_matter, body) = front_matter(&path, &content)?;
+                let name = front_matter
+                    .name
+                    .unwrap_or_else(|| key.trim_end_matches(&format!(".{EXTENSION}")).into());
+
+                entries.push(Entry {
+                    template: Template {
+                        name,
+                        description: front_matter.description,
+                        template: body.to_string(),
+                        default_vars: front_matter.default_vars,
+                        system: front_matter.system,
+                    },
+                    key,
+                    path: Some(path),
+                });
+            }
+        }
+
+        let mut tera = tera::Tera::default();
+        let sources = entries
+            .iter()
+            .flat_map(|entry| {
+                let system = entry
+                    .template
+                    .system
+                    .as_ref()
+                    .map(|system| (system_key(&entry.key), system.clone()));
+                std::iter::once((entry.key.clone(), entry.template.template.clone())).chain(system)
+            })
+            .chain(partials);
+        tera.add_raw_templates(sources)?;
+        crate::template_functions::register(&mut tera, allowed_commands);
+
+        Ok(Self { entries, tera })
+    }
+
+    /// Replaces the template functions with stubs that don't read files or run commands.
+    pub fn stub_functions(&mut self) {
+        crate::template_functions::register_stubs(&mut self.tera);
+    }
+
+    /// Finds the template called `name`.
+    pub fn get(&self, name: &str) -> Result<&Entry> {
+        self.entries
+            .iter()
+            .find(|entry| entry.template.name == name)
+            .ok_or_else(|| Error::TemplateNotFound(name.to_string()))
+    }
+
+    /// Renders the template. The default variables of the template override the `builtin`
+    /// ones, and `vars` override both.
+    pub fn render(&self, entry: &Entry, mut builtin: Value, vars: Value) -> Result<Rendered> {
+        let mut default_vars = entry.template.default_vars.clone().unwrap_or_default();
+        merge(&mut default_vars, vars);
+        merge(&mut builtin, default_vars);
+        let context = tera::Context::from_value(builtin)?;
+        log::info!("context: {:#?}", context);
+
+        let system = match entry.template.system {
+            Some(_) => Some(self.tera.render(&system_key(&entry.key), &context)?),
+            None => None,
+        };
+
+        Ok(Rendered {
+            system,
+            prompt: self.tera.render(&entry.key, &context)?,
+        })
+    }
+}
+
+/// Variables available to every template.
+pub fn builtin_vars(
+    prompt: &str,
+    system: &str,
+    stdin: &str,
+    suffix: &str,
+    language: &str,
+) -> Value {
+    serde_json::json!({
+        "prompt": prompt,
+        "system": system,
+        "stdin": stdin,
+        "suffix": suffix,
+        "language": language,
+    })
+}
+
+/// Runs the `templates` subcommands.
+pub fn run(mut library: Library, command: &TemplatesCommand) -> Result<()> {
+    match command {
+        TemplatesCommand::List => {
+            let width = library
+                .entries
+                .iter()
+                .map(|entry| entry.template.name.len())
+                .max()
+                .unwrap_or_default();
+            for entry in &library.entries {
+                let description = entry.template.description.as_deref().unwrap_or_default();
+                println!("{:width$}  {}", entry.template.name, description);
+            }
+        }
+        TemplatesCommand::Show { name } => show(library.get(name)?)?,
+        TemplatesCommand::Validate => {
+            // Validating a template must not read files or run the commands it uses.
+            library.stub_functions();
+            let mut failed = 0;
+            for entry in &library.entries {
+                let builtin = builtin_vars("", "", "", "", "markdown");
+                match library.render(entry, builtin, serde_json::json!({})) {
+                    Ok(_) => println!("ok     {}", entry.template.name),
+                    Err(e) => {
+                        failed += 1;
+                        println!("error  {}: {:?}", entry.template.name, e);
+                    }
+                }
+            }
+            if failed > 0 {
+                return Err(Error::InvalidTemplates(failed));
+            }
+        }
+        TemplatesCommand::Render { name, prompt, vars } => {
+            let builtin = builtin_vars(prompt, "", "", "", "markdown");
+            let rendered = library.render(library.get(name)?, builtin, vars.clone())?;
+            print_rendered(rendered.system.as_deref(), &rendered.prompt);
+        }
+    }
+
+    Ok(())
+}
+
+/// Prints a rendered prompt, and its system message if any.
+pub fn print_rendered(system: Option<&str>, prompt: &str) {
+    if let Some(system) = system {
+        println!("System Prompt:\n{}\n---\n", system);
+    }
+    println!("Prompt:\n{}", prompt);
+}
+
+fn show(entry: &Entry) -> Result<()> {
+    let template = &entry.template;
+
+    println!("name: {}", template.name);
+    if let Some(description) = &template.description {
+        println!("description: {}", description);
+    }
+    match &entry.path {
+        Some(path) => println!("path: {}", path.display()),
+        None => println!("path: (config file)"),
+    }
+    if let Some(default_vars) = &template.default_vars {
+        println!("default_vars: {}", serde_json::to_string(default_vars)?);
+    }
+    if let Some(system) = &template.system {
+        println!("system:\n{}", system);
+    }
+    println!("---\n{}", template.template);
+
+    Ok(())
+}
+
+fn system_key(key: &str) -> String {
+    format!("{key}#system")
+}
+
+/// Splits the TOML front-matter from the body of the template. Files without front-matter only
+/// have a body.
+fn front_matter<'a>(path: &Path, content: &'a str) -> Result<(FrontMatter, &'a str)> {
+    let invalid = |message: String| Error::FrontMatter(path.to_path_buf(), message);
+
+    let Some(rest) = content
+        .strip_prefix(FRONT_MATTER)
+        .and_then(|rest| rest.strip_prefix("\r\n").or(rest.strip_prefix('\n')))
+    else {
+        return Ok((FrontMatter::default(), content));
+    };
+
+    let Some(end) = rest.find(&format!("\n{FRONT_MATTER}")) else {
+        return Err(invalid(format!("missing closing `{FRONT_MATTER}`")));
+    };
+    let front_matter = toml::from_str(&rest[..end]).map_err(|e| invalid(e.to_string()))?;
+
+    let body = &rest[end + 1 + FRONT_MATTER.len()..];
+    let body = body
+        .strip_prefix("\r\n")
+        .or(body.strip_prefix('\n'))
+        .unwrap_or(body);
+
+    Ok((front_matter, body))
+}
+
+/// Lists the template files under `dir`, sorted by path.
+fn files(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut paths = Vec::new();
+
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            paths.extend(files(&path)?);
+        } else if path.extension().is_some_and(|ext| ext == EXTENSION) {
+            paths.push(path);
+        }
+    }
+
+    paths.sort();
+    Ok(paths)
+}
@@ -0,0 +1,158 @@
+use es_stream::tool::{self, ToolCall, ToolResult};
+use serde_json::Value;
+use std::io::{BufRead, Write};
+use std::process::{Command, Stdio};
+
+use crate::config::{Builtin, Tool};
+use crate::prelude::*;
+
+/// Picks the tools named on `names` from the configured tools.
+pub fn select(tools: Vec<Tool>, names: &[String]) -> Result<Vec<Tool>> {
+    names
+        .iter()
+        .map(|name| {
+            tools
+                .iter()
+                .find(|tool| tool.name == *name)
+                .cloned()
+                .ok_or_else(|| Error::ToolNotFound(name.clone()))
+        })
+        .collect()
+}
+
+impl Tool {
+    /// Tool definition sent to the model.
+    pub fn definition(&self) -> tool::Tool {
+        let parameters = match (&self.parameters, self.builtin) {
+            (Some(parameters), _) => parameters.clone(),
+            (None, Some(Builtin::ReadFile | Builtin::ListFiles)) => serde_json::json!({
+                "type": "object",
+                "properties": {
+                    "path": { "type": "string" }
+                },
+                "required": ["path"]
+            }),
+            (None, None) => serde_json::json!({ "type": "object", "properties": {} }),
+        };
+
+        tool::Tool::new(self.name.clone(), self.description.clone(), parameters)
+    }
+}
+
+/// Runs the tool requested by the model, asking the user for confirmation unless the tool is
+/// marked as safe. Failures are reported back to the model instead of stopping `e`.
+pub fn execute(tools: &[Tool], call: &ToolCall) -> Result<ToolResult> {
+    let result = |content: String, is_error: bool| ToolResult {
+        tool_call_id: call.id.clone(),
+        name: call.name.clone(),
+        content,
+        is_error,
+    };
+
+    let Some(tool) = tools.iter().find(|tool| tool.name == call.name) else {
+        return Ok(result(format!("Unknown tool: {}", call.name), true));
+    };
+
+    if !tool.safe && !confirm(call)? {
+        return Ok(result(
+            "The user declined to run the tool.".to_string(),
+            true,
+        ));
+    }
+
+    log::info!("running tool: {:?}", call);
+
+    let output = match (&tool.command, tool.builtin) {
+        (Some(command), _) => run_command(command, &call.arguments),
+        (None, Some(builtin)) => run_builtin(builtin, &call.arguments),
+        (None, None) => Err(format!("Tool {} has no command", tool.name)),
+    };
+
+    Ok(match output {
+        Ok(content) => result(content, false),
+        Err(content) => result(content, true),
+    })
+}
+
+/// Asks the user to confirm the tool call on the terminal. `stdin` may hold the prompt, so the
+/// answer is read from `/dev/tty`.
+fn confirm(call: &ToolCall) -> Result<bool> {
+    let Ok(tty) = std::fs::File::open("/dev/tty") else {
+        log::warn!(
+            "unable to ask for confirmation, skipping tool: {}",
+            call.name
+        );
+        return Ok(false);
+    };
+
+    eprint!(
+        "\nRun tool `{}` with arguments {}? [y/N] ",
+        call.name, call.arguments
+    );
+    std::io::stderr().flush()?;
+
+    let mut answer = String::new();
+    std::io::BufReader::new(tty).read_line(&mut answer)?;
+
+    Ok(matches!(answer.trim(), "y" | "Y" | "yes"))
+}
+
+fn run_command(command: &str, arguments: &Value) -> std::result::Result<String, String> {
+    let mut cmd = Command::new("sh");
+    cmd.arg("-c")
+        .arg(command)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+
+    if let Value::Object(arguments) = arguments {
+        for (key, value) in arguments {
+            match value {
+                Value::String(value) => cmd.env(key, value),
+                value => cmd.env(key, value.to_string()),
+            };
+        }
+    }
+
+    let mut child = cmd.spawn().map_err(|e| e.to_string())?;
+
+    if let Some(mut stdin) = child.stdin.take() {
+        // Commands that don't read `stdin` may exit before the arguments are written.
+        if let Err(e) = stdin.write_all(arguments.to_string().as_bytes()) {
+            log::debug!("unable to write the tool arguments to stdin: {}", e);
+        }
+    }
+
+    let output = child.wait_with_output().map_err(|e| e.to_string())?;
+    let stdout = String::from_utf8_lossy(&output.stdout).to_string();
+
+    if output.status.success() {
+        Ok(stdout)
+    } else {
+        Err(format!(
+            "{}\n{}\n{}",
+            output.status,
+            stdout,
+            String::from_utf8_lossy(&output.stderr)
+        ))
+    }
+}
+
+fn run_builtin(builtin: Builtin, arguments: &Value) -> std::result::Result<String, String> {
+    let path = arguments["path"]
+        .as_str()
+        .ok_or_else(|| "Missing `path` argument".to_string())?;
+
+    match builtin {
+        Builtin::ReadFile => std::fs::read_to_string(path).map_err(|e| e.to_string()),
+        Builtin::ListFiles => {
+            let mut entries = std::fs::read_dir(path)
+                .map_err(|e| e.to_string())?
+                .filter_map(|entry| entry.ok())
+                .map(|entry| entry.file_name().to_string_lossy().to_string())
+                .collect::<Vec<_>>();
+            entries.sort();
+            Ok(entries.join("\n"))
+        }
+    }
+}
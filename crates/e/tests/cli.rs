@@ -0,0 +1,426 @@
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+
+use mock_provider::{fixtures, MockServer, Response};
+
+/// Empty home directory, so the tests don't read the user configuration.
+fn home(name: &str) -> PathBuf {
+    let home = std::env::temp_dir().join(format!("e-cli-{}-{name}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&home);
+    std::fs::create_dir_all(&home).unwrap();
+    home
+}
+
+fn e(name: &str, server: &MockServer, args: &[&str]) -> Output {
+    e_at(&home(name), server, args)
+}
+
+/// Runs `e` with a home directory prepared by the test.
+fn e_at(home: &Path, server: &MockServer, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_e"))
+        .args(args)
+        .args(["--api-base-url", &server.url(), "--api-key", "test-key"])
+        .args(["--quiet", "true"])
+        .env("HOME", home)
+        .env_remove("RUST_LOG")
+        .stdin(Stdio::null())
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn openai() {
+    let server = MockServer::start();
+    server.mock("/chat/completions", Response::sse(fixtures::OPENAI));
+
+    let output = e("openai", &server, &["--api", "openai", "Hi"]);
+
+    assert!(output.status.success(), "{output:?}");
+    assert!(String::from_utf8_lossy(&output.stdout).contains("Hello world"));
+
+    let request = server.requests()[0].json();
+    assert_eq!(request["messages"][0]["content"], "Hi");
+}
+
+#[test]
+fn anthropic() {
+    let server = MockServer::start();
+    server.mock("/messages", Response::sse(fixtures::ANTHROPIC));
+
+    let output = e("anthropic", &server, &["--api", "anthropic", "Hi"]);
+
+    assert!(output.status.success(), "{output:?}");
+    assert!(String::from_utf8_lossy(&output.stdout).contains("Hello world"));
+}
+
+#[test]
+fn google() {
+    let server = MockServer::start();
+    server.mock(":streamGenerateContent", Response::sse(fixtures::GOOGLE));
+
+    let output = e("google", &server, &["--api", "google", "Hi"]);
+
+    assert!(output.status.success(), "{output:?}");
+    assert!(String::from_utf8_lossy(&output.stdout).contains("Hello world"));
+    assert_eq!(
+        server.requests()[0].header("x-goog-api-key"),
+        Some("test-key")
+    );
+}
+
+#[test]
+fn api_errors_fail() {
+    let server = MockServer::start();
+    server.mock(
+        "/chat/completions",
+        Response::json(401, r#"{"error":{"message":"Incorrect API key provided"}}"#),
+    );
+
+    let output = e("error", &server, &["--api", "openai", "Hi"]);
+
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Incorrect API key provided"));
+}
+
+#[test]
+fn record_and_replay() {
+    let cassette = home("cassette").join("cassette");
+    let cassette = cassette.to_str().unwrap();
+
+    let server = MockServer::start();
+    server.mock("/chat/completions", Response::sse(fixtures::OPENAI));
+
+    let output = e(
+        "record",
+        &server,
+        &["--api", "openai", "--record", cassette, "Hi"],
+    );
+    assert!(output.status.success(), "{output:?}");
+    drop(server);
+
+    let output = Command::new(env!("CARGO_BIN_EXE_e"))
+        .args([
+            "--api", "openai", "--replay", cassette, "--quiet", "true", "Hi",
+        ])
+        .env("HOME", home("replay"))
+        .env_remove("OPENAI_API_KEY")
+        .stdin(Stdio::null())
+        .output()
+        .unwrap();
+
+    assert!(output.status.success(), "{output:?}");
+    assert!(String::from_utf8_lossy(&output.stdout).contains("Hello world"));
+}
+
+#[test]
+fn template_functions() {
+    let home = home("template-functions");
+    let templates = home.join(".config/e/templates");
+    std::fs::create_dir_all(&templates).unwrap();
+    std::fs::write(
+        home.join(".config/e.toml"),
+        r#"template_commands = ["echo", "touch"]"#,
+    )
+    .unwrap();
+    std::fs::write(home.join("notes.txt"), "some notes").unwrap();
+    std::fs::write(
+        templates.join("context.tera"),
+        r#"{{ file(path="notes.txt") }} {{ cmd(command="echo 'from a command'") }}"#,
+    )
+    .unwrap();
+    std::fs::write(
+        templates.join("forbidden.tera"),
+        r#"{{ cmd(command="rm notes.txt") }}"#,
+    )
+    .unwrap();
+    std::fs::write(
+        templates.join("option.tera"),
+        r#"{{ cmd(command="echo --help") }}"#,
+    )
+    .unwrap();
+    std::fs::write(
+        templates.join("touch.tera"),
+        r#"{{ cmd(command="touch touched.txt") }}"#,
+    )
+    .unwrap();
+
+    let templates = |args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_e"))
+            .arg("templates")
+            .args(args)
+            .current_dir(&home)
+            .env("HOME", &home)
+            .stdin(Stdio::null())
+            .output()
+            .unwrap()
+    };
+
+    let output = templates(&["render", "context"]);
+    assert!(output.status.success(), "{output:?}");
+    assert!(String::from_utf8_lossy(&output.stdout).contains("some notes from a command"));
+
+    let output = templates(&["render", "forbidden"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("is not allowed"));
+    assert!(home.join("notes.txt").exists());
+
+    let output = templates(&["render", "option"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("is not allowed"));
+
+    let output = templates(&["validate"]);
+    assert!(output.status.success(), "{output:?}");
+    assert!(!home.join("touched.txt").exists());
+}
+
+#[test]
+fn layered_config() {
+    let home = home("layered-config");
+    let project = home.join("project");
+    std::fs::create_dir_all(project.join("src")).unwrap();
+    std::fs::create_dir_all(home.join(".config")).unwrap();
+    std::fs::write(
+        home.join(".config/e.toml"),
+        "model = \"user-model\"\ntemperature = 0.5\n\n[[presets]]\nname = \"fast\"\napi = \"openai\"\n",
+    )
+    .unwrap();
+    std::fs::write(
+        project.join(".e.toml"),
+        "key_command = \"echo project-key\"\nmodel = \"project-model\"\n\n[[presets]]\nname = \"repo\"\napi = \"mistral\"\nbase_url = \"http://127.0.0.1:1\"\n",
+    )
+    .unwrap();
+
+    let show = || {
+        let output = Command::new(env!("CARGO_BIN_EXE_e"))
+            .args(["config", "show", "--origin"])
+            .current_dir(project.join("src"))
+            .env("HOME", &home)
+            .env("E_TEMPERATURE", "0.1")
+            .stdin(Stdio::null())
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{output:?}");
+        String::from_utf8_lossy(&output.stdout).to_string()
+    };
+    let origin = |stdout: &str, key: &str| {
+        stdout
+            .lines()
+            .find(|line| line.starts_with(&format!("{key} = ")))
+            .and_then(|line| line.split_once("  # "))
+            .map(|(_, origin)| origin.split(' ').next().unwrap().to_string())
+    };
+
+    let stdout = show();
+    assert!(stdout.contains("model = \"project-model\""), "{stdout}");
+    assert_eq!(origin(&stdout, "model").as_deref(), Some("project"));
+    assert_eq!(origin(&stdout, "temperature").as_deref(), Some("env"));
+    assert_eq!(origin(&stdout, "presets.fast.api").as_deref(), Some("user"));
+    assert_eq!(
+        origin(&stdout, "presets.repo.api").as_deref(),
+        Some("project")
+    );
+    // Untrusted projects can't set how the api key is read or where it is sent.
+    assert_eq!(origin(&stdout, "key_command"), None, "{stdout}");
+    assert_eq!(origin(&stdout, "presets.repo.base_url"), None, "{stdout}");
+
+    std::fs::write(
+        home.join(".config/e.toml"),
+        format!("trusted_projects = [{:?}]\n", project.to_str().unwrap()),
+    )
+    .unwrap();
+    let stdout = show();
+    assert!(stdout.contains("key_command = \"***\""), "{stdout}");
+    assert_eq!(origin(&stdout, "key_command").as_deref(), Some("project"));
+    assert_eq!(
+        origin(&stdout, "presets.repo.base_url").as_deref(),
+        Some("project")
+    );
+}
+
+#[test]
+fn preset_extends_and_aliases() {
+    let home = home("presets");
+    std::fs::create_dir_all(home.join(".config")).unwrap();
+    std::fs::write(
+        home.join(".config/e.toml"),
+        r#"
+[aliases]
+fast = { api = "openai", model = "gpt-4o-mini" }
+
+[[presets]]
+name = "base"
+api = "anthropic"
+temperature = 0.5
+
+[[presets]]
+name = "child"
+extends = "base"
+model = "fast"
+"#,
+    )
+    .unwrap();
+
+    let server = MockServer::start();
+    server.mock("/chat/completions", Response::sse(fixtures::OPENAI));
+
+    let output = e_at(&home, &server, &["--preset", "child", "Hi"]);
+
+    assert!(output.status.success(), "{output:?}");
+    let request = server.requests()[0].json();
+    assert_eq!(request["model"], "gpt-4o-mini");
+    assert_eq!(request["temperature"], 0.5);
+}
+
+#[test]
+fn invalid_presets_fail() {
+    let home = home("invalid-presets");
+    std::fs::create_dir_all(home.join(".config")).unwrap();
+    std::fs::write(
+        home.join(".config/e.toml"),
+        r#"
+[[presets]]
+name = "a"
+extends = "b"
+
+[[presets]]
+name = "b"
+extends = "a"
+
+[[presets]]
+name = "orphan"
+extends = "missing"
+"#,
+    )
+    .unwrap();
+
+    let server = MockServer::start();
+    let output = e_at(&home, &server, &["--api", "openai", "Hi"]);
+
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("cycle on `extends`: a -> b -> a"),
+        "{stderr}"
+    );
+    assert!(stderr.contains(r#"unknown preset "missing""#), "{stderr}");
+    assert!(server.requests().is_empty());
+}
+
+#[test]
+fn missing_api_key_fails_early() {
+    let output = Command::new(env!("CARGO_BIN_EXE_e"))
+        .args(["--api", "openai", "Hi"])
+        .env("HOME", home("missing-key"))
+        .env_remove("OPENAI_API_KEY")
+        .stdin(Stdio::null())
+        .output()
+        .unwrap();
+
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("OPENAI_API_KEY"));
+}
+
+#[test]
+fn missing_api_key_falls_back() {
+    let server = MockServer::start();
+    server.mock("/messages", Response::sse(fixtures::ANTHROPIC));
+
+    let home = home("missing-key-fallback");
+    std::fs::create_dir_all(home.join(".config")).unwrap();
+    let config = |key: &str| {
+        let config = format!(
+            "fallbacks = [\"backup\"]\n\n[[presets]]\nname = \"backup\"\napi = \"anthropic\"\nbase_url = \"{}\"\n{key}",
+            server.url()
+        );
+        std::fs::write(home.join(".config/e.toml"), config).unwrap();
+    };
+    let e = || {
+        Command::new(env!("CARGO_BIN_EXE_e"))
+            .args(["--api", "openai", "--quiet", "true", "Hi"])
+            .env("HOME", &home)
+            .env_remove("OPENAI_API_KEY")
+            .env_remove("ANTHROPIC_API_KEY")
+            .env_remove("RUST_LOG")
+            .stdin(Stdio::null())
+            .output()
+            .unwrap()
+    };
+
+    config("key = \"fallback-key\"\n");
+    let output = e();
+    assert!(output.status.success(), "{output:?}");
+    assert!(String::from_utf8_lossy(&output.stdout).contains("Hello world"));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains(r#"falling back to the "backup" preset: "#),
+        "{stderr}"
+    );
+    assert!(stderr.contains("OPENAI_API_KEY"), "{stderr}");
+    assert_eq!(server.requests().len(), 1);
+
+    // Without a key on any of them, it fails before sending anything.
+    config("");
+    let output = e();
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("OPENAI_API_KEY"));
+    assert_eq!(server.requests().len(), 1);
+}
+
+#[test]
+fn fallbacks() {
+    let primary = MockServer::start();
+    primary.mock(
+        "/chat/completions",
+        Response::json(401, r#"{"error":{"message":"Incorrect API key provided"}}"#),
+    );
+    let fallback = MockServer::start();
+    fallback.mock("/messages", Response::sse(fixtures::ANTHROPIC));
+
+    let home = home("fallbacks");
+    std::fs::create_dir_all(home.join(".config")).unwrap();
+    std::fs::write(
+        home.join(".config/e.toml"),
+        format!(
+            r#"
+fallbacks = ["backup"]
+system = "You answer first"
+
+[[presets]]
+name = "backup"
+api = "anthropic"
+model = "claude-3-haiku-20240307"
+key = "fallback-key"
+base_url = "{}"
+system = "You answer when the others fail"
+"#,
+            fallback.url()
+        ),
+    )
+    .unwrap();
+
+    let output = e_at(
+        &home,
+        &primary,
+        &["--api", "openai", "--temperature", "0.5", "Hi"],
+    );
+
+    assert!(output.status.success(), "{output:?}");
+    assert!(String::from_utf8_lossy(&output.stdout).contains("Hello world"));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains(r#"falling back to the "backup" preset"#),
+        "{stderr}"
+    );
+    assert!(stderr.contains("answered by"), "{stderr}");
+
+    assert_eq!(primary.requests().len(), 1);
+    let requests = fallback.requests();
+    assert_eq!(requests[0].header("x-api-key"), Some("fallback-key"));
+    assert_eq!(requests[0].json()["model"], "claude-3-haiku-20240307");
+    // The preset settings replace the ones of the primary model, but not the flags.
+    assert_eq!(
+        requests[0].json()["system"],
+        "You answer when the others fail"
+    );
+    assert_eq!(requests[0].json()["temperature"], 0.5);
+}
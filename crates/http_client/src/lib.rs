@@ -0,0 +1,64 @@
+use std::time::Duration;
+
+use tokio_stream::{Stream, StreamExt};
+
+/// Line closing each certificate of a PEM bundle.
+const PEM_CERTIFICATE_END: &str = "-----END CERTIFICATE-----";
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("can't read {0}: {1}")]
+    Io(String, std::io::Error),
+    #[error("invalid certificates on {0}: {1}")]
+    Certificate(String, reqwest::Error),
+    #[error("no data received for {0:?}")]
+    ReadTimeout(Duration),
+}
+
+/// Creates a `reqwest` client builder configured from the environment. Proxies are read from
+/// `HTTPS_PROXY`, `HTTP_PROXY`, `ALL_PROXY` and `NO_PROXY` by `reqwest` itself, extra CA
+/// certificates from the `SSL_CERT_FILE` bundle, and the connect timeout (in seconds) from
+/// `HTTP_CONNECT_TIMEOUT`.
+///
+/// `timeout` limits the whole request, body included. `reqwest` 0.11 can't limit the wait between
+/// chunks of a stream, so streams are read with [`next`], which applies `HTTP_READ_TIMEOUT`.
+pub fn builder(timeout: Duration) -> Result<reqwest::ClientBuilder, Error> {
+    let mut builder = reqwest::Client::builder().timeout(timeout);
+
+    if let Some(timeout) = env_seconds("HTTP_CONNECT_TIMEOUT") {
+        builder = builder.connect_timeout(timeout);
+    }
+
+    if let Ok(path) = std::env::var("SSL_CERT_FILE") {
+        let bundle = std::fs::read_to_string(&path).map_err(|e| Error::Io(path.clone(), e))?;
+        for pem in bundle
+            .split_inclusive(PEM_CERTIFICATE_END)
+            .filter(|pem| pem.contains(PEM_CERTIFICATE_END))
+        {
+            let certificate = reqwest::Certificate::from_pem(pem.trim().as_bytes())
+                .map_err(|e| Error::Certificate(path.clone(), e))?;
+            builder = builder.add_root_certificate(certificate);
+        }
+    }
+
+    Ok(builder)
+}
+
+/// Reads the next item of a stream, failing when `HTTP_READ_TIMEOUT` seconds pass without one.
+pub async fn next<S: Stream + Unpin>(stream: &mut S) -> Option<Result<S::Item, Error>> {
+    match env_seconds("HTTP_READ_TIMEOUT") {
+        Some(timeout) => match tokio::time::timeout(timeout, stream.next()).await {
+            Ok(item) => item.map(Ok),
+            Err(_) => Some(Err(Error::ReadTimeout(timeout))),
+        },
+        None => stream.next().await.map(Ok),
+    }
+}
+
+/// Reads a number of seconds from an environment variable.
+fn env_seconds(name: &str) -> Option<Duration> {
+    std::env::var(name)
+        .ok()
+        .and_then(|secs| secs.parse::<f64>().ok())
+        .map(|secs| Duration::from_secs_f64(secs.max(0.0)))
+}
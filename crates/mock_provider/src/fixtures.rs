@@ -0,0 +1,22 @@
+//! Responses recorded from each provider. Every chat fixture answers `Hello world`.
+
+/// OpenAI Chat Completions stream, with usage on the last chunk.
+pub const OPENAI: &str = include_str!("../fixtures/openai.sse");
+/// OpenAI Chat Completion, without streaming.
+pub const OPENAI_COMPLETION: &str = include_str!("../fixtures/openai.json");
+/// Anthropic Messages stream.
+pub const ANTHROPIC: &str = include_str!("../fixtures/anthropic.sse");
+/// Anthropic legacy Text Completions stream.
+pub const ANTHROPIC_COMPLETE: &str = include_str!("../fixtures/anthropic_complete.sse");
+/// Anthropic legacy Text Completion, without streaming.
+pub const ANTHROPIC_COMPLETION: &str = include_str!("../fixtures/anthropic_complete.json");
+/// Gemini `streamGenerateContent` candidates.
+pub const GOOGLE: &str = include_str!("../fixtures/google.sse");
+/// Mistral Chat Completions stream.
+pub const MISTRAL: &str = include_str!("../fixtures/mistral.sse");
+/// Mistral FIM Completions stream.
+pub const MISTRAL_FIM: &str = include_str!("../fixtures/mistral_fim.sse");
+/// GitHub Copilot session token.
+pub const COPILOT_TOKEN: &str = include_str!("../fixtures/copilot_token.json");
+/// Ollama chat stream, as newline delimited JSON.
+pub const OLLAMA: &str = include_str!("../fixtures/ollama.ndjson");
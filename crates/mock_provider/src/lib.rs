@@ -0,0 +1,227 @@
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+
+pub mod fixtures;
+
+/// A request received by the server.
+#[derive(Debug, Clone, Default)]
+pub struct Request {
+    pub method: String,
+    /// Path of the request, including the query string.
+    pub path: String,
+    /// Headers of the request, with lowercase names.
+    pub headers: Vec<(String, String)>,
+    pub body: String,
+}
+
+impl Request {
+    /// Returns the value of the header `name`.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Parses the body as JSON.
+    pub fn json(&self) -> serde_json::Value {
+        serde_json::from_str(&self.body).unwrap_or_default()
+    }
+}
+
+/// A canned response.
+#[derive(Debug, Clone)]
+pub struct Response {
+    pub status: u16,
+    pub content_type: String,
+    pub body: String,
+}
+
+impl Response {
+    /// A server-sent events stream, usually one of the [`fixtures`].
+    pub fn sse(body: &str) -> Self {
+        Self {
+            status: 200,
+            content_type: "text/event-stream".to_string(),
+            body: body.to_string(),
+        }
+    }
+
+    /// A JSON response.
+    pub fn json(status: u16, body: &str) -> Self {
+        Self {
+            status,
+            content_type: "application/json".to_string(),
+            body: body.to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct State {
+    routes: Vec<(String, Response)>,
+    requests: Vec<Request>,
+}
+
+/// Local HTTP server that answers requests with canned responses.
+///
+/// Each response is registered for a path suffix with [`MockServer::mock`], so the same fixture
+/// works no matter the base url used by the client (e.g. `/v1/chat/completions` and
+/// `/chat/completions`.) Requests without a matching route get a `404`.
+#[derive(Debug)]
+pub struct MockServer {
+    addr: SocketAddr,
+    state: Arc<Mutex<State>>,
+    stopped: Arc<AtomicBool>,
+}
+
+impl MockServer {
+    /// Starts the server on a random local port.
+    pub fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("unable to bind the mock server");
+        let addr = listener
+            .local_addr()
+            .expect("unable to get the server address");
+        let state = Arc::new(Mutex::new(State::default()));
+        let stopped = Arc::new(AtomicBool::new(false));
+
+        let server_state = state.clone();
+        let server_stopped = stopped.clone();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                if server_stopped.load(Ordering::SeqCst) {
+                    break;
+                }
+                let Ok(stream) = stream else {
+                    continue;
+                };
+                let state = server_state.clone();
+                std::thread::spawn(move || handle(stream, &state));
+            }
+        });
+
+        Self {
+            addr,
+            state,
+            stopped,
+        }
+    }
+
+    /// Base url of the server, without a trailing slash.
+    pub fn url(&self) -> String {
+        format!("http://{}", self.addr)
+    }
+
+    /// Answers the requests whose path (without the query string) ends with `path`.
+    pub fn mock(&self, path: &str, response: Response) -> &Self {
+        self.lock().routes.push((path.to_string(), response));
+        self
+    }
+
+    /// Returns the requests received so far.
+    pub fn requests(&self) -> Vec<Request> {
+        self.lock().requests.clone()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl Drop for MockServer {
+    fn drop(&mut self) {
+        self.stopped.store(true, Ordering::SeqCst);
+        // Wake up the listener so the thread can see the flag.
+        let _ = TcpStream::connect(self.addr);
+    }
+}
+
+fn handle(stream: TcpStream, state: &Mutex<State>) {
+    let Some(request) = read_request(&stream) else {
+        return;
+    };
+
+    let response = {
+        let mut state = state.lock().unwrap_or_else(PoisonError::into_inner);
+        let path = request.path.split('?').next().unwrap_or_default();
+        let response = state
+            .routes
+            .iter()
+            .rev()
+            .find(|(route, _)| path.ends_with(route.as_str()))
+            .map(|(_, response)| response.clone());
+        state.requests.push(request);
+        response
+    };
+
+    let response = response
+        .unwrap_or_else(|| Response::json(404, r#"{"error":{"message":"no mock for this path"}}"#));
+
+    let _ = write_response(stream, &response);
+}
+
+fn read_request(stream: &TcpStream) -> Option<Request> {
+    let mut reader = BufReader::new(stream);
+
+    let mut line = String::new();
+    reader.read_line(&mut line).ok()?;
+    let mut parts = line.split_whitespace();
+    let method = parts.next()?.to_string();
+    let path = parts.next()?.to_string();
+
+    let mut headers = Vec::new();
+    loop {
+        let mut line = String::new();
+        reader.read_line(&mut line).ok()?;
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            headers.push((name.trim().to_lowercase(), value.trim().to_string()));
+        }
+    }
+
+    let length = headers
+        .iter()
+        .find(|(name, _)| name == "content-length")
+        .and_then(|(_, value)| value.parse::<usize>().ok())
+        .unwrap_or_default();
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body).ok()?;
+
+    Some(Request {
+        method,
+        path,
+        headers,
+        body: String::from_utf8_lossy(&body).to_string(),
+    })
+}
+
+fn write_response(mut stream: TcpStream, response: &Response) -> std::io::Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 {} {}\r\ncontent-type: {}\r\ncontent-length: {}\r\nconnection: close\r\n\r\n",
+        response.status,
+        reason(response.status),
+        response.content_type,
+        response.body.len(),
+    )?;
+    stream.write_all(response.body.as_bytes())?;
+    stream.flush()
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        404 => "Not Found",
+        429 => "Too Many Requests",
+        500 => "Internal Server Error",
+        529 => "Overloaded",
+        _ => "Unknown",
+    }
+}
@@ -11,7 +11,7 @@ use serde_json::Value;
 use tokio::sync::mpsc;
 use tokio::sync::Mutex;
 use tokio_stream::wrappers::ReceiverStream;
-use tokio_stream::{Stream, StreamExt};
+use tokio_stream::Stream;
 
 use crate::client::Client;
 use crate::error;
@@ -328,14 +328,18 @@ impl ChatsApi {
         let acc_clone = Arc::clone(&acc);
 
         tokio::spawn(async move {
-            while let Some(ev) = event_source.next().await {
+            while let Some(ev) = http_client::next(&mut event_source).await {
                 match ev {
                     Err(_) => {
+                        let _ = tx.send(Err(error::OpenAi::StreamError)).await;
+                        return;
+                    }
+                    Ok(Err(_)) => {
                         if tx.send(Err(error::OpenAi::StreamError)).await.is_err() {
                             return;
                         }
                     }
-                    Ok(event) => match event {
+                    Ok(Ok(event)) => match event {
                         reqwest_eventsource::Event::Open { .. } => {}
                         reqwest_eventsource::Event::Message(message) => {
                             log::debug!("Message: {:?}", message);
@@ -46,10 +46,15 @@ fn create_headers(api_key: String) -> Result<HeaderMap, error::OpenAi> {
 impl Client {
     /// Creates a new client.
     pub fn new(api_key: String) -> Result<Self, error::OpenAi> {
-        let reqwest = match ReqwestClient::builder()
-            .timeout(Duration::from_secs(300))
-            .build()
-        {
+        let reqwest = match http_client::builder(Duration::from_secs(300))
+            .map_err(|e| error::OpenAi::RequestError {
+                body: e.to_string(),
+            })
+            .and_then(|builder| {
+                builder.build().map_err(|e| error::OpenAi::RequestError {
+                    body: e.to_string(),
+                })
+            }) {
             Ok(x) => x,
             Err(e) => {
                 return Err(error::OpenAi::RequestError {
@@ -120,6 +125,7 @@ impl Client {
     }
 
     /// Makes a POST request to the OpenAi API that returns a SSE stream.
+    /// Read its events with `http_client::next` to apply `HTTP_READ_TIMEOUT`.
     pub async fn post_stream(
         &self,
         endpoint: &str,
@@ -0,0 +1,60 @@
+use mock_provider::{fixtures, MockServer, Response};
+use openai::client::Client;
+use reqwest_eventsource::Event;
+
+fn client(server: &MockServer) -> Client {
+    let mut client = Client::new("test-key".to_string()).unwrap();
+    client.set_base_url(server.url());
+    client
+}
+
+#[tokio::test]
+async fn post_stream() {
+    let server = MockServer::start();
+    server.mock("/v1/chat/completions", Response::sse(fixtures::OPENAI));
+
+    let mut events = client(&server)
+        .post_stream("/v1/chat/completions", r#"{"stream":true}"#.to_string())
+        .await
+        .unwrap();
+
+    let mut content = String::new();
+    while let Some(event) = http_client::next(&mut events).await {
+        match event.unwrap().unwrap() {
+            Event::Open => continue,
+            Event::Message(message) if message.data == "[DONE]" => break,
+            Event::Message(message) => {
+                let chunk: serde_json::Value = serde_json::from_str(&message.data).unwrap();
+                if let Some(text) = chunk["choices"][0]["delta"]["content"].as_str() {
+                    content.push_str(text);
+                }
+            }
+        }
+    }
+    events.close();
+
+    assert_eq!(content, "Hello world");
+
+    let requests = server.requests();
+    assert_eq!(requests[0].header("authorization"), Some("Bearer test-key"));
+    assert_eq!(requests[0].body, r#"{"stream":true}"#);
+}
+
+#[tokio::test]
+async fn post() {
+    let server = MockServer::start();
+    server.mock(
+        "/v1/chat/completions",
+        Response::json(200, fixtures::OPENAI_COMPLETION),
+    );
+
+    let response: serde_json::Value = client(&server)
+        .post("/v1/chat/completions", "{}".to_string())
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+
+    assert_eq!(response["choices"][0]["message"]["content"], "Hello world");
+}
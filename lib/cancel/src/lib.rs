@@ -0,0 +1,85 @@
+use std::io::{IsTerminal, Write};
+
+use tokio::sync::watch;
+
+/// Token cancelled when the user interrupts the program with Ctrl+C or `SIGTERM`.
+#[derive(Debug, Clone)]
+pub struct CancellationToken {
+    receiver: watch::Receiver<bool>,
+}
+
+impl CancellationToken {
+    /// Starts listening for interrupt signals. The first signal cancels the token, so the running
+    /// stream can stop and keep what arrived so far. A second signal exits right away.
+    ///
+    /// Must be called from within a tokio runtime.
+    pub fn on_interrupt() -> Self {
+        let (sender, receiver) = watch::channel(false);
+
+        tokio::spawn(async move {
+            interrupted().await;
+            log::info!("received interrupt signal");
+            sender.send_replace(true);
+
+            interrupted().await;
+            restore_terminal();
+            std::process::exit(130);
+        });
+
+        Self { receiver }
+    }
+
+    /// Waits until the token is cancelled.
+    pub async fn cancelled(&self) {
+        let mut receiver = self.receiver.clone();
+
+        if receiver.wait_for(|cancelled| *cancelled).await.is_err() {
+            // The signal handler is gone, so the token can't be cancelled anymore.
+            std::future::pending::<()>().await;
+        }
+    }
+}
+
+/// Resets the terminal colors and shows the cursor, in case the output was interrupted in the
+/// middle of a highlighted line or while a spinner was running.
+pub fn restore_terminal() {
+    let mut stdout = std::io::stdout();
+
+    if !stdout.is_terminal() {
+        return;
+    }
+
+    if let Err(e) = write!(stdout, "\x1b[0m\x1b[?25h").and_then(|_| stdout.flush()) {
+        log::error!("unable to restore the terminal: {}", e);
+    }
+}
+
+async fn interrupted() {
+    let ctrl_c = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            log::error!("unable to listen for Ctrl+C: {}", e);
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(e) => {
+                log::error!("unable to listen for SIGTERM: {}", e);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+}
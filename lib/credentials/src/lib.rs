@@ -0,0 +1,185 @@
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+/// Environment variable holding the path of the age identity used to decrypt key files.
+pub const IDENTITY_ENV: &str = "AGE_IDENTITY";
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("io error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("key command `{0}` failed: {1}")]
+    Command(String, String),
+    #[error("unable to decrypt {0:?}: {1}")]
+    Decrypt(PathBuf, String),
+    #[error("{0} is empty")]
+    Empty(String),
+    #[error("no key found, tried: {0}")]
+    NotFound(String),
+    #[error("no key sources configured")]
+    NoProviders,
+    #[error("HOME not found")]
+    Home,
+}
+
+/// A place to get a key from.
+#[derive(Clone, PartialEq, Eq)]
+pub enum Provider {
+    /// A key given directly, like the value of a command line argument.
+    Key(String),
+    /// Reads the key from an environment variable.
+    Env(String),
+    /// Runs a shell command (e.g. `pass show openai`) and uses the first line of its output.
+    Command(String),
+    /// Decrypts an [age](https://age-encryption.org) encrypted file holding the key, using the
+    /// identity file set on `AGE_IDENTITY`, or `~/.config/age/identity.txt`.
+    File(PathBuf),
+}
+
+impl Provider {
+    /// Gets the key. Returns `None` if the key or the environment variable is unset or blank, so
+    /// the next provider is tried, like with an exported `OPENAI_API_KEY=`. Commands that fail or
+    /// print nothing, and files that can't be decrypted, are reported as errors, since they are
+    /// only used when they are explicitly configured.
+    pub fn resolve(&self) -> Result<Option<String>, Error> {
+        let key = match self {
+            Provider::Key(key) => return Ok(non_blank(key)),
+            Provider::Env(name) => {
+                return Ok(std::env::var(name).ok().and_then(|key| non_blank(&key)))
+            }
+            Provider::Command(command) => run(command)?,
+            Provider::File(path) => decrypt(path, &identity()?)?,
+        };
+
+        match non_blank(&key) {
+            Some(key) => Ok(Some(key)),
+            None => Err(Error::Empty(self.to_string())),
+        }
+    }
+}
+
+/// Trims `key`, returning `None` if nothing is left.
+fn non_blank(key: &str) -> Option<String> {
+    let key = key.trim();
+    (!key.is_empty()).then(|| key.to_string())
+}
+
+impl std::fmt::Display for Provider {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Provider::Key(_) => write!(f, "key"),
+            Provider::Env(name) => write!(f, "environment variable {name}"),
+            Provider::Command(command) => write!(f, "command `{command}`"),
+            Provider::File(path) => write!(f, "file {path:?}"),
+        }
+    }
+}
+
+impl std::fmt::Debug for Provider {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Provider::Key(_) => write!(f, "Key(***)"),
+            Provider::Env(name) => f.debug_tuple("Env").field(name).finish(),
+            Provider::Command(command) => f.debug_tuple("Command").field(command).finish(),
+            Provider::File(path) => f.debug_tuple("File").field(path).finish(),
+        }
+    }
+}
+
+/// Gets the key from the first provider that has one.
+pub fn resolve(providers: &[Provider]) -> Result<String, Error> {
+    for provider in providers {
+        if let Some(key) = provider.resolve()? {
+            log::info!("key resolved from {}", provider);
+            return Ok(key);
+        }
+    }
+
+    if providers.is_empty() {
+        return Err(Error::NoProviders);
+    }
+
+    Err(Error::NotFound(
+        providers
+            .iter()
+            .map(Provider::to_string)
+            .collect::<Vec<_>>()
+            .join(", "),
+    ))
+}
+
+/// Providers for the key named `name`: the `name` environment variable, the command set on
+/// `{name}_COMMAND`, and the age encrypted file set on `{name}_FILE`, in that order.
+pub fn from_env(name: &str) -> Result<Vec<Provider>, Error> {
+    let mut providers = vec![Provider::Env(name.to_string())];
+
+    if let Ok(command) = std::env::var(format!("{name}_COMMAND")) {
+        providers.push(Provider::Command(command));
+    }
+    if let Ok(path) = std::env::var(format!("{name}_FILE")) {
+        providers.push(Provider::File(expand_home(&path)?));
+    }
+
+    Ok(providers)
+}
+
+/// Expands a leading `~` to the user home directory.
+pub fn expand_home(path: &str) -> Result<PathBuf, Error> {
+    match path.strip_prefix('~') {
+        Some(rest) => {
+            let home = std::env::var("HOME").map_err(|_| Error::Home)?;
+            Ok(PathBuf::from(home + rest))
+        }
+        None => Ok(PathBuf::from(path)),
+    }
+}
+
+/// Path of the age identity used to decrypt key files.
+fn identity() -> Result<PathBuf, Error> {
+    match std::env::var(IDENTITY_ENV) {
+        Ok(path) => expand_home(&path),
+        Err(_) => expand_home("~/.config/age/identity.txt"),
+    }
+}
+
+fn run(command: &str) -> Result<String, Error> {
+    let output = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .stdin(Stdio::inherit())
+        .stderr(Stdio::inherit())
+        .output()?;
+
+    if !output.status.success() {
+        return Err(Error::Command(
+            command.to_string(),
+            output.status.to_string(),
+        ));
+    }
+
+    // Password managers like `pass` store metadata after the first line.
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    Ok(stdout.lines().next().unwrap_or_default().to_string())
+}
+
+fn decrypt(path: &Path, identity: &Path) -> Result<String, Error> {
+    let error = |e: &dyn std::fmt::Display| Error::Decrypt(path.to_path_buf(), e.to_string());
+
+    let identities = age::IdentityFile::from_file(identity.to_string_lossy().to_string())
+        .map_err(|e| error(&format!("unable to read identity {identity:?}: {e}")))?
+        .into_identities()
+        .map_err(|e| error(&e))?;
+
+    let file = std::fs::File::open(path).map_err(|e| error(&e))?;
+    let decryptor =
+        age::Decryptor::new(age::armor::ArmoredReader::new(file)).map_err(|e| error(&e))?;
+
+    let mut key = String::new();
+    decryptor
+        .decrypt(identities.iter().map(|identity| identity.as_ref()))
+        .map_err(|e| error(&e))?
+        .read_to_string(&mut key)?;
+
+    Ok(key)
+}
@@ -0,0 +1,24 @@
+use anyhow::Result;
+use es_stream::anthropic::{Auth, Client, Message, MessageBody, Role};
+
+fn main() -> Result<()> {
+    env_logger::init();
+
+    let key = std::env::var("ANTHROPIC_API_KEY")?;
+
+    let auth = Auth::new(key, None);
+    let client = Client::new(auth, "https://api.anthropic.com/v1");
+
+    let messages = vec![Message {
+        role: Role::User,
+        content: "What is the capital of the United States?".into(),
+    }];
+
+    let body = MessageBody::new("claude-3-5-sonnet-20240620", messages, 100);
+
+    let response = client.complete(&body)?;
+
+    println!("{}", serde_json::to_string_pretty(&response)?);
+
+    Ok(())
+}
@@ -1,5 +1,6 @@
 use anyhow::Result;
 use es_stream::anthropic::{Auth, Client, Message, MessageBody, Role};
+use es_stream::event::StreamEvent;
 use futures::stream::TryStreamExt;
 use std::io::Write;
 
@@ -14,7 +15,7 @@ async fn main() -> Result<()> {
 
     let messages = vec![Message {
         role: Role::User,
-        content: "What is the capital of the United States?".to_string(),
+        content: "What is the capital of the United States?".into(),
     }];
 
     let body = MessageBody::new("claude-3-opus-20240229", messages, 300);
@@ -22,9 +23,11 @@ async fn main() -> Result<()> {
     // let mut stream = client.message_stream(&body)?;
     let mut stream = client.delta(&body)?;
 
-    while let Ok(Some(text)) = stream.try_next().await {
-        print!("{text}");
-        std::io::stdout().flush()?;
+    while let Ok(Some(event)) = stream.try_next().await {
+        if let StreamEvent::TextDelta(text) = event {
+            print!("{text}");
+            std::io::stdout().flush()?;
+        }
     }
 
     Ok(())
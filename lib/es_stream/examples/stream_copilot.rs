@@ -1,5 +1,6 @@
 use anyhow::Result;
-use es_stream::openai::{Auth, Client, Message, MessageBody, Role};
+use es_stream::copilot::{Auth, Client, Message, MessageBody, Role};
+use es_stream::event::StreamEvent;
 use futures::stream::TryStreamExt;
 use std::io::Write;
 
@@ -7,24 +8,23 @@ use std::io::Write;
 async fn main() -> Result<()> {
     env_logger::init();
 
-    let key = std::env::var("COPILOT_API_KEY")?;
-
-    let auth = Auth::new(key);
+    let auth = Auth::from_env().or_else(|_| Auth::from_copilot_config())?;
     let client = Client::new(auth, "https://api.githubcopilot.com");
 
-    let messages = vec![Message {
-        role: Role::User,
-        content: "What is the capital of the United States?".to_string(),
-    }];
+    let messages = vec![Message::new(
+        Role::User,
+        "What is the capital of the United States?",
+    )];
 
     let body = MessageBody::new("gpt-4o", messages);
 
-    // let mut stream = client.message_stream(&body)?;
     let mut stream = client.delta(&body)?;
 
-    while let Ok(Some(text)) = stream.try_next().await {
-        print!("{text}");
-        std::io::stdout().flush()?;
+    while let Ok(Some(event)) = stream.try_next().await {
+        if let StreamEvent::TextDelta(text) = event {
+            print!("{text}");
+            std::io::stdout().flush()?;
+        }
     }
 
     Ok(())
@@ -1,4 +1,5 @@
 use anyhow::Result;
+use es_stream::event::StreamEvent;
 use es_stream::google::{Auth, Client, Content, MessageBody, Part, Role};
 use futures::stream::TryStreamExt;
 use std::io::Write;
@@ -13,9 +14,7 @@ async fn main() -> Result<()> {
     let client = Client::new(auth, "https://generativelanguage.googleapis.com/v1beta");
 
     let messages = vec![Content {
-        parts: vec![Part {
-            text: "What is the capital of the United States?".to_string(),
-        }],
+        parts: vec![Part::text("What is the capital of the United States?")],
         role: Role::User,
     }];
 
@@ -24,9 +23,11 @@ async fn main() -> Result<()> {
     // let mut stream = client.message_stream(&body)?;
     let mut stream = client.delta(&body)?;
 
-    while let Ok(Some(text)) = stream.try_next().await {
-        print!("{text}");
-        std::io::stdout().flush()?;
+    while let Ok(Some(event)) = stream.try_next().await {
+        if let StreamEvent::TextDelta(text) = event {
+            print!("{text}");
+            std::io::stdout().flush()?;
+        }
     }
 
     Ok(())
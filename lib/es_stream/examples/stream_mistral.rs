@@ -1,4 +1,5 @@
 use anyhow::Result;
+use es_stream::event::StreamEvent;
 use es_stream::mistral::{Auth, Client, Message, MessageBody, Role};
 use futures::stream::TryStreamExt;
 use std::io::Write;
@@ -12,19 +13,21 @@ async fn main() -> Result<()> {
     let auth = Auth::new(key);
     let client = Client::new(auth, "https://api.mistral.ai/v1");
 
-    let messages = vec![Message {
-        role: Role::User,
-        content: "What is the capital of the United States?".to_string(),
-    }];
+    let messages = vec![Message::new(
+        Role::User,
+        "What is the capital of the United States?",
+    )];
 
     let body = MessageBody::new("mistral-small-latest", messages);
 
     // let mut stream = client.message_stream(&body)?;
     let mut stream = client.delta(&body)?;
 
-    while let Ok(Some(text)) = stream.try_next().await {
-        print!("{text}");
-        std::io::stdout().flush()?;
+    while let Ok(Some(event)) = stream.try_next().await {
+        if let StreamEvent::TextDelta(text) = event {
+            print!("{text}");
+            std::io::stdout().flush()?;
+        }
     }
 
     Ok(())
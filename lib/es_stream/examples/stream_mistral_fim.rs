@@ -1,4 +1,5 @@
 use anyhow::Result;
+use es_stream::event::StreamEvent;
 use es_stream::mistral_fim::{Auth, Client, MessageBody};
 use futures::stream::TryStreamExt;
 use std::io::Write;
@@ -20,9 +21,11 @@ async fn main() -> Result<()> {
     // let mut stream = client.message_stream(&body)?;
     let mut stream = client.delta(&body)?;
 
-    while let Ok(Some(text)) = stream.try_next().await {
-        print!("{text}");
-        std::io::stdout().flush()?;
+    while let Ok(Some(event)) = stream.try_next().await {
+        if let StreamEvent::TextDelta(text) = event {
+            print!("{text}");
+            std::io::stdout().flush()?;
+        }
     }
 
     Ok(())
@@ -0,0 +1,31 @@
+use anyhow::Result;
+use es_stream::event::StreamEvent;
+use es_stream::ollama::{Auth, Client, Message, MessageBody, Role};
+use futures::stream::TryStreamExt;
+use std::io::Write;
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    env_logger::init();
+
+    let auth = Auth::from_env()?;
+    let client = Client::new(auth, "http://localhost:11434");
+
+    let messages = vec![Message::new(
+        Role::User,
+        "What is the capital of the United States?",
+    )];
+
+    let body = MessageBody::new("llama3.1", messages);
+
+    let mut stream = client.delta(&body)?;
+
+    while let Ok(Some(event)) = stream.try_next().await {
+        if let StreamEvent::TextDelta(text) = event {
+            print!("{text}");
+            std::io::stdout().flush()?;
+        }
+    }
+
+    Ok(())
+}
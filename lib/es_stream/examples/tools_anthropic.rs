@@ -0,0 +1,75 @@
+use anyhow::Result;
+use es_stream::anthropic::{Auth, Client};
+use es_stream::event::StreamEvent;
+use es_stream::provider::{ChatProvider, ChatRequest, Message, Role};
+use es_stream::tool::{Tool, ToolResult};
+use futures::stream::TryStreamExt;
+use std::io::Write;
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    env_logger::init();
+
+    let key = std::env::var("ANTHROPIC_API_KEY")?;
+
+    let auth = Auth::new(key, None);
+    let client = Client::new(auth, "https://api.anthropic.com/v1");
+
+    let mut request = ChatRequest::new(
+        "claude-3-5-sonnet-20240620",
+        vec![Message::new(
+            Role::User,
+            "What is the weather like in Montevideo?",
+        )],
+    );
+    request.tools = Some(vec![Tool::new(
+        "get_weather",
+        Some("Get the current weather of a city".to_string()),
+        serde_json::json!({
+            "type": "object",
+            "properties": {
+                "city": { "type": "string" }
+            },
+            "required": ["city"]
+        }),
+    )]);
+
+    loop {
+        let mut stream = client.stream(&request)?;
+        let mut assistant = Message::new(Role::Assistant, "");
+
+        while let Some(event) = stream.try_next().await? {
+            match event {
+                StreamEvent::TextDelta(text) => {
+                    print!("{text}");
+                    std::io::stdout().flush()?;
+                    assistant.content.push_str(&text);
+                }
+                StreamEvent::ToolCall(call) => assistant.tool_calls.push(call),
+                _ => {}
+            }
+        }
+
+        if assistant.tool_calls.is_empty() {
+            break;
+        }
+
+        let results = assistant
+            .tool_calls
+            .iter()
+            .map(|call| ToolResult {
+                tool_call_id: call.id.clone(),
+                name: call.name.clone(),
+                content: format!("It's sunny in {}", call.arguments["city"]),
+                is_error: false,
+            })
+            .collect();
+
+        request.messages.push(assistant);
+        request.messages.push(Message::tool_results(results));
+    }
+
+    println!();
+
+    Ok(())
+}
@@ -2,32 +2,134 @@ use eventsource_client as es;
 use futures::stream::{Stream, StreamExt};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
-use std::time::Duration;
 
-use crate::error::Error;
+use crate::cassette::Track;
+use crate::error::{ApiErrorDetails, Error};
+use crate::event::{map_sse, StreamEvent, TokenUsage};
+use crate::options::{ClientOptions, WithOptions};
+use crate::provider::{ChatProvider, ChatRequest, EventStream};
 use crate::requests::{Json, Requests};
+use crate::secret::Secret;
+use crate::tool::{self, ToolCallBuffer};
 
 // Messages API
 const MESSAGES_CREATE: &str = "/messages";
+// Used when a `ChatRequest` doesn't set `max_tokens`, since the Messages API requires it.
+const DEFAULT_MAX_TOKENS: u32 = 4096;
+/// Name of the tool used to force a structured response that follows the `response_schema`.
+const RESPONSE_TOOL: &str = "json_response";
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, Clone, Copy)]
 pub struct Usage {
     pub input_tokens: Option<u32>,
     pub output_tokens: Option<u32>,
 }
 
+impl From<Usage> for TokenUsage {
+    fn from(usage: Usage) -> Self {
+        Self {
+            input_tokens: usage.input_tokens,
+            output_tokens: usage.output_tokens,
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Content {
     /// Determines the content shape.
     pub r#type: String,
     /// Response content
     pub text: Option<String>,
+    /// Tool use identifier. Only present on `tool_use` blocks.
+    pub id: Option<String>,
+    /// Name of the tool to use. Only present on `tool_use` blocks.
+    pub name: Option<String>,
+    /// Input of the tool. Only present on `tool_use` blocks.
+    pub input: Option<Json>,
+}
+
+/// Source of an image or document block.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Source {
+    /// Source type. Only `base64` is supported.
+    pub r#type: String,
+    /// MIME type of the data.
+    pub media_type: String,
+    /// Base64 encoded data.
+    pub data: String,
+}
+
+impl From<&crate::provider::Attachment> for Source {
+    fn from(attachment: &crate::provider::Attachment) -> Self {
+        Self {
+            r#type: "base64".to_string(),
+            media_type: attachment.mime_type.clone(),
+            data: attachment.data.clone(),
+        }
+    }
+}
+
+/// A block of a message content.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ContentBlock {
+    /// Text content.
+    Text { text: String },
+    /// An image.
+    Image { source: Source },
+    /// A document, such as a PDF.
+    Document { source: Source },
+    /// A tool call made by the assistant.
+    ToolUse {
+        id: String,
+        name: String,
+        input: Json,
+    },
+    /// The result of a tool call, sent on a user message.
+    ToolResult {
+        tool_use_id: String,
+        content: String,
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        is_error: bool,
+    },
+}
+
+/// The content of a message, either a single string or a list of content blocks.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(untagged)]
+pub enum MessageContent {
+    Text(String),
+    Blocks(Vec<ContentBlock>),
+}
+
+impl From<String> for MessageContent {
+    fn from(text: String) -> Self {
+        Self::Text(text)
+    }
+}
+
+impl From<&str> for MessageContent {
+    fn from(text: &str) -> Self {
+        Self::Text(text.to_string())
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Message {
     pub role: Role,
-    pub content: String,
+    pub content: MessageContent,
+}
+
+/// A tool the model may use.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Tool {
+    /// Name of the tool.
+    pub name: String,
+    /// Description of what this tool does.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// JSON schema for the tool input.
+    pub input_schema: Json,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -67,6 +169,24 @@ pub struct MessageBody {
     /// Use nucleus sampling.
     #[serde(skip_serializing_if = "Option::is_none")]
     pub top_p: Option<f32>,
+    /// Definitions of tools that the model may use.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tools: Option<Vec<Tool>>,
+    /// How the model should use the provided tools.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tool_choice: Option<ToolChoice>,
+}
+
+/// How the model should use the provided tools.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ToolChoice {
+    /// The model decides whether to use a tool.
+    Auto,
+    /// The model must use one of the tools.
+    Any,
+    /// The model must use the named tool.
+    Tool { name: String },
 }
 
 impl MessageBody {
@@ -129,10 +249,20 @@ struct Delta {
     pub r#type: Option<String>,
     /// Response content
     pub text: Option<String>,
+    /// Fragment of the input of a `tool_use` block.
+    pub partial_json: Option<String>,
     pub stop_reason: Option<String>,
     pub end_turn: Option<String>,
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+struct ErrorDetails {
+    /// Error type.
+    pub r#type: String,
+    /// Human readable error message.
+    pub message: String,
+}
+
 #[derive(Debug, Serialize, Deserialize, Default)]
 #[serde(rename_all = "snake_case")]
 enum MessageEventType {
@@ -155,7 +285,7 @@ struct MessageEvent {
     /// Init message
     pub message: Option<MessageEventResponse>,
     /// Event index
-    pub index: Option<i32>,
+    pub index: Option<usize>,
     /// Content block
     pub content_block: Option<Content>,
     /// Delta block
@@ -164,18 +294,91 @@ struct MessageEvent {
     pub usage: Option<Usage>,
     /// Comment
     pub comment: Option<String>,
+    /// Error details
+    pub error: Option<ErrorDetails>,
+}
+
+/// Creates a parser that maps Anthropic SSE events into `StreamEvent`s.
+fn event_parser() -> impl FnMut(&es::Event) -> Result<Vec<StreamEvent>, Error> {
+    let mut tool_calls = ToolCallBuffer::default();
+
+    move |ev| parse_event(ev, &mut tool_calls)
+}
+
+/// Maps an Anthropic SSE event into `StreamEvent`s. The input of `tool_use` blocks is buffered
+/// until the block stops.
+fn parse_event(ev: &es::Event, tool_calls: &mut ToolCallBuffer) -> Result<Vec<StreamEvent>, Error> {
+    let event = serde_json::from_str::<MessageEvent>(&ev.data)?;
+    let index = event.index.unwrap_or_default();
+
+    let events = match event.r#type {
+        MessageEventType::MessageStart => match event.message {
+            Some(message) => vec![
+                StreamEvent::MessageStart {
+                    id: Some(message.id),
+                    model: Some(message.model),
+                },
+                StreamEvent::Usage(message.usage.into()),
+            ],
+            None => Vec::new(),
+        },
+        MessageEventType::ContentBlockStart => {
+            if let Some(block) = event.content_block.filter(|b| b.r#type == "tool_use") {
+                tool_calls.push(index, block.id, block.name, None);
+            }
+            Vec::new()
+        }
+        MessageEventType::ContentBlockDelta => match event.delta {
+            Some(Delta {
+                partial_json: Some(partial_json),
+                ..
+            }) => {
+                tool_calls.push(index, None, None, Some(&partial_json));
+                Vec::new()
+            }
+            Some(Delta {
+                text: Some(text), ..
+            }) => vec![StreamEvent::TextDelta(text)],
+            _ => Vec::new(),
+        },
+        MessageEventType::ContentBlockStop => tool_calls.events()?,
+        MessageEventType::MessageDelta => {
+            let mut events = Vec::new();
+            if let Some(stop_reason) = event.delta.and_then(|delta| delta.stop_reason) {
+                events.push(StreamEvent::StopReason(stop_reason));
+            }
+            if let Some(usage) = event.usage {
+                events.push(StreamEvent::Usage(usage.into()));
+            }
+            events
+        }
+        MessageEventType::MessageStop => vec![StreamEvent::Done],
+        MessageEventType::Error => {
+            let details = match event.error {
+                Some(error) => ApiErrorDetails::new(Some(error.r#type), error.message),
+                None => ApiErrorDetails::new(None, ev.data.clone()),
+            };
+            return Err(Error::ApiError(details));
+        }
+        _ => Vec::new(),
+    };
+
+    Ok(events)
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Auth {
-    pub api_key: String,
+    pub api_key: Secret,
     pub version: Option<String>,
 }
 
 impl Auth {
     #[must_use]
-    pub fn new(api_key: String, version: Option<String>) -> Self {
-        Self { api_key, version }
+    pub fn new(api_key: impl Into<Secret>, version: Option<String>) -> Self {
+        Self {
+            api_key: api_key.into(),
+            version,
+        }
     }
 
     pub fn from_env() -> Result<Self, Error> {
@@ -184,7 +387,7 @@ impl Auth {
             Err(_) => return Err(Error::AuthError("ANTHROPIC_API_KEY not found".to_string())),
         };
         let version = std::env::var("ANTHROPIC_API_VERSION").ok();
-        Ok(Self { api_key, version })
+        Ok(Self::new(api_key, version))
     }
 }
 
@@ -192,6 +395,7 @@ impl Auth {
 pub struct Client {
     pub auth: Auth,
     pub api_url: String,
+    pub options: ClientOptions,
 }
 
 impl Client {
@@ -199,15 +403,22 @@ impl Client {
         Self {
             auth,
             api_url: api_url.into(),
+            options: ClientOptions::default(),
         }
     }
 }
 
+impl WithOptions for Client {
+    fn options_mut(&mut self) -> &mut ClientOptions {
+        &mut self.options
+    }
+}
+
 impl Client {
     pub fn delta<'a>(
         &'a self,
-        message_body: &'a MessageBody,
-    ) -> Result<impl Stream<Item = Result<String, Error>> + 'a, Error> {
+        message_body: &MessageBody,
+    ) -> Result<impl Stream<Item = Result<StreamEvent, Error>> + 'a, Error> {
         log::debug!("message_body: {:#?}", message_body);
 
         let request_body = match serde_json::to_value(message_body) {
@@ -216,44 +427,169 @@ impl Client {
         };
         log::debug!("request_body: {:#?}", request_body);
 
-        let original_stream = match self.post_stream(MESSAGES_CREATE.to_string(), request_body) {
-            Ok(stream) => stream,
-            Err(e) => return Err(Error::EventsourceClient(e)),
-        };
+        let track = Track::new(self.options.cassette.as_ref());
 
-        let mapped_stream = original_stream.map(|item| {
-            if item.is_err() {
-                return Err(Error::EventsourceClient(item.err().unwrap()));
-            }
-            item.map(|event| match event {
-                es::SSE::Connected(_) => String::default(),
-                es::SSE::Event(ev) => match serde_json::from_str::<MessageEvent>(&ev.data) {
-                    Ok(ev) => {
-                        if matches!(ev.r#type, MessageEventType::ContentBlockDelta) {
-                            if let Some(delta) = ev.delta {
-                                delta.text.map_or_else(String::default, |text| text)
-                            } else {
-                                String::default()
-                            }
-                        } else {
-                            String::default()
-                        }
-                    }
-                    Err(e) => {
-                        log::error!("Error parsing event: {:#?}", ev);
-                        log::error!("Error: {:#?}", e);
-                        String::default()
-                    }
+        Ok(crate::retry::stream(self.options.retry, move || {
+            let stream = track.sse(&request_body, || {
+                self.post_stream(MESSAGES_CREATE.to_string(), request_body.clone())
+            })?;
+            Ok(map_sse(stream, event_parser()))
+        }))
+    }
+
+    /// Sends the request without streaming and returns the complete message.
+    pub fn complete(&self, message_body: &MessageBody) -> Result<MessageResponse, Error> {
+        log::debug!("message_body: {:#?}", message_body);
+
+        let mut request_body = serde_json::to_value(message_body)?;
+        request_body["stream"] = false.into();
+        log::debug!("request_body: {:#?}", request_body);
+
+        let response = self.post(MESSAGES_CREATE.to_string(), request_body)?;
+        log::debug!("response: {:#?}", response);
+
+        Ok(serde_json::from_value(response)?)
+    }
+}
+
+impl From<crate::provider::Role> for Role {
+    fn from(role: crate::provider::Role) -> Self {
+        match role {
+            crate::provider::Role::Assistant => Role::Assistant,
+            crate::provider::Role::User => Role::User,
+        }
+    }
+}
+
+impl From<&tool::Tool> for Tool {
+    fn from(tool: &tool::Tool) -> Self {
+        Self {
+            name: tool.name.clone(),
+            description: tool.description.clone(),
+            input_schema: tool.parameters.clone(),
+        }
+    }
+}
+
+impl From<&crate::provider::Message> for Message {
+    /// Messages with attachments, tool calls or results are sent as content blocks, with the tool
+    /// results first as required by the API.
+    fn from(message: &crate::provider::Message) -> Self {
+        if message.attachments.is_empty()
+            && message.tool_calls.is_empty()
+            && message.tool_results.is_empty()
+        {
+            return Self {
+                role: message.role.into(),
+                content: message.content.clone().into(),
+            };
+        }
+
+        let results = message
+            .tool_results
+            .iter()
+            .map(|result| ContentBlock::ToolResult {
+                tool_use_id: result.tool_call_id.clone(),
+                content: result.content.clone(),
+                is_error: result.is_error,
+            });
+        let attachments = message
+            .attachments
+            .iter()
+            .map(|attachment| match attachment.text() {
+                Some(text) => ContentBlock::Text { text },
+                None if attachment.is_image() => ContentBlock::Image {
+                    source: attachment.into(),
                 },
-                es::SSE::Comment(comment) => {
-                    log::debug!("Comment: {:#?}", comment);
-                    String::default()
-                }
-            })
-            .map_err(Error::from)
+                None => ContentBlock::Document {
+                    source: attachment.into(),
+                },
+            });
+        let text = (!message.content.is_empty()).then(|| ContentBlock::Text {
+            text: message.content.clone(),
+        });
+        let calls = message.tool_calls.iter().map(|call| ContentBlock::ToolUse {
+            id: call.id.clone(),
+            name: call.name.clone(),
+            input: call.arguments.clone(),
         });
 
-        Ok(mapped_stream)
+        Self {
+            role: message.role.into(),
+            content: MessageContent::Blocks(
+                results
+                    .chain(attachments)
+                    .chain(text)
+                    .chain(calls)
+                    .collect(),
+            ),
+        }
+    }
+}
+
+impl From<&ChatRequest> for MessageBody {
+    fn from(request: &ChatRequest) -> Self {
+        let messages = request.messages.iter().map(Message::from).collect();
+        let mut tools = request
+            .tools
+            .as_ref()
+            .map(|tools| tools.iter().map(Tool::from).collect::<Vec<_>>());
+        let mut tool_choice = None;
+
+        // Anthropic has no structured output mode, so the model is forced to call a tool whose
+        // input schema is the response schema.
+        if let Some(schema) = &request.response_schema {
+            tools.get_or_insert_with(Vec::new).push(Tool {
+                name: RESPONSE_TOOL.to_string(),
+                description: Some("Respond with a JSON object that follows the schema".to_string()),
+                input_schema: schema.clone(),
+            });
+            // Forcing the response tool would keep the model from calling the user tools, so it
+            // is only asked to call one of them.
+            tool_choice = Some(if request.tools.as_ref().is_some_and(|t| !t.is_empty()) {
+                ToolChoice::Any
+            } else {
+                ToolChoice::Tool {
+                    name: RESPONSE_TOOL.to_string(),
+                }
+            });
+        }
+
+        Self {
+            system: request.system.clone(),
+            stop_sequences: request.stop_sequences.clone(),
+            temperature: request.temperature,
+            top_p: request.top_p,
+            top_k: request.top_k,
+            tools,
+            tool_choice,
+            ..Self::new(
+                &request.model,
+                messages,
+                request.max_tokens.unwrap_or(DEFAULT_MAX_TOKENS),
+            )
+        }
+    }
+}
+
+impl ChatProvider for Client {
+    /// When the request has a `response_schema`, the input of the forced response tool is
+    /// streamed back as text.
+    fn stream<'a>(&'a self, request: &ChatRequest) -> Result<EventStream<'a>, Error> {
+        let stream = self.delta(&MessageBody::from(request))?;
+
+        if request.response_schema.is_none() {
+            return Ok(stream.boxed_local());
+        }
+
+        Ok(stream
+            .map(|event| match event {
+                Ok(StreamEvent::ToolCall(call)) if call.name == RESPONSE_TOOL => {
+                    Ok(StreamEvent::TextDelta(call.arguments.to_string()))
+                }
+                event => event,
+            })
+            .boxed_local())
     }
 }
 
@@ -265,22 +601,31 @@ impl Requests for Client {
     ) -> Result<impl Stream<Item = Result<es::SSE, es::Error>>, es::Error> {
         let anthropic_version = self.auth.version.as_deref().unwrap_or("2023-06-01");
 
-        let client = es::ClientBuilder::for_url(&(self.api_url.clone() + &sub_url))?
+        let builder = es::ClientBuilder::for_url(&(self.api_url.clone() + &sub_url))?
             .header("anthropic-version", anthropic_version)?
             .header("content-type", "application/json")?
-            .header("x-api-key", &self.auth.api_key)?
+            .header("x-api-key", self.auth.api_key.expose())?
             .method("POST".into())
             .body(body.to_string())
-            .reconnect(
-                es::ReconnectOptions::reconnect(true)
-                    .retry_initial(false)
-                    .delay(Duration::from_secs(1))
-                    .backoff_factor(2)
-                    .delay_max(Duration::from_secs(60))
-                    .build(),
-            )
-            .build();
+            .reconnect(crate::requests::no_reconnect());
+        let client = self.options.http.sse_client(builder)?;
+
+        Ok(crate::requests::tail(client))
+    }
+
+    fn post(&self, sub_url: String, body: Json) -> Result<Json, Error> {
+        let anthropic_version = self.auth.version.as_deref().unwrap_or("2023-06-01");
+
+        let request = self
+            .options
+            .http
+            .post(&(self.api_url.clone() + &sub_url))?
+            .set("anthropic-version", anthropic_version)
+            .set("content-type", "application/json")
+            .set("x-api-key", self.auth.api_key.expose());
 
-        Ok(crate::requests::tail(&client))
+        crate::retry::blocking(self.options.retry, || {
+            crate::requests::send(request.clone(), body.clone())
+        })
     }
 }
@@ -0,0 +1,452 @@
+use eventsource_client as es;
+use futures::future::FutureExt;
+use futures::stream::{self, LocalBoxStream, Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use crate::error::{ApiErrorDetails, Error};
+use crate::requests::Json;
+
+/// What to do with the requests sent through a `Cassette`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    /// Send the requests and save them, together with the raw responses, on the directory.
+    Record,
+    /// Read the responses from the directory instead of sending the requests.
+    Replay,
+}
+
+/// Directory holding the requests sent to a provider and the raw responses it streamed back.
+///
+/// Every request is saved as `001.request.json`, with the exact body that was sent, and
+/// `001.response.sse` (or `001.response.ndjson` for Ollama, and `001.response.json` for the
+/// requests that aren't streamed), with the response as it came from the server. Requests refused
+/// by the provider save the status, headers and body of the error response on `001.error.json`
+/// instead. The number goes up with each request of the session, so conversations with tool calls
+/// are replayed in the same order they were recorded.
+///
+/// Replaying fails with `Error::Cassette` when a request differs from the recorded one.
+#[derive(Debug, Clone)]
+pub struct Cassette {
+    pub mode: Mode,
+    pub dir: PathBuf,
+    requests: Arc<AtomicUsize>,
+}
+
+impl Cassette {
+    #[must_use]
+    pub fn new(mode: Mode, dir: impl Into<PathBuf>) -> Self {
+        Self {
+            mode,
+            dir: dir.into(),
+            requests: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    /// Records the requests and responses on `dir`.
+    #[must_use]
+    pub fn record(dir: impl Into<PathBuf>) -> Self {
+        Self::new(Mode::Record, dir)
+    }
+
+    /// Replays the responses recorded on `dir`.
+    #[must_use]
+    pub fn replay(dir: impl Into<PathBuf>) -> Self {
+        Self::new(Mode::Replay, dir)
+    }
+
+    /// Checks if the responses are read from disk instead of the network.
+    #[must_use]
+    pub fn is_replay(&self) -> bool {
+        self.mode == Mode::Replay
+    }
+}
+
+/// A single request of a `Cassette`. Without a cassette the requests go straight to the network.
+#[derive(Debug, Clone)]
+pub(crate) enum Track {
+    Off,
+    Record(PathBuf),
+    Replay(PathBuf),
+}
+
+impl Track {
+    /// Takes the next request number from `cassette`. Retries of the same request must use the
+    /// same `Track`, so only the last attempt is kept.
+    pub(crate) fn new(cassette: Option<&Cassette>) -> Self {
+        let Some(cassette) = cassette else {
+            return Track::Off;
+        };
+
+        let number = cassette.requests.fetch_add(1, Ordering::SeqCst) + 1;
+        let prefix = cassette.dir.join(format!("{number:03}"));
+
+        match cassette.mode {
+            Mode::Record => Track::Record(prefix),
+            Mode::Replay => Track::Replay(prefix),
+        }
+    }
+
+    /// Checks if the response is read from disk instead of the network.
+    pub(crate) fn is_replay(&self) -> bool {
+        matches!(self, Track::Replay(_))
+    }
+
+    /// Opens the SSE stream with `send`, saving the body and the events, or the error response,
+    /// when recording, or reads them from disk when replaying. Error responses are reported as
+    /// `Error::ApiError`.
+    pub(crate) fn sse<'a, S, E>(
+        &self,
+        body: &Json,
+        send: impl FnOnce() -> Result<S, E>,
+    ) -> Result<LocalBoxStream<'a, Result<es::SSE, Error>>, Error>
+    where
+        S: Stream<Item = Result<es::SSE, es::Error>> + 'a,
+        E: Into<Error>,
+    {
+        match self {
+            Track::Off => Ok(api_errors(send().map_err(Into::into)?, |_| {})),
+            Track::Record(prefix) => {
+                let mut file = create(prefix, body, "sse")?;
+                let stream = send().map_err(Into::into)?.inspect(move |item| {
+                    if let Ok(es::SSE::Event(event)) = item {
+                        if let Err(e) = file.write_all(to_sse(event).as_bytes()) {
+                            log::warn!("unable to record event: {}", e);
+                        }
+                    }
+                });
+                let prefix = prefix.clone();
+                Ok(api_errors(stream, move |response| response.save(&prefix)))
+            }
+            Track::Replay(prefix) => {
+                check_body(prefix, body)?;
+                if let Some(response) = ErrorResponse::load(prefix)? {
+                    return Ok(stream::iter([Err(response.to_error())]).boxed_local());
+                }
+                let content = read(&path(prefix, "response.sse"))?;
+                let events = parse_sse(&content)
+                    .into_iter()
+                    .map(|event| Ok(es::SSE::Event(event)))
+                    .chain(std::iter::once(Err(Error::EventsourceClient(
+                        es::Error::Eof,
+                    ))));
+                Ok(stream::iter(events).boxed_local())
+            }
+        }
+    }
+
+    /// Sends the request with `send` and returns its JSON response, saving the body and the
+    /// response, or the error response, when recording, or reads them from disk when replaying.
+    pub(crate) fn json(
+        &self,
+        body: &Json,
+        send: impl FnOnce() -> Result<ureq::Response, ureq::Error>,
+    ) -> Result<Json, Error> {
+        match self {
+            Track::Off => Ok(send()?.into_json()?),
+            Track::Record(prefix) => {
+                let mut file = create(prefix, body, "json")?;
+                let response: Json = match send() {
+                    Ok(response) => response.into_json()?,
+                    Err(ureq::Error::Status(status, response)) => {
+                        let response = ErrorResponse::from_ureq(status, response);
+                        response.save(prefix);
+                        return Err(response.to_error());
+                    }
+                    Err(e) => return Err(e.into()),
+                };
+                file.write_all(response.to_string().as_bytes())?;
+                Ok(response)
+            }
+            Track::Replay(prefix) => {
+                check_body(prefix, body)?;
+                if let Some(response) = ErrorResponse::load(prefix)? {
+                    return Err(response.to_error());
+                }
+                let path = path(prefix, "response.json");
+                serde_json::from_str(&read(&path)?)
+                    .map_err(|e| Error::Cassette(format!("invalid response on {:?}: {}", path, e)))
+            }
+        }
+    }
+
+    /// Sends the request with `send` and returns the lines of a newline delimited JSON response,
+    /// saving the body and the lines, or the error response, when recording, or reads them from
+    /// disk when replaying.
+    pub(crate) fn lines<'a, I>(
+        &self,
+        body: &Json,
+        send: impl FnOnce() -> Result<I, ureq::Error>,
+    ) -> Result<Box<dyn Iterator<Item = std::io::Result<String>> + 'a>, Error>
+    where
+        I: Iterator<Item = std::io::Result<String>> + 'a,
+    {
+        match self {
+            Track::Off => Ok(Box::new(send()?)),
+            Track::Record(prefix) => {
+                let mut file = create(prefix, body, "ndjson")?;
+                let lines = match send() {
+                    Ok(lines) => lines,
+                    Err(ureq::Error::Status(status, response)) => {
+                        let response = ErrorResponse::from_ureq(status, response);
+                        response.save(prefix);
+                        return Err(response.to_error());
+                    }
+                    Err(e) => return Err(e.into()),
+                };
+                let lines = lines.inspect(move |line| {
+                    if let Ok(line) = line {
+                        if let Err(e) = writeln!(file, "{line}") {
+                            log::warn!("unable to record line: {}", e);
+                        }
+                    }
+                });
+                Ok(Box::new(lines))
+            }
+            Track::Replay(prefix) => {
+                check_body(prefix, body)?;
+                if let Some(response) = ErrorResponse::load(prefix)? {
+                    return Err(response.to_error());
+                }
+                let path = path(prefix, "response.ndjson");
+                let file = std::fs::File::open(&path).map_err(|e| not_found(&path, e))?;
+                Ok(Box::new(BufReader::new(file).lines()))
+            }
+        }
+    }
+}
+
+fn path(prefix: &Path, suffix: &str) -> PathBuf {
+    let mut path = prefix.as_os_str().to_owned();
+    path.push(".");
+    path.push(suffix);
+    PathBuf::from(path)
+}
+
+/// Saves the request body and creates the file for the response.
+fn create(prefix: &Path, body: &Json, format: &str) -> Result<std::fs::File, Error> {
+    if let Some(dir) = prefix.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+
+    let request = path(prefix, "request.json");
+    std::fs::write(&request, body.to_string())?;
+    log::info!("recording request on {:?}", request);
+
+    // A previous attempt of the same request may have failed.
+    let error = path(prefix, "error.json");
+    if error.exists() {
+        std::fs::remove_file(error)?;
+    }
+
+    Ok(std::fs::File::create(path(
+        prefix,
+        &format!("response.{format}"),
+    ))?)
+}
+
+fn read(path: &Path) -> Result<String, Error> {
+    std::fs::read_to_string(path).map_err(|e| not_found(path, e))
+}
+
+fn not_found(path: &Path, e: std::io::Error) -> Error {
+    Error::Cassette(format!("unable to read {:?}: {}", path, e))
+}
+
+/// Checks that the request is the same one that was recorded, so the replayed response answers
+/// it.
+fn check_body(prefix: &Path, body: &Json) -> Result<(), Error> {
+    let request = path(prefix, "request.json");
+    let recorded: Json = serde_json::from_str(&read(&request)?)
+        .map_err(|e| Error::Cassette(format!("invalid request on {:?}: {}", request, e)))?;
+
+    if &recorded != body {
+        return Err(Error::Cassette(format!(
+            "the request doesn't match the one recorded on {:?}",
+            request
+        )));
+    }
+
+    Ok(())
+}
+
+/// Maps the errors of an SSE stream into `Error`s, reading the body of the error responses and
+/// passing them to `on_response` first.
+fn api_errors<'a>(
+    stream: impl Stream<Item = Result<es::SSE, es::Error>> + 'a,
+    mut on_response: impl FnMut(&ErrorResponse) + 'a,
+) -> LocalBoxStream<'a, Result<es::SSE, Error>> {
+    stream
+        .then(|item| {
+            async move {
+                match item {
+                    Ok(sse) => Ok(Ok(sse)),
+                    Err(e) => match ErrorResponse::from_sse(e).await {
+                        Ok(response) => Err(response),
+                        Err(e) => Ok(Err(Error::from(e))),
+                    },
+                }
+            }
+            .boxed_local()
+        })
+        .map(move |item| match item {
+            Ok(item) => item,
+            Err(response) => {
+                on_response(&response);
+                Err(response.to_error())
+            }
+        })
+        .boxed_local()
+}
+
+/// An error response of the provider. Recorded requests that failed save it on `NNN.error.json`.
+#[derive(Debug, Serialize, Deserialize)]
+struct ErrorResponse {
+    status: u16,
+    #[serde(default)]
+    headers: BTreeMap<String, String>,
+    body: String,
+}
+
+impl ErrorResponse {
+    /// Reads the error response out of `error`, or gives `error` back if it isn't one.
+    async fn from_sse(error: es::Error) -> Result<Self, es::Error> {
+        let es::Error::UnexpectedResponse(response, body) = error else {
+            return Err(error);
+        };
+
+        let headers = response
+            .get_header_keys()
+            .into_iter()
+            .filter_map(|name| {
+                let value = response.get_header_value(name).ok().flatten()?;
+                Some((name.to_string(), value.to_string()))
+            })
+            .collect();
+        let body = body.body_bytes().await.unwrap_or_default();
+
+        Ok(Self {
+            status: response.status(),
+            headers,
+            body: String::from_utf8_lossy(&body).to_string(),
+        })
+    }
+
+    fn from_ureq(status: u16, response: ureq::Response) -> Self {
+        let headers = response
+            .headers_names()
+            .into_iter()
+            .filter_map(|name| {
+                let value = response.header(&name)?.to_string();
+                Some((name, value))
+            })
+            .collect();
+
+        Self {
+            status,
+            headers,
+            body: response.into_string().unwrap_or_default(),
+        }
+    }
+
+    /// Saves the response. Failing to do so doesn't hide the error of the provider, so it's only
+    /// logged.
+    fn save(&self, prefix: &Path) {
+        let path = path(prefix, "error.json");
+        let saved = serde_json::to_string(self)
+            .map_err(Error::from)
+            .and_then(|json| Ok(std::fs::write(&path, json)?));
+
+        if let Err(e) = saved {
+            log::warn!("unable to record the error on {:?}: {}", path, e);
+        }
+    }
+
+    /// Reads the response saved for the request, if it failed.
+    fn load(prefix: &Path) -> Result<Option<Self>, Error> {
+        let path = path(prefix, "error.json");
+        if !path.exists() {
+            return Ok(None);
+        }
+
+        serde_json::from_str(&read(&path)?)
+            .map(Some)
+            .map_err(|e| Error::Cassette(format!("invalid error on {:?}: {}", path, e)))
+    }
+
+    /// The error reported to the caller, with the status, the message found on the body, and the
+    /// delay asked by the provider before retrying.
+    fn to_error(&self) -> Error {
+        let retry_after =
+            crate::retry::retry_after(|name| self.headers.get(name).map(String::as_str));
+
+        Error::ApiError(ApiErrorDetails {
+            retry_after,
+            ..ApiErrorDetails::from_response(self.status, self.body.as_bytes())
+        })
+    }
+}
+
+/// Writes `event` in the server-sent events wire format.
+fn to_sse(event: &es::Event) -> String {
+    let mut sse = String::new();
+    if event.event_type != "message" {
+        sse.push_str(&format!("event: {}\n", event.event_type));
+    }
+    if let Some(id) = &event.id {
+        sse.push_str(&format!("id: {id}\n"));
+    }
+    for line in event.data.split('\n') {
+        sse.push_str(&format!("data: {line}\n"));
+    }
+    sse.push('\n');
+    sse
+}
+
+/// Parses the events saved by `to_sse`, or any other server-sent events stream.
+fn parse_sse(content: &str) -> Vec<es::Event> {
+    let mut events = Vec::new();
+    let mut event_type = String::new();
+    let mut data: Option<String> = None;
+    let mut id = None;
+
+    for line in content.lines().chain(std::iter::once("")) {
+        if line.is_empty() {
+            let event_type = std::mem::take(&mut event_type);
+            if let Some(data) = data.take() {
+                events.push(es::Event {
+                    event_type: if event_type.is_empty() {
+                        "message".to_string()
+                    } else {
+                        event_type
+                    },
+                    data,
+                    id: id.take(),
+                    retry: None,
+                });
+            }
+            continue;
+        }
+
+        let (field, value) = line.split_once(':').unwrap_or((line, ""));
+        let value = value.strip_prefix(' ').unwrap_or(value);
+        match field {
+            "event" => event_type = value.to_string(),
+            "id" => id = Some(value.to_string()),
+            "data" => match &mut data {
+                Some(data) => {
+                    data.push('\n');
+                    data.push_str(value);
+                }
+                None => data = Some(value.to_string()),
+            },
+            _ => {}
+        }
+    }
+
+    events
+}
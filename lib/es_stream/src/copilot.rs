@@ -0,0 +1,357 @@
+use eventsource_client as es;
+use futures::stream::{Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::cassette::Track;
+use crate::error::Error;
+use crate::event::{map_sse, StreamEvent};
+use crate::options::{ClientOptions, WithOptions};
+use crate::provider::{ChatProvider, ChatRequest, EventStream};
+use crate::requests::Json;
+use crate::secret::Secret;
+
+pub use crate::openai::{ChatCompletion, Message, MessageBody, Role};
+
+// Chat Completions Api
+const CHAT_API: &str = "/chat/completions";
+// Exchanges the GitHub OAuth token for a short-lived Copilot session token.
+const TOKEN_URL: &str = "https://api.github.com/copilot_internal/v2/token";
+
+// Copilot only accepts requests coming from a known editor integration.
+const EDITOR_VERSION: &str = "vscode/1.95.3";
+const EDITOR_PLUGIN_VERSION: &str = "copilot-chat/0.22.4";
+const INTEGRATION_ID: &str = "vscode-chat";
+const USER_AGENT: &str = "GitHubCopilotChat/0.22.4";
+
+// Session tokens are refreshed this many seconds before they expire.
+const EXPIRY_MARGIN: u64 = 60;
+
+/// Short-lived session token used to call the Copilot API.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SessionToken {
+    /// The session token.
+    pub token: Secret,
+    /// Unix timestamp (in seconds) of when the token expires.
+    pub expires_at: u64,
+}
+
+impl SessionToken {
+    /// Checks if the token can still be used.
+    #[must_use]
+    pub fn is_valid(&self) -> bool {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or(Duration::ZERO)
+            .as_secs();
+
+        now + EXPIRY_MARGIN < self.expires_at
+    }
+}
+
+/// Session token persisted on the token cache, next to a hash of the OAuth token it was
+/// exchanged for, so the cache is ignored once the OAuth token changes.
+#[derive(Debug, Serialize, Deserialize)]
+struct CachedToken {
+    oauth_token_sha256: String,
+    #[serde(flatten)]
+    session_token: SessionToken,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Auth {
+    /// Long-lived GitHub OAuth token with access to Copilot.
+    pub oauth_token: Secret,
+}
+
+impl Auth {
+    #[must_use]
+    pub fn new(oauth_token: impl Into<Secret>) -> Self {
+        Self {
+            oauth_token: oauth_token.into(),
+        }
+    }
+
+    pub fn from_env() -> Result<Self, Error> {
+        let oauth_token = match std::env::var("GITHUB_COPILOT_TOKEN") {
+            Ok(key) => key,
+            Err(_) => {
+                return Err(Error::AuthError(
+                    "GITHUB_COPILOT_TOKEN not found".to_string(),
+                ))
+            }
+        };
+        Ok(Self::new(oauth_token))
+    }
+
+    /// Reads the OAuth token stored by the official Copilot editor plugins on
+    /// `$XDG_CONFIG_HOME/github-copilot` (`~/.config/github-copilot` by default.)
+    pub fn from_copilot_config() -> Result<Self, Error> {
+        let config_dir = match std::env::var("XDG_CONFIG_HOME") {
+            Ok(dir) => PathBuf::from(dir),
+            Err(_) => match std::env::var("HOME") {
+                Ok(home) => PathBuf::from(home).join(".config"),
+                Err(_) => return Err(Error::AuthError("HOME not found".to_string())),
+            },
+        };
+
+        for file in ["hosts.json", "apps.json"] {
+            let Ok(content) = std::fs::read_to_string(config_dir.join("github-copilot").join(file))
+            else {
+                continue;
+            };
+            let json: Json = serde_json::from_str(&content)?;
+
+            let oauth_token = json
+                .as_object()
+                .into_iter()
+                .flatten()
+                .filter(|(host, _)| host.starts_with("github.com"))
+                .find_map(|(_, value)| value.get("oauth_token").and_then(Json::as_str));
+
+            if let Some(oauth_token) = oauth_token {
+                return Ok(Self::new(oauth_token));
+            }
+        }
+
+        Err(Error::AuthError(
+            "GitHub Copilot OAuth token not found".to_string(),
+        ))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Client {
+    pub auth: Auth,
+    pub api_url: String,
+    /// Url used to exchange the OAuth token for a session token.
+    pub token_url: String,
+    /// File where the session token is persisted so it can be reused across processes.
+    pub token_cache: Option<PathBuf>,
+    pub options: ClientOptions,
+    session_token: Arc<Mutex<Option<SessionToken>>>,
+}
+
+impl Client {
+    pub fn new(auth: Auth, api_url: impl Into<String>) -> Self {
+        Self {
+            auth,
+            api_url: api_url.into(),
+            token_url: TOKEN_URL.to_string(),
+            token_cache: None,
+            options: ClientOptions::default(),
+            session_token: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /// Persists the session token on `path` until it expires.
+    #[must_use]
+    pub fn with_token_cache(mut self, path: impl Into<PathBuf>) -> Self {
+        self.token_cache = Some(path.into());
+        self
+    }
+}
+
+impl WithOptions for Client {
+    fn options_mut(&mut self) -> &mut ClientOptions {
+        &mut self.options
+    }
+}
+
+impl Client {
+    /// Returns a valid session token, exchanging the OAuth token for a new one when the cached
+    /// token is missing or about to expire.
+    pub fn session_token(&self) -> Result<String, Error> {
+        let mut session_token = self
+            .session_token
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+
+        if let Some(token) = session_token.as_ref().filter(|token| token.is_valid()) {
+            return Ok(token.token.expose().to_string());
+        }
+
+        let token = match self.read_token_cache() {
+            Some(token) => token,
+            None => {
+                let token = self.exchange_token()?;
+                self.write_token_cache(&token);
+                token
+            }
+        };
+
+        let value = token.token.expose().to_string();
+        *session_token = Some(token);
+
+        Ok(value)
+    }
+
+    fn exchange_token(&self) -> Result<SessionToken, Error> {
+        log::debug!("exchanging oauth token for a session token");
+
+        let response = self
+            .options
+            .http
+            .get(&self.token_url)?
+            .set(
+                "authorization",
+                &format!("token {}", self.auth.oauth_token.expose()),
+            )
+            .set("accept", "application/json")
+            .set("editor-version", EDITOR_VERSION)
+            .set("editor-plugin-version", EDITOR_PLUGIN_VERSION)
+            .set("user-agent", USER_AGENT)
+            .call()?;
+
+        Ok(response.into_json()?)
+    }
+
+    fn read_token_cache(&self) -> Option<SessionToken> {
+        let content = std::fs::read_to_string(self.token_cache.as_ref()?).ok()?;
+
+        serde_json::from_str::<CachedToken>(&content)
+            .ok()
+            .filter(|cached| cached.oauth_token_sha256 == self.oauth_token_sha256())
+            .map(|cached| cached.session_token)
+            .filter(SessionToken::is_valid)
+    }
+
+    fn write_token_cache(&self, token: &SessionToken) {
+        let Some(path) = &self.token_cache else {
+            return;
+        };
+
+        let cached = CachedToken {
+            oauth_token_sha256: self.oauth_token_sha256(),
+            session_token: token.clone(),
+        };
+        let result = serde_json::to_string(&cached)
+            .map_err(Error::from)
+            .and_then(|content| write_private(path, &content).map_err(Error::from));
+
+        if let Err(e) = result {
+            log::warn!("unable to cache the session token on {:?}: {}", path, e);
+        }
+    }
+
+    /// Hex encoded SHA-256 hash of the OAuth token.
+    fn oauth_token_sha256(&self) -> String {
+        format!(
+            "{:x}",
+            Sha256::digest(self.auth.oauth_token.expose().as_bytes())
+        )
+    }
+
+    /// Headers of every request to the Copilot API.
+    fn headers(token: &str) -> [(&'static str, String); 7] {
+        [
+            ("content-type", "application/json".to_string()),
+            ("authorization", format!("Bearer {token}")),
+            ("editor-version", EDITOR_VERSION.to_string()),
+            ("editor-plugin-version", EDITOR_PLUGIN_VERSION.to_string()),
+            ("copilot-integration-id", INTEGRATION_ID.to_string()),
+            ("openai-intent", "conversation-panel".to_string()),
+            ("user-agent", USER_AGENT.to_string()),
+        ]
+    }
+
+    /// Returns the session token, unless the response is replayed from a cassette.
+    fn track_token(&self, track: &Track) -> Result<String, Error> {
+        // Replayed responses don't need a session token.
+        if track.is_replay() {
+            Ok(String::new())
+        } else {
+            self.session_token()
+        }
+    }
+
+    pub fn delta<'a>(
+        &'a self,
+        message_body: &MessageBody,
+    ) -> Result<impl Stream<Item = Result<StreamEvent, Error>> + 'a, Error> {
+        log::debug!("message_body: {:#?}", message_body);
+
+        let request_body = serde_json::to_value(message_body)?;
+        log::debug!("request_body: {:#?}", request_body);
+
+        let track = Track::new(self.options.cassette.as_ref());
+
+        let token = self.track_token(&track)?;
+
+        Ok(crate::retry::stream(self.options.retry, move || {
+            let stream = track.sse(&request_body, || -> Result<_, Error> {
+                let mut builder = es::ClientBuilder::for_url(&(self.api_url.clone() + CHAT_API))?;
+                for (name, value) in Self::headers(&token) {
+                    builder = builder.header(name, &value)?;
+                }
+                let builder = builder
+                    .method("POST".into())
+                    .body(request_body.to_string())
+                    .reconnect(crate::requests::no_reconnect());
+                let client = self.options.http.sse_client(builder)?;
+
+                Ok(crate::requests::tail(client))
+            })?;
+
+            Ok(map_sse(stream, crate::openai::chunk_parser()))
+        }))
+    }
+
+    /// Sends the request without streaming and returns the complete chat completion.
+    pub fn complete(&self, message_body: &MessageBody) -> Result<ChatCompletion, Error> {
+        log::debug!("message_body: {:#?}", message_body);
+
+        let mut request_body = serde_json::to_value(message_body)?;
+        request_body["stream"] = false.into();
+        if let Some(body) = request_body.as_object_mut() {
+            body.remove("stream_options");
+        }
+        log::debug!("request_body: {:#?}", request_body);
+
+        let track = Track::new(self.options.cassette.as_ref());
+        let token = self.track_token(&track)?;
+
+        let mut request = self.options.http.post(&(self.api_url.clone() + CHAT_API))?;
+        for (name, value) in Self::headers(&token) {
+            request = request.set(name, &value);
+        }
+
+        let response = crate::retry::blocking(self.options.retry, || {
+            track.json(&request_body, || {
+                request.clone().send_json(request_body.clone())
+            })
+        })?;
+        log::debug!("response: {:#?}", response);
+
+        Ok(serde_json::from_value(response)?)
+    }
+}
+
+/// Writes `content` to `path` making sure only the current user can read it.
+fn write_private(path: &std::path::Path, content: &str) -> std::io::Result<()> {
+    use std::io::Write;
+
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+
+    options.open(path)?.write_all(content.as_bytes())
+}
+
+impl ChatProvider for Client {
+    fn stream<'a>(&'a self, request: &ChatRequest) -> Result<EventStream<'a>, Error> {
+        let mut body = MessageBody::from(request);
+        // The Copilot API doesn't document `stream_options`, so usage is not requested.
+        body.stream_options = None;
+
+        Ok(self.delta(&body)?.boxed_local())
+    }
+}
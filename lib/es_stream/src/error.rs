@@ -1,3 +1,4 @@
+use std::time::Duration;
 use thiserror::Error;
 
 /// Error type returned from this library's functions
@@ -11,7 +12,7 @@ pub enum Error {
     AuthError(String),
     /// An Error returned by the API
     #[error("API Error: {0}")]
-    ApiError(String),
+    ApiError(ApiErrorDetails),
     /// An Error not related to the API
     #[error("Request Error: {0}")]
     RequestError(String),
@@ -21,4 +22,95 @@ pub enum Error {
     /// An Error occurred when performing an IO operation.
     #[error("io error: {0}")]
     IO(#[from] std::io::Error),
+    /// A recorded request can't be replayed.
+    #[error("cassette error: {0}")]
+    Cassette(String),
+}
+
+/// Details of an error reported by a provider, either as an HTTP response or in the middle of
+/// the stream.
+#[derive(Debug, Clone, Default)]
+pub struct ApiErrorDetails {
+    /// HTTP status code, if the API refused the connection.
+    pub status: Option<u16>,
+    /// Provider specific error type (e.g. `overloaded_error` or `INVALID_ARGUMENT`.)
+    pub kind: Option<String>,
+    /// Human readable error message.
+    pub message: String,
+    /// How long the provider asked to wait before retrying, read from the `Retry-After` or
+    /// `x-ratelimit-reset-*` headers.
+    pub retry_after: Option<Duration>,
+}
+
+impl ApiErrorDetails {
+    /// Creates a new `ApiErrorDetails` for an error reported in the middle of the stream.
+    #[must_use]
+    pub fn new(kind: Option<String>, message: impl Into<String>) -> Self {
+        Self {
+            kind,
+            message: message.into(),
+            ..Default::default()
+        }
+    }
+
+    /// Creates a new `ApiErrorDetails` from a failed HTTP response, looking for the error type and
+    /// message on the usual places providers put them on the JSON body.
+    pub(crate) fn from_response(status: u16, body: &[u8]) -> Self {
+        let json = match serde_json::from_slice::<serde_json::Value>(body) {
+            // Gemini wraps streaming errors in an array.
+            Ok(serde_json::Value::Array(mut values)) if !values.is_empty() => {
+                Some(values.swap_remove(0))
+            }
+            Ok(value) => Some(value),
+            Err(_) => None,
+        };
+        let error = json.as_ref().map(|json| json.get("error").unwrap_or(json));
+
+        let message = error
+            .and_then(|error| error.get("message").or(Some(error)))
+            .and_then(serde_json::Value::as_str)
+            .map_or_else(
+                || String::from_utf8_lossy(body).trim().to_string(),
+                String::from,
+            );
+        let kind = error
+            .and_then(|error| error.get("type").or_else(|| error.get("status")))
+            .and_then(serde_json::Value::as_str)
+            .map(String::from);
+
+        Self {
+            status: Some(status),
+            kind,
+            message,
+            retry_after: None,
+        }
+    }
+}
+
+impl std::fmt::Display for ApiErrorDetails {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(status) = self.status {
+            write!(f, "{status} ")?;
+        }
+        if let Some(kind) = &self.kind {
+            write!(f, "{kind}: ")?;
+        }
+        write!(f, "{}", self.message)
+    }
+}
+
+impl From<ureq::Error> for Error {
+    fn from(e: ureq::Error) -> Self {
+        match e {
+            ureq::Error::Status(status, response) => {
+                let retry_after = crate::retry::retry_after(|name| response.header(name));
+                let body = response.into_string().unwrap_or_default();
+                Error::ApiError(ApiErrorDetails {
+                    retry_after,
+                    ..ApiErrorDetails::from_response(status, body.as_bytes())
+                })
+            }
+            ureq::Error::Transport(transport) => Error::RequestError(transport.to_string()),
+        }
+    }
 }
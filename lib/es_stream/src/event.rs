@@ -0,0 +1,137 @@
+use eventsource_client as es;
+use futures::stream::{self, Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+
+use crate::error::Error;
+use crate::tool::ToolCall;
+
+/// Data payload used by OpenAI compatible APIs to signal the end of the stream.
+pub(crate) const DONE: &str = "[DONE]";
+
+/// Token usage reported by a provider.
+///
+/// Providers may report usage more than once per stream (e.g. input tokens when the message starts
+/// and output tokens when it ends), so consumers should `merge` every `StreamEvent::Usage` they
+/// receive.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TokenUsage {
+    /// Number of tokens in the prompt.
+    pub input_tokens: Option<u32>,
+    /// Number of tokens generated by the model.
+    pub output_tokens: Option<u32>,
+}
+
+impl TokenUsage {
+    /// Overrides the counters of `self` with the ones present on `other`.
+    pub fn merge(&mut self, other: TokenUsage) {
+        if other.input_tokens.is_some() {
+            self.input_tokens = other.input_tokens;
+        }
+        if other.output_tokens.is_some() {
+            self.output_tokens = other.output_tokens;
+        }
+    }
+}
+
+/// Provider agnostic event emitted by every `Client::delta` stream.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum StreamEvent {
+    /// The provider started a new message.
+    MessageStart {
+        /// Provider message identifier, if any.
+        id: Option<String>,
+        /// The model that is handling the request.
+        model: Option<String>,
+    },
+    /// A chunk of generated text.
+    TextDelta(String),
+    /// A tool call requested by the model. Emitted once the call arguments are complete.
+    ToolCall(ToolCall),
+    /// The reason the model stopped generating tokens.
+    StopReason(String),
+    /// Token usage for the request.
+    Usage(TokenUsage),
+    /// The stream finished. No more events will be emitted after this one.
+    Done,
+}
+
+/// Maps a raw SSE stream into a stream of `StreamEvent`.
+///
+/// `parse` is called with every SSE event and returns the `StreamEvent`s found on it, or the error
+/// reported by the provider. `parse` emits `StreamEvent::Done` on the terminal event of the
+/// provider, so a response that ends before it is reported as an unexpected EOF. Connection and
+/// comment events are dropped. The returned stream ends after the first `StreamEvent::Done` or
+/// error so the underlying client never tries to reconnect.
+pub(crate) fn map_sse<'a>(
+    stream: impl Stream<Item = Result<es::SSE, Error>> + 'a,
+    mut parse: impl FnMut(&es::Event) -> Result<Vec<StreamEvent>, Error> + 'a,
+) -> impl Stream<Item = Result<StreamEvent, Error>> + 'a {
+    stream
+        .flat_map(move |item| {
+            let events = match item {
+                Ok(es::SSE::Event(ev)) => match parse(&ev) {
+                    Ok(events) => events.into_iter().map(Ok).collect(),
+                    Err(e) => {
+                        log::error!("Error parsing event: {:#?}", ev);
+                        vec![Err(e)]
+                    }
+                },
+                Ok(es::SSE::Connected(_)) => Vec::new(),
+                Ok(es::SSE::Comment(comment)) => {
+                    log::debug!("Comment: {:#?}", comment);
+                    Vec::new()
+                }
+                Err(Error::EventsourceClient(es::Error::Eof)) => vec![Err(unexpected_eof())],
+                Err(e) => vec![Err(e)],
+            };
+            stream::iter(events)
+        })
+        .scan(false, |finished, item| {
+            if *finished {
+                return futures::future::ready(None);
+            }
+            *finished = matches!(item, Ok(StreamEvent::Done) | Err(_));
+            futures::future::ready(Some(item))
+        })
+}
+
+/// Maps the lines of a newline delimited JSON response into a stream of `StreamEvent`.
+///
+/// `parse` is called with every non-empty line and returns the `StreamEvent`s found on it, or the
+/// error reported by the provider. `parse` emits `StreamEvent::Done` on the terminal line of the
+/// provider, so a response that ends before it is reported as an unexpected EOF. The returned
+/// stream ends after the first `StreamEvent::Done` or error.
+pub(crate) fn map_lines<'a>(
+    lines: impl Stream<Item = Result<String, Error>> + 'a,
+    mut parse: impl FnMut(&str) -> Result<Vec<StreamEvent>, Error> + 'a,
+) -> impl Stream<Item = Result<StreamEvent, Error>> + 'a {
+    lines
+        .flat_map(move |line| {
+            let events = match line {
+                Ok(line) if line.trim().is_empty() => Vec::new(),
+                Ok(line) => match parse(&line) {
+                    Ok(events) => events.into_iter().map(Ok).collect(),
+                    Err(e) => {
+                        log::error!("Error parsing line: {:#?}", line);
+                        vec![Err(e)]
+                    }
+                },
+                Err(e) => vec![Err(e)],
+            };
+            stream::iter(events)
+        })
+        .chain(stream::once(futures::future::ready(Err(unexpected_eof()))))
+        .scan(false, |finished, item| {
+            if *finished {
+                return futures::future::ready(None);
+            }
+            *finished = matches!(item, Ok(StreamEvent::Done) | Err(_));
+            futures::future::ready(Some(item))
+        })
+}
+
+/// Error reported when the response ends before the terminal event of the provider.
+fn unexpected_eof() -> Error {
+    Error::EventsourceClient(es::Error::UnexpectedEof)
+}
@@ -1,14 +1,19 @@
 use eventsource_client as es;
 use futures::stream::{Stream, StreamExt};
 use serde::{Deserialize, Serialize};
-use std::time::Duration;
 
-use crate::error::Error;
+use crate::cassette::Track;
+use crate::error::{ApiErrorDetails, Error};
+use crate::event::{map_sse, StreamEvent, TokenUsage};
+use crate::options::{ClientOptions, WithOptions};
+use crate::provider::{ChatProvider, ChatRequest, EventStream};
 use crate::requests::{Json, Requests};
+use crate::secret::Secret;
+use crate::tool;
 
 // Chat Completions Api
-const STREAM_GENERATE_CONTENT_TEMPLATE: &str =
-    "/models/{{model}}:streamGenerateContent?alt=sse&key={{key}}";
+const STREAM_GENERATE_CONTENT_TEMPLATE: &str = "/models/{{model}}:streamGenerateContent?alt=sse";
+const GENERATE_CONTENT_TEMPLATE: &str = "/models/{{model}}:generateContent";
 
 #[derive(Debug, Serialize, Deserialize, Clone, Default)]
 #[serde(rename_all = "lowercase")]
@@ -36,6 +41,10 @@ pub struct GenerationConfig {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub response_mime_type: Option<String>,
 
+    /// Output schema of the generated candidate text. `response_mime_type` must be set to `application/json`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub response_schema: Option<Json>,
+
     /// Number of generated responses to return.
     pub candidate_count: Option<u32>,
 
@@ -74,7 +83,111 @@ pub struct GenerationConfig {
 #[serde(rename_all = "camelCase")]
 pub struct Part {
     /// Inline text.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
     pub text: String,
+
+    /// Inline media bytes.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub inline_data: Option<Blob>,
+
+    /// A predicted function call returned from the model.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub function_call: Option<FunctionCall>,
+
+    /// The result of a function call, sent back to the model.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub function_response: Option<FunctionResponse>,
+}
+
+impl Part {
+    /// Creates a new text `Part`
+    #[must_use]
+    pub fn text(text: impl Into<String>) -> Self {
+        Self {
+            text: text.into(),
+            ..Default::default()
+        }
+    }
+}
+
+impl From<&crate::provider::Attachment> for Part {
+    fn from(attachment: &crate::provider::Attachment) -> Self {
+        match attachment.text() {
+            Some(text) => Self::text(text),
+            None => Self {
+                inline_data: Some(Blob {
+                    mime_type: attachment.mime_type.clone(),
+                    data: attachment.data.clone(),
+                }),
+                ..Default::default()
+            },
+        }
+    }
+}
+
+/// Raw media bytes.
+#[derive(Debug, Serialize, Deserialize, Default, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Blob {
+    /// The IANA standard MIME type of the source data.
+    pub mime_type: String,
+
+    /// Base64 encoded raw bytes.
+    pub data: String,
+}
+
+/// A predicted function call returned from the model, with the arguments and their values.
+#[derive(Debug, Serialize, Deserialize, Default, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct FunctionCall {
+    /// The unique id of the function call, if the model assigned one.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+
+    /// The name of the function to call.
+    pub name: String,
+
+    /// The function parameters and values in JSON object format.
+    #[serde(default)]
+    pub args: Json,
+}
+
+/// The result of a function call.
+#[derive(Debug, Serialize, Deserialize, Default, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct FunctionResponse {
+    /// The id of the function call this response is for.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+
+    /// The name of the function that was called.
+    pub name: String,
+
+    /// The function response in JSON object format.
+    pub response: Json,
+}
+
+/// Structured representation of a function declaration.
+#[derive(Debug, Serialize, Deserialize, Default, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct FunctionDeclaration {
+    /// The name of the function.
+    pub name: String,
+
+    /// A brief description of the function.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+
+    /// Describes the parameters of this function, as an OpenAPI schema object.
+    pub parameters: Json,
+}
+
+/// Tool details that the model may use to generate a response.
+#[derive(Debug, Serialize, Deserialize, Default, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Tool {
+    /// A list of functions available to the model.
+    pub function_declarations: Vec<FunctionDeclaration>,
 }
 
 /// The base structured datatype containing multi-part content of a message.
@@ -82,6 +195,7 @@ pub struct Part {
 #[serde(rename_all = "camelCase")]
 pub struct Content {
     /// Ordered Parts that constitute a single message. Parts may have different MIME types.
+    #[serde(default)]
     pub parts: Vec<Part>,
 
     /// The producer of the content. Must be either 'user' or 'model'.
@@ -100,16 +214,139 @@ pub struct MessageBody {
 
     /// Configuration options for model generation and outputs.
     pub generation_config: Option<GenerationConfig>,
+
+    /// A list of tools the model may use to generate the next response.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tools: Option<Vec<Tool>>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
 pub struct Candidate {
+    /// Generated content returned from the model.
+    #[serde(default)]
     pub content: Content,
+
+    /// The reason why the model stopped generating tokens.
+    pub finish_reason: Option<String>,
+}
+
+/// Metadata on the generation request's token usage.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+#[serde(rename_all = "camelCase")]
+pub struct UsageMetadata {
+    /// Number of tokens in the prompt.
+    pub prompt_token_count: Option<u32>,
+
+    /// Total number of tokens across all the generated response candidates.
+    pub candidates_token_count: Option<u32>,
+
+    /// Total token count for the generation request (prompt + response candidates).
+    pub total_token_count: Option<u32>,
 }
 
+impl From<UsageMetadata> for TokenUsage {
+    fn from(usage: UsageMetadata) -> Self {
+        Self {
+            input_tokens: usage.prompt_token_count,
+            output_tokens: usage.candidates_token_count,
+        }
+    }
+}
+
+/// Error returned by the API.
 #[derive(Serialize, Deserialize, Debug)]
+pub struct Status {
+    /// HTTP status code.
+    pub code: Option<u16>,
+
+    /// Human readable error message.
+    pub message: String,
+
+    /// Error status name.
+    pub status: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
 pub struct Root {
+    /// Candidate responses from the model.
+    #[serde(default)]
     pub candidates: Vec<Candidate>,
+
+    /// Metadata on the generation requests' token usage.
+    pub usage_metadata: Option<UsageMetadata>,
+
+    /// The model version used to generate the response.
+    pub model_version: Option<String>,
+
+    /// Error details, if the API failed in the middle of the stream.
+    pub error: Option<Status>,
+}
+
+/// Creates a parser that maps Gemini SSE events into `StreamEvent`s.
+fn chunk_parser() -> impl FnMut(&es::Event) -> Result<Vec<StreamEvent>, Error> {
+    let mut started = false;
+    let mut tool_calls = 0;
+
+    move |ev| {
+        let root = serde_json::from_str::<Root>(&ev.data)?;
+
+        if let Some(error) = root.error {
+            return Err(Error::ApiError(ApiErrorDetails {
+                status: error.code,
+                kind: error.status,
+                message: error.message,
+                retry_after: None,
+            }));
+        }
+
+        let mut events = Vec::new();
+        let mut finished = false;
+
+        if !started {
+            started = true;
+            events.push(StreamEvent::MessageStart {
+                id: None,
+                model: root.model_version,
+            });
+        }
+
+        if let Some(candidate) = root.candidates.into_iter().next() {
+            for part in candidate.content.parts {
+                if !part.text.is_empty() {
+                    events.push(StreamEvent::TextDelta(part.text));
+                }
+                // Function calls are streamed complete. Older models don't assign them an id, so
+                // one is made up from the call position.
+                if let Some(call) = part.function_call {
+                    tool_calls += 1;
+                    events.push(StreamEvent::ToolCall(tool::ToolCall {
+                        id: call
+                            .id
+                            .unwrap_or_else(|| format!("{}-{}", call.name, tool_calls)),
+                        name: call.name,
+                        arguments: call.args,
+                    }));
+                }
+            }
+            if let Some(finish_reason) = candidate.finish_reason {
+                events.push(StreamEvent::StopReason(finish_reason));
+                finished = true;
+            }
+        }
+
+        if let Some(usage) = root.usage_metadata {
+            events.push(StreamEvent::Usage(usage.into()));
+        }
+
+        // The chunk with the finish reason is the last one of the response.
+        if finished {
+            events.push(StreamEvent::Done);
+        }
+
+        Ok(events)
+    }
 }
 
 impl MessageBody {
@@ -126,13 +363,15 @@ impl MessageBody {
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Auth {
-    pub api_key: String,
+    pub api_key: Secret,
 }
 
 impl Auth {
     #[must_use]
-    pub fn new(api_key: String) -> Self {
-        Self { api_key }
+    pub fn new(api_key: impl Into<Secret>) -> Self {
+        Self {
+            api_key: api_key.into(),
+        }
     }
 
     pub fn from_env() -> Result<Self, Error> {
@@ -140,7 +379,7 @@ impl Auth {
             Ok(key) => key,
             Err(_) => return Err(Error::AuthError("OPENAI_API_KEY not found".to_string())),
         };
-        Ok(Self { api_key })
+        Ok(Self::new(api_key))
     }
 }
 
@@ -148,6 +387,7 @@ impl Auth {
 pub struct Client {
     pub auth: Auth,
     pub api_url: String,
+    pub options: ClientOptions,
 }
 
 impl Client {
@@ -155,15 +395,22 @@ impl Client {
         Self {
             auth,
             api_url: api_url.into(),
+            options: ClientOptions::default(),
         }
     }
 }
 
+impl WithOptions for Client {
+    fn options_mut(&mut self) -> &mut ClientOptions {
+        &mut self.options
+    }
+}
+
 impl Client {
     pub fn delta<'a>(
         &'a self,
-        message_body: &'a MessageBody,
-    ) -> Result<impl Stream<Item = Result<String, Error>> + 'a, Error> {
+        message_body: &MessageBody,
+    ) -> Result<impl Stream<Item = Result<StreamEvent, Error>> + 'a, Error> {
         log::debug!("message_body: {:#?}", message_body);
 
         let request_body = match serde_json::to_value(message_body) {
@@ -175,36 +422,137 @@ impl Client {
         let sub_url =
             STREAM_GENERATE_CONTENT_TEMPLATE.replace("{{model}}", message_body.model.as_str());
 
-        let original_stream = match self.post_stream(sub_url, request_body) {
-            Ok(stream) => stream,
-            Err(e) => return Err(Error::EventsourceClient(e)),
-        };
+        let track = Track::new(self.options.cassette.as_ref());
 
-        let mapped_stream = original_stream.map(|item| {
-            if item.is_err() {
-                return Err(Error::EventsourceClient(item.err().unwrap()));
-            }
-            item.map(|event| match event {
-                es::SSE::Connected(_) => String::default(),
-                es::SSE::Event(ev) => match serde_json::from_str::<Root>(&ev.data) {
-                    Ok(root) => {
-                        if root.candidates[0].content.parts.is_empty() {
-                            String::default()
-                        } else {
-                            root.candidates[0].content.parts[0].text.clone()
-                        }
-                    }
-                    Err(_) => String::default(),
+        Ok(crate::retry::stream(self.options.retry, move || {
+            let stream = track.sse(&request_body, || {
+                self.post_stream(sub_url.clone(), request_body.clone())
+            })?;
+            Ok(map_sse(stream, chunk_parser()))
+        }))
+    }
+
+    /// Sends the request without streaming and returns the complete response.
+    pub fn complete(&self, message_body: &MessageBody) -> Result<Root, Error> {
+        log::debug!("message_body: {:#?}", message_body);
+
+        let request_body = serde_json::to_value(message_body)?;
+        log::debug!("request_body: {:#?}", request_body);
+
+        let sub_url = GENERATE_CONTENT_TEMPLATE.replace("{{model}}", message_body.model.as_str());
+
+        let response = self.post(sub_url, request_body)?;
+        log::debug!("response: {:#?}", response);
+
+        Ok(serde_json::from_value(response)?)
+    }
+}
+
+impl From<crate::provider::Role> for Role {
+    fn from(role: crate::provider::Role) -> Self {
+        match role {
+            crate::provider::Role::Assistant => Role::Model,
+            crate::provider::Role::User => Role::User,
+        }
+    }
+}
+
+impl From<&tool::Tool> for FunctionDeclaration {
+    fn from(tool: &tool::Tool) -> Self {
+        Self {
+            name: tool.name.clone(),
+            description: tool.description.clone(),
+            parameters: tool.parameters.clone(),
+        }
+    }
+}
+
+/// Returns `id`, unless it's empty.
+fn non_empty(id: &str) -> Option<String> {
+    (!id.is_empty()).then(|| id.to_string())
+}
+
+impl From<&crate::provider::Message> for Content {
+    /// Tool results are sent as `functionResponse` parts, followed by the attachments, the text
+    /// and the `functionCall` parts of the message. The call ids are sent back so the model can
+    /// match each response with its call.
+    fn from(message: &crate::provider::Message) -> Self {
+        let results = message.tool_results.iter().map(|result| Part {
+            function_response: Some(FunctionResponse {
+                id: non_empty(&result.tool_call_id),
+                name: result.name.clone(),
+                response: if result.is_error {
+                    serde_json::json!({ "error": result.content })
+                } else {
+                    serde_json::json!({ "content": result.content })
                 },
-                es::SSE::Comment(comment) => {
-                    log::debug!("Comment: {:#?}", comment);
-                    String::default()
-                }
-            })
-            .map_err(Error::from)
+            }),
+            ..Default::default()
+        });
+        let attachments = message.attachments.iter().map(Part::from);
+        let text = (!message.content.is_empty()
+            || (message.tool_results.is_empty()
+                && message.tool_calls.is_empty()
+                && message.attachments.is_empty()))
+        .then(|| Part::text(message.content.clone()));
+        let calls = message.tool_calls.iter().map(|call| Part {
+            function_call: Some(FunctionCall {
+                id: non_empty(&call.id),
+                name: call.name.clone(),
+                args: call.arguments.clone(),
+            }),
+            ..Default::default()
+        });
+
+        Self {
+            parts: results
+                .chain(attachments)
+                .chain(text)
+                .chain(calls)
+                .collect(),
+            role: message.role.into(),
+        }
+    }
+}
+
+impl From<&ChatRequest> for MessageBody {
+    /// The system prompt is sent as the first user message of the conversation.
+    fn from(request: &ChatRequest) -> Self {
+        let system = request.system.iter().map(|system| Content {
+            parts: vec![Part::text(system.clone())],
+            role: Role::User,
         });
+        let contents = system
+            .chain(request.messages.iter().map(Content::from))
+            .collect();
 
-        Ok(mapped_stream)
+        Self {
+            generation_config: Some(GenerationConfig {
+                stop_sequences: request.stop_sequences.clone(),
+                max_output_tokens: request.max_tokens,
+                temperature: request.temperature,
+                top_p: request.top_p,
+                top_k: request.top_k,
+                response_mime_type: request
+                    .response_schema
+                    .as_ref()
+                    .map(|_| "application/json".to_string()),
+                response_schema: request.response_schema.clone(),
+                ..Default::default()
+            }),
+            tools: request.tools.as_ref().map(|tools| {
+                vec![Tool {
+                    function_declarations: tools.iter().map(FunctionDeclaration::from).collect(),
+                }]
+            }),
+            ..Self::new(&request.model, contents)
+        }
+    }
+}
+
+impl ChatProvider for Client {
+    fn stream<'a>(&'a self, request: &ChatRequest) -> Result<EventStream<'a>, Error> {
+        Ok(self.delta(&MessageBody::from(request))?.boxed_local())
     }
 }
 
@@ -215,22 +563,30 @@ impl Requests for Client {
         body: Json,
     ) -> Result<impl Stream<Item = Result<es::SSE, es::Error>>, es::Error> {
         let url = &(self.api_url.clone() + &sub_url);
-        let url = url.replace("{{key}}", &self.auth.api_key);
 
-        let client = es::ClientBuilder::for_url(&url)?
+        let builder = es::ClientBuilder::for_url(url)?
             .header("content-type", "application/json")?
+            .header("x-goog-api-key", self.auth.api_key.expose())?
             .method("POST".into())
             .body(body.to_string())
-            .reconnect(
-                es::ReconnectOptions::reconnect(true)
-                    .retry_initial(false)
-                    .delay(Duration::from_secs(1))
-                    .backoff_factor(2)
-                    .delay_max(Duration::from_secs(60))
-                    .build(),
-            )
-            .build();
-
-        Ok(crate::requests::tail(&client))
+            .reconnect(crate::requests::no_reconnect());
+        let client = self.options.http.sse_client(builder)?;
+
+        Ok(crate::requests::tail(client))
+    }
+
+    fn post(&self, sub_url: String, body: Json) -> Result<Json, Error> {
+        let url = &(self.api_url.clone() + &sub_url);
+
+        let request = self
+            .options
+            .http
+            .post(url)?
+            .set("content-type", "application/json")
+            .set("x-goog-api-key", self.auth.api_key.expose());
+
+        crate::retry::blocking(self.options.retry, || {
+            crate::requests::send(request.clone(), body.clone())
+        })
     }
 }
@@ -1,7 +1,17 @@
 pub mod anthropic;
+pub mod cassette;
+pub mod copilot;
 pub mod error;
+pub mod event;
 pub mod google;
+pub mod http;
 pub mod mistral;
 pub mod mistral_fim;
+pub mod ollama;
 pub mod openai;
+pub mod options;
+pub mod provider;
 pub mod requests;
+pub mod retry;
+pub mod secret;
+pub mod tool;
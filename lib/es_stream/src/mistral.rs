@@ -1,26 +1,94 @@
 use eventsource_client as es;
 use futures::stream::{Stream, StreamExt};
 use serde::{Deserialize, Serialize};
-use std::time::Duration;
 
+use crate::cassette::Track;
 use crate::error::Error;
+use crate::event::{map_sse, StreamEvent, TokenUsage, DONE};
+use crate::options::{ClientOptions, WithOptions};
+use crate::provider::{ChatProvider, ChatRequest, EventStream};
 use crate::requests::{Json, Requests};
+use crate::secret::Secret;
+use crate::tool::{self, ToolCallBuffer};
+
+/// Mistral follows the OpenAI structured outputs format.
+pub use crate::openai::{JsonSchema, ResponseFormat};
 
 // Chat Completion API
 const CHAT_API: &str = "/chat/completions";
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
 pub struct Message {
     pub role: Role,
+    #[serde(default)]
     pub content: String,
+    /// Tool calls made by the assistant.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tool_calls: Option<Vec<ToolCall>>,
+    /// The id of the tool call this message answers. Only used by `tool` messages.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tool_call_id: Option<String>,
+    /// The name of the called function. Only used by `tool` messages.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+impl Message {
+    /// Creates a new `Message`
+    #[must_use]
+    pub fn new(role: Role, content: impl Into<String>) -> Self {
+        Self {
+            role,
+            content: content.into(),
+            ..Default::default()
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
 #[serde(rename_all = "lowercase")]
 pub enum Role {
     System,
     Assistant,
+    #[default]
     User,
+    Tool,
+}
+
+/// A function the model may call.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct Function {
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// JSON schema of the function parameters.
+    pub parameters: Json,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct Tool {
+    /// Always `function`.
+    pub r#type: String,
+    pub function: Function,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct FunctionCall {
+    #[serde(default)]
+    pub name: String,
+    /// JSON encoded arguments.
+    #[serde(default)]
+    pub arguments: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct ToolCall {
+    #[serde(default)]
+    pub id: String,
+    /// Position of the call, only sent while streaming.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub index: Option<usize>,
+    pub function: FunctionCall,
 }
 
 #[derive(Debug, Serialize, Deserialize, Default)]
@@ -50,6 +118,12 @@ pub struct MessageBody {
     /// The seed to use for random sampling. If set, different calls will generate deterministic results.
     #[serde(skip_serializing_if = "Option::is_none")]
     pub random_seed: Option<u32>,
+    /// A list of tools the model may call.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tools: Option<Vec<Tool>>,
+    /// An object specifying the format that the model must output.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub response_format: Option<ResponseFormat>,
 }
 
 impl MessageBody {
@@ -86,25 +160,115 @@ pub struct Choice {
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Delta {
     pub role: Option<String>,
-    pub content: String,
+    pub content: Option<String>,
+    pub tool_calls: Option<Vec<ToolCall>>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ChatCompletion {
+    pub id: String,
+    pub object: String,
+    pub created: u64,
+    pub model: String,
+    pub choices: Vec<CompletionChoice>,
+    pub usage: Usage,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
+pub struct CompletionChoice {
+    pub index: u32,
+    pub message: CompletionMessage,
+    pub finish_reason: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CompletionMessage {
+    pub role: String,
+    #[serde(default)]
+    pub content: String,
+    pub tool_calls: Option<Vec<ToolCall>>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy)]
 pub struct Usage {
     pub prompt_tokens: u32,
     pub total_tokens: u32,
     pub completion_tokens: u32,
 }
 
+impl From<Usage> for TokenUsage {
+    fn from(usage: Usage) -> Self {
+        Self {
+            input_tokens: Some(usage.prompt_tokens),
+            output_tokens: Some(usage.completion_tokens),
+        }
+    }
+}
+
+/// Creates a parser that maps Mistral SSE events into `StreamEvent`s.
+fn chunk_parser() -> impl FnMut(&es::Event) -> Result<Vec<StreamEvent>, Error> {
+    let mut started = false;
+    let mut tool_calls = ToolCallBuffer::default();
+
+    move |ev| {
+        if ev.data == DONE {
+            let mut events = tool_calls.events()?;
+            events.push(StreamEvent::Done);
+            return Ok(events);
+        }
+
+        let chunk = serde_json::from_str::<ChatCompletionChunk>(&ev.data)?;
+
+        let mut events = Vec::new();
+
+        if !started {
+            started = true;
+            events.push(StreamEvent::MessageStart {
+                id: Some(chunk.id),
+                model: Some(chunk.model),
+            });
+        }
+
+        if let Some(choice) = chunk.choices.into_iter().next() {
+            if let Some(content) = choice.delta.content.filter(|c| !c.is_empty()) {
+                events.push(StreamEvent::TextDelta(content));
+            }
+            // Mistral usually sends complete tool calls on a single chunk, without an index, so
+            // those are told apart by their id.
+            for call in choice.delta.tool_calls.unwrap_or_default() {
+                let index = call.index.unwrap_or_else(|| tool_calls.index_of(&call.id));
+                tool_calls.push(
+                    index,
+                    Some(call.id),
+                    Some(call.function.name),
+                    Some(&call.function.arguments),
+                );
+            }
+            if let Some(finish_reason) = choice.finish_reason {
+                events.extend(tool_calls.events()?);
+                events.push(StreamEvent::StopReason(finish_reason));
+            }
+        }
+
+        if let Some(usage) = chunk.usage {
+            events.push(StreamEvent::Usage(usage.into()));
+        }
+
+        Ok(events)
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Auth {
-    pub api_key: String,
+    pub api_key: Secret,
 }
 
 impl Auth {
     #[must_use]
-    pub fn new(api_key: String) -> Self {
-        Self { api_key }
+    pub fn new(api_key: impl Into<Secret>) -> Self {
+        Self {
+            api_key: api_key.into(),
+        }
     }
 
     pub fn from_env() -> Result<Self, Error> {
@@ -112,7 +276,7 @@ impl Auth {
             Ok(key) => key,
             Err(_) => return Err(Error::AuthError("MISTRAL_API_KEY not found".to_string())),
         };
-        Ok(Self { api_key })
+        Ok(Self::new(api_key))
     }
 }
 
@@ -120,6 +284,7 @@ impl Auth {
 pub struct Client {
     pub auth: Auth,
     pub api_url: String,
+    pub options: ClientOptions,
 }
 
 impl Client {
@@ -127,15 +292,22 @@ impl Client {
         Self {
             auth,
             api_url: api_url.into(),
+            options: ClientOptions::default(),
         }
     }
 }
 
+impl WithOptions for Client {
+    fn options_mut(&mut self) -> &mut ClientOptions {
+        &mut self.options
+    }
+}
+
 impl Client {
     pub fn delta<'a>(
         &'a self,
-        message_body: &'a MessageBody,
-    ) -> Result<impl Stream<Item = Result<String, Error>> + 'a, Error> {
+        message_body: &MessageBody,
+    ) -> Result<impl Stream<Item = Result<StreamEvent, Error>> + 'a, Error> {
         log::debug!("message_body: {:#?}", message_body);
 
         let request_body = match serde_json::to_value(message_body) {
@@ -144,36 +316,139 @@ impl Client {
         };
         log::debug!("request_body: {:#?}", request_body);
 
-        let original_stream = match self.post_stream(CHAT_API.to_string(), request_body) {
-            Ok(stream) => stream,
-            Err(e) => return Err(Error::EventsourceClient(e)),
-        };
+        let track = Track::new(self.options.cassette.as_ref());
 
-        let mapped_stream = original_stream.map(|item| {
-            if item.is_err() {
-                return Err(Error::EventsourceClient(item.err().unwrap()));
-            }
-            item.map(|event| match event {
-                es::SSE::Connected(_) => String::default(),
-                es::SSE::Event(ev) => match serde_json::from_str::<ChatCompletionChunk>(&ev.data) {
-                    Ok(chunk) => {
-                        if chunk.choices.is_empty() {
-                            String::default()
-                        } else {
-                            chunk.choices.first().unwrap().delta.content.clone()
-                        }
-                    }
-                    Err(_) => String::default(),
-                },
-                es::SSE::Comment(comment) => {
-                    log::debug!("Comment: {:#?}", comment);
-                    String::default()
-                }
-            })
-            .map_err(Error::from)
+        Ok(crate::retry::stream(self.options.retry, move || {
+            let stream = track.sse(&request_body, || {
+                self.post_stream(CHAT_API.to_string(), request_body.clone())
+            })?;
+            Ok(map_sse(stream, chunk_parser()))
+        }))
+    }
+
+    /// Sends the request without streaming and returns the complete chat completion.
+    pub fn complete(&self, message_body: &MessageBody) -> Result<ChatCompletion, Error> {
+        log::debug!("message_body: {:#?}", message_body);
+
+        let mut request_body = serde_json::to_value(message_body)?;
+        request_body["stream"] = false.into();
+        log::debug!("request_body: {:#?}", request_body);
+
+        let response = self.post(CHAT_API.to_string(), request_body)?;
+        log::debug!("response: {:#?}", response);
+
+        Ok(serde_json::from_value(response)?)
+    }
+}
+
+impl From<crate::provider::Role> for Role {
+    fn from(role: crate::provider::Role) -> Self {
+        match role {
+            crate::provider::Role::Assistant => Role::Assistant,
+            crate::provider::Role::User => Role::User,
+        }
+    }
+}
+
+impl From<&tool::Tool> for Tool {
+    fn from(tool: &tool::Tool) -> Self {
+        Self {
+            r#type: "function".to_string(),
+            function: Function {
+                name: tool.name.clone(),
+                description: tool.description.clone(),
+                parameters: tool.parameters.clone(),
+            },
+        }
+    }
+}
+
+impl From<&tool::ToolCall> for ToolCall {
+    fn from(call: &tool::ToolCall) -> Self {
+        Self {
+            id: call.id.clone(),
+            index: None,
+            function: FunctionCall {
+                name: call.name.clone(),
+                arguments: call.arguments.to_string(),
+            },
+        }
+    }
+}
+
+/// Maps a provider agnostic message into Mistral messages. Each tool result is sent as a
+/// separate `tool` message.
+fn messages(message: &crate::provider::Message) -> Vec<Message> {
+    let mut messages: Vec<Message> = message
+        .tool_results
+        .iter()
+        .map(|result| Message {
+            role: Role::Tool,
+            content: result.content.clone(),
+            tool_call_id: Some(result.tool_call_id.clone()),
+            name: Some(result.name.clone()),
+            ..Default::default()
+        })
+        .collect();
+
+    // Only text files can be sent to Mistral, so they are prepended to the content.
+    let mut content = Vec::new();
+    for attachment in &message.attachments {
+        match attachment.text() {
+            Some(text) => content.push(text),
+            None => log::warn!(
+                "mistral doesn't support {} attachments, skipping: {}",
+                attachment.mime_type,
+                attachment.name
+            ),
+        }
+    }
+
+    if message.tool_results.is_empty() || !message.content.is_empty() || !content.is_empty() {
+        if content.is_empty() || !message.content.is_empty() {
+            content.push(message.content.clone());
+        }
+        messages.push(Message {
+            role: message.role.into(),
+            content: content.join("\n\n"),
+            tool_calls: (!message.tool_calls.is_empty())
+                .then(|| message.tool_calls.iter().map(ToolCall::from).collect()),
+            ..Default::default()
         });
+    }
+
+    messages
+}
+
+impl From<&ChatRequest> for MessageBody {
+    fn from(request: &ChatRequest) -> Self {
+        let system = request
+            .system
+            .iter()
+            .map(|system| Message::new(Role::System, system.clone()));
+        let messages = system
+            .chain(request.messages.iter().flat_map(messages))
+            .collect();
+
+        Self {
+            max_tokens: request.max_tokens,
+            min_tokens: request.min_tokens,
+            stop: request.stop_sequences.clone(),
+            temperature: request.temperature,
+            top_p: request.top_p,
+            tools: request
+                .tools
+                .as_ref()
+                .map(|tools| tools.iter().map(Tool::from).collect()),
+            response_format: request.response_schema.as_ref().map(ResponseFormat::from),
+            ..Self::new(&request.model, messages)
+        }
+    }
+}
 
-        Ok(mapped_stream)
+impl ChatProvider for Client {
+    fn stream<'a>(&'a self, request: &ChatRequest) -> Result<EventStream<'a>, Error> {
+        Ok(self.delta(&MessageBody::from(request))?.boxed_local())
     }
 }
 
@@ -183,23 +458,31 @@ impl Requests for Client {
         sub_url: String,
         body: Json,
     ) -> Result<impl Stream<Item = Result<es::SSE, es::Error>>, es::Error> {
-        let authorization: &str = &format!("Bearer {}", self.auth.api_key);
+        let authorization: &str = &format!("Bearer {}", self.auth.api_key.expose());
 
-        let client = es::ClientBuilder::for_url(&(self.api_url.clone() + &sub_url))?
+        let builder = es::ClientBuilder::for_url(&(self.api_url.clone() + &sub_url))?
             .header("content-type", "application/json")?
             .header("authorization", authorization)?
             .method("POST".into())
             .body(body.to_string())
-            .reconnect(
-                es::ReconnectOptions::reconnect(true)
-                    .retry_initial(false)
-                    .delay(Duration::from_secs(1))
-                    .backoff_factor(2)
-                    .delay_max(Duration::from_secs(60))
-                    .build(),
-            )
-            .build();
-
-        Ok(crate::requests::tail(&client))
+            .reconnect(crate::requests::no_reconnect());
+        let client = self.options.http.sse_client(builder)?;
+
+        Ok(crate::requests::tail(client))
+    }
+
+    fn post(&self, sub_url: String, body: Json) -> Result<Json, Error> {
+        let authorization: &str = &format!("Bearer {}", self.auth.api_key.expose());
+
+        let request = self
+            .options
+            .http
+            .post(&(self.api_url.clone() + &sub_url))?
+            .set("content-type", "application/json")
+            .set("authorization", authorization);
+
+        crate::retry::blocking(self.options.retry, || {
+            crate::requests::send(request.clone(), body.clone())
+        })
     }
 }
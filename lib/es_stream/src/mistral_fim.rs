@@ -1,9 +1,12 @@
 use eventsource_client as es;
 use futures::stream::{Stream, StreamExt};
 use serde::{Deserialize, Serialize};
-use std::time::Duration;
 
+use crate::cassette::Track;
 use crate::error::Error;
+use crate::event::{map_sse, StreamEvent, DONE};
+use crate::options::{ClientOptions, WithOptions};
+use crate::provider::{ChatProvider, ChatRequest, EventStream};
 use crate::requests::{Json, Requests};
 
 // Fill in the Middle Completion API
@@ -62,6 +65,7 @@ pub struct FimCompletionsChunk {
     pub created: u64,
     pub model: String,
     pub choices: Vec<Choice>,
+    pub usage: Option<crate::mistral::Usage>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -77,12 +81,54 @@ pub struct Delta {
     pub content: String,
 }
 
+/// Creates a parser that maps Mistral FIM SSE events into `StreamEvent`s.
+fn chunk_parser() -> impl FnMut(&es::Event) -> Result<Vec<StreamEvent>, Error> {
+    let mut started = false;
+
+    move |ev| {
+        if ev.data == DONE {
+            return Ok(vec![StreamEvent::Done]);
+        }
+
+        let chunk = serde_json::from_str::<FimCompletionsChunk>(&ev.data)?;
+
+        let mut events = Vec::new();
+
+        if !started {
+            started = true;
+            events.push(StreamEvent::MessageStart {
+                id: Some(chunk.id),
+                model: Some(chunk.model),
+            });
+        }
+
+        if let Some(choice) = chunk.choices.into_iter().next() {
+            if !choice.delta.content.is_empty() {
+                events.push(StreamEvent::TextDelta(choice.delta.content));
+            }
+            if let Some(finish_reason) = choice.finish_reason {
+                events.push(StreamEvent::StopReason(finish_reason));
+            }
+        }
+
+        if let Some(usage) = chunk.usage {
+            events.push(StreamEvent::Usage(usage.into()));
+        }
+
+        Ok(events)
+    }
+}
+
 pub use crate::mistral::Auth;
 
+/// FIM completions share the response shape of the Mistral Chat Completion API.
+pub type FimCompletion = crate::mistral::ChatCompletion;
+
 #[derive(Debug, Clone)]
 pub struct Client {
     pub auth: Auth,
     pub api_url: String,
+    pub options: ClientOptions,
 }
 
 impl Client {
@@ -90,15 +136,22 @@ impl Client {
         Self {
             auth,
             api_url: api_url.into(),
+            options: ClientOptions::default(),
         }
     }
 }
 
+impl WithOptions for Client {
+    fn options_mut(&mut self) -> &mut ClientOptions {
+        &mut self.options
+    }
+}
+
 impl Client {
     pub fn delta<'a>(
         &'a self,
-        message_body: &'a MessageBody,
-    ) -> Result<impl Stream<Item = Result<String, Error>> + 'a, Error> {
+        message_body: &MessageBody,
+    ) -> Result<impl Stream<Item = Result<StreamEvent, Error>> + 'a, Error> {
         log::debug!("message_body: {:#?}", message_body);
 
         let request_body = match serde_json::to_value(message_body) {
@@ -107,36 +160,54 @@ impl Client {
         };
         log::debug!("request_body: {:#?}", request_body);
 
-        let original_stream = match self.post_stream(FIM_API.to_string(), request_body) {
-            Ok(stream) => stream,
-            Err(e) => return Err(Error::EventsourceClient(e)),
-        };
+        let track = Track::new(self.options.cassette.as_ref());
 
-        let mapped_stream = original_stream.map(|item| {
-            if item.is_err() {
-                return Err(Error::EventsourceClient(item.err().unwrap()));
-            }
-            item.map(|event| match event {
-                es::SSE::Connected(_) => String::default(),
-                es::SSE::Event(ev) => match serde_json::from_str::<FimCompletionsChunk>(&ev.data) {
-                    Ok(chunk) => {
-                        if chunk.choices.is_empty() {
-                            String::default()
-                        } else {
-                            chunk.choices.first().unwrap().delta.content.clone()
-                        }
-                    }
-                    Err(_) => String::default(),
-                },
-                es::SSE::Comment(comment) => {
-                    log::debug!("Comment: {:#?}", comment);
-                    String::default()
-                }
-            })
-            .map_err(Error::from)
-        });
-
-        Ok(mapped_stream)
+        Ok(crate::retry::stream(self.options.retry, move || {
+            let stream = track.sse(&request_body, || {
+                self.post_stream(FIM_API.to_string(), request_body.clone())
+            })?;
+            Ok(map_sse(stream, chunk_parser()))
+        }))
+    }
+
+    /// Sends the request without streaming and returns the complete completion.
+    pub fn complete(&self, message_body: &MessageBody) -> Result<FimCompletion, Error> {
+        log::debug!("message_body: {:#?}", message_body);
+
+        let mut request_body = serde_json::to_value(message_body)?;
+        request_body["stream"] = false.into();
+        log::debug!("request_body: {:#?}", request_body);
+
+        let response = self.post(FIM_API.to_string(), request_body)?;
+        log::debug!("response: {:#?}", response);
+
+        Ok(serde_json::from_value(response)?)
+    }
+}
+
+impl From<&ChatRequest> for MessageBody {
+    /// Uses the last message of the conversation as the prompt to complete.
+    fn from(request: &ChatRequest) -> Self {
+        let prompt = request
+            .messages
+            .last()
+            .map(|message| message.content.clone())
+            .unwrap_or_default();
+
+        Self {
+            max_tokens: request.max_tokens,
+            min_tokens: request.min_tokens,
+            stop: request.stop_sequences.clone(),
+            temperature: request.temperature,
+            top_p: request.top_p,
+            ..Self::new(&request.model, prompt, request.suffix.clone())
+        }
+    }
+}
+
+impl ChatProvider for Client {
+    fn stream<'a>(&'a self, request: &ChatRequest) -> Result<EventStream<'a>, Error> {
+        Ok(self.delta(&MessageBody::from(request))?.boxed_local())
     }
 }
 
@@ -146,23 +217,31 @@ impl Requests for Client {
         sub_url: String,
         body: Json,
     ) -> Result<impl Stream<Item = Result<es::SSE, es::Error>>, es::Error> {
-        let authorization: &str = &format!("Bearer {}", self.auth.api_key);
+        let authorization: &str = &format!("Bearer {}", self.auth.api_key.expose());
 
-        let client = es::ClientBuilder::for_url(&(self.api_url.clone() + &sub_url))?
+        let builder = es::ClientBuilder::for_url(&(self.api_url.clone() + &sub_url))?
             .header("content-type", "application/json")?
             .header("authorization", authorization)?
             .method("POST".into())
             .body(body.to_string())
-            .reconnect(
-                es::ReconnectOptions::reconnect(true)
-                    .retry_initial(false)
-                    .delay(Duration::from_secs(1))
-                    .backoff_factor(2)
-                    .delay_max(Duration::from_secs(60))
-                    .build(),
-            )
-            .build();
-
-        Ok(crate::requests::tail(&client))
+            .reconnect(crate::requests::no_reconnect());
+        let client = self.options.http.sse_client(builder)?;
+
+        Ok(crate::requests::tail(client))
+    }
+
+    fn post(&self, sub_url: String, body: Json) -> Result<Json, Error> {
+        let authorization: &str = &format!("Bearer {}", self.auth.api_key.expose());
+
+        let request = self
+            .options
+            .http
+            .post(&(self.api_url.clone() + &sub_url))?
+            .set("content-type", "application/json")
+            .set("authorization", authorization);
+
+        crate::retry::blocking(self.options.retry, || {
+            crate::requests::send(request.clone(), body.clone())
+        })
     }
 }
@@ -0,0 +1,386 @@
+use futures::stream::{Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+
+use crate::cassette::Track;
+use crate::error::{ApiErrorDetails, Error};
+use crate::event::{map_lines, StreamEvent, TokenUsage};
+use crate::options::{ClientOptions, WithOptions};
+use crate::provider::{ChatProvider, ChatRequest, EventStream};
+use crate::requests::Json;
+use crate::secret::Secret;
+use crate::tool;
+
+// Chat API
+const CHAT_API: &str = "/api/chat";
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    System,
+    Assistant,
+    #[default]
+    User,
+    Tool,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct Message {
+    pub role: Role,
+    pub content: String,
+    /// A list of base64 encoded images for multimodal models.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub images: Option<Vec<String>>,
+    /// Tool calls made by the assistant.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_calls: Option<Vec<ToolCall>>,
+    /// The name of the tool that produced the content. Only used by `tool` messages.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_name: Option<String>,
+}
+
+impl Message {
+    /// Creates a new `Message`
+    #[must_use]
+    pub fn new(role: Role, content: impl Into<String>) -> Self {
+        Self {
+            role,
+            content: content.into(),
+            ..Default::default()
+        }
+    }
+}
+
+/// A tool call made by the model. Ollama doesn't assign ids to the calls.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct ToolCall {
+    pub function: FunctionCall,
+}
+
+/// The function called by the model and its arguments.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct FunctionCall {
+    pub name: String,
+    #[serde(default)]
+    pub arguments: Json,
+}
+
+/// Additional model parameters.
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct Options {
+    /// The temperature of the model. Increasing the temperature will make the model answer more creatively.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f32>,
+    /// Works together with top-k. A higher value will lead to more diverse text, while a lower value will generate more focused and conservative text.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f32>,
+    /// Reduces the probability of generating nonsense. A higher value will give more diverse answers, while a lower value will be more conservative.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_k: Option<u32>,
+    /// Maximum number of tokens to predict when generating text.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub num_predict: Option<u32>,
+    /// Sets the stop sequences to use. When this pattern is encountered the LLM will stop generating text and return.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stop: Option<Vec<String>>,
+    /// Sets the random number seed to use for generation.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seed: Option<u32>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct MessageBody {
+    /// The model name.
+    pub model: String,
+    /// The messages of the chat, this can be used to keep a chat memory.
+    pub messages: Vec<Message>,
+    /// If false the response will be returned as a single response object, rather than a stream of objects.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stream: Option<bool>,
+    /// Additional model parameters.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub options: Option<Options>,
+    /// Controls how long the model will stay loaded into memory following the request.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub keep_alive: Option<String>,
+    /// The format to return a response in. Can be `json` or a JSON schema.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub format: Option<Json>,
+}
+
+impl MessageBody {
+    /// Creates a new `MessageBody`
+    #[must_use]
+    pub fn new(model: &str, messages: Vec<Message>) -> Self {
+        Self {
+            model: model.into(),
+            messages,
+            stream: Some(true),
+            ..Default::default()
+        }
+    }
+}
+
+/// A message generated by the model.
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct ResponseMessage {
+    pub role: String,
+    pub content: String,
+}
+
+/// A chat response. When streaming, every line of the response is a `ChatResponse` and the
+/// last one has `done` set to `true` along with the request statistics.
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct ChatResponse {
+    /// The model that handled the request.
+    #[serde(default)]
+    pub model: String,
+    /// Response creation time.
+    pub created_at: Option<String>,
+    /// Content generated by the model.
+    pub message: Option<ResponseMessage>,
+    /// Whether this is the last response of the stream.
+    #[serde(default)]
+    pub done: bool,
+    /// The reason the model stopped generating tokens.
+    pub done_reason: Option<String>,
+    /// Number of tokens in the prompt.
+    pub prompt_eval_count: Option<u32>,
+    /// Number of tokens in the response.
+    pub eval_count: Option<u32>,
+    /// Error message, if the model failed in the middle of the stream.
+    pub error: Option<String>,
+}
+
+/// Creates a parser that maps Ollama NDJSON lines into `StreamEvent`s.
+fn chunk_parser() -> impl FnMut(&str) -> Result<Vec<StreamEvent>, Error> {
+    let mut started = false;
+
+    move |line| {
+        let chunk = serde_json::from_str::<ChatResponse>(line)?;
+
+        if let Some(error) = chunk.error {
+            return Err(Error::ApiError(ApiErrorDetails::new(None, error)));
+        }
+
+        let mut events = Vec::new();
+
+        if !started {
+            started = true;
+            events.push(StreamEvent::MessageStart {
+                id: None,
+                model: Some(chunk.model),
+            });
+        }
+
+        if let Some(message) = chunk.message.filter(|m| !m.content.is_empty()) {
+            events.push(StreamEvent::TextDelta(message.content));
+        }
+
+        if chunk.done {
+            if let Some(done_reason) = chunk.done_reason {
+                events.push(StreamEvent::StopReason(done_reason));
+            }
+            events.push(StreamEvent::Usage(TokenUsage {
+                input_tokens: chunk.prompt_eval_count,
+                output_tokens: chunk.eval_count,
+            }));
+            events.push(StreamEvent::Done);
+        }
+
+        Ok(events)
+    }
+}
+
+/// Ollama doesn't require authentication, but the key is sent as a bearer token when present so
+/// the server can sit behind an authenticating proxy.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct Auth {
+    pub api_key: Option<Secret>,
+}
+
+impl Auth {
+    #[must_use]
+    pub fn new(api_key: Option<Secret>) -> Self {
+        Self { api_key }
+    }
+
+    pub fn from_env() -> Result<Self, Error> {
+        let api_key = std::env::var("OLLAMA_API_KEY").ok().map(Secret::from);
+        Ok(Self { api_key })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Client {
+    pub auth: Auth,
+    pub api_url: String,
+    pub options: ClientOptions,
+}
+
+impl Client {
+    pub fn new(auth: Auth, api_url: impl Into<String>) -> Self {
+        Self {
+            auth,
+            api_url: api_url.into(),
+            options: ClientOptions::default(),
+        }
+    }
+}
+
+impl WithOptions for Client {
+    fn options_mut(&mut self) -> &mut ClientOptions {
+        &mut self.options
+    }
+}
+
+impl Client {
+    /// Streams the response. Ollama streams newline delimited JSON instead of server-sent events,
+    /// so the lines are read from a blocking reader on a separate thread.
+    pub fn delta<'a>(
+        &'a self,
+        message_body: &MessageBody,
+    ) -> Result<impl Stream<Item = Result<StreamEvent, Error>> + 'a, Error> {
+        log::debug!("message_body: {:#?}", message_body);
+
+        let request_body = serde_json::to_value(message_body)?;
+        log::debug!("request_body: {:#?}", request_body);
+
+        let track = Track::new(self.options.cassette.as_ref());
+
+        Ok(crate::retry::stream(self.options.retry, move || {
+            let lines = crate::requests::stream_lines(
+                track.clone(),
+                self.request(CHAT_API)?,
+                request_body.clone(),
+            );
+            Ok(map_lines(lines, chunk_parser()))
+        }))
+    }
+
+    /// Sends the request without streaming and returns the complete response.
+    pub fn complete(&self, message_body: &MessageBody) -> Result<ChatResponse, Error> {
+        log::debug!("message_body: {:#?}", message_body);
+
+        let mut request_body = serde_json::to_value(message_body)?;
+        request_body["stream"] = false.into();
+        log::debug!("request_body: {:#?}", request_body);
+
+        let response: Json = crate::retry::blocking(self.options.retry, || {
+            crate::requests::send(self.request(CHAT_API)?, request_body.clone())
+        })?;
+        log::debug!("response: {:#?}", response);
+
+        Ok(serde_json::from_value(response)?)
+    }
+
+    fn request(&self, sub_url: &str) -> Result<ureq::Request, Error> {
+        let request = self
+            .options
+            .http
+            .post(&(self.api_url.clone() + sub_url))?
+            .set("content-type", "application/json");
+
+        Ok(match &self.auth.api_key {
+            Some(api_key) => request.set("authorization", &format!("Bearer {}", api_key.expose())),
+            None => request,
+        })
+    }
+}
+
+impl From<crate::provider::Role> for Role {
+    fn from(role: crate::provider::Role) -> Self {
+        match role {
+            crate::provider::Role::Assistant => Role::Assistant,
+            crate::provider::Role::User => Role::User,
+        }
+    }
+}
+
+impl From<&tool::ToolCall> for ToolCall {
+    fn from(call: &tool::ToolCall) -> Self {
+        Self {
+            function: FunctionCall {
+                name: call.name.clone(),
+                arguments: call.arguments.clone(),
+            },
+        }
+    }
+}
+
+/// Maps a provider agnostic message into Ollama messages. Each tool result is sent as a separate
+/// `tool` message. Images are sent on the `images` field and text files are prepended to the
+/// content. Other attachments aren't supported by Ollama and are dropped.
+fn messages(message: &crate::provider::Message) -> Vec<Message> {
+    let mut messages: Vec<Message> = message
+        .tool_results
+        .iter()
+        .map(|result| Message {
+            tool_name: Some(result.name.clone()),
+            ..Message::new(Role::Tool, result.content.clone())
+        })
+        .collect();
+
+    let mut content = Vec::new();
+    let mut images = Vec::new();
+
+    for attachment in &message.attachments {
+        match attachment.text() {
+            Some(text) => content.push(text),
+            None if attachment.is_image() => images.push(attachment.data.clone()),
+            None => log::warn!(
+                "ollama doesn't support {} attachments, skipping: {}",
+                attachment.mime_type,
+                attachment.name
+            ),
+        }
+    }
+
+    if message.tool_results.is_empty()
+        || !message.content.is_empty()
+        || !content.is_empty()
+        || !images.is_empty()
+    {
+        if content.is_empty() || !message.content.is_empty() {
+            content.push(message.content.clone());
+        }
+        messages.push(Message {
+            images: (!images.is_empty()).then_some(images),
+            tool_calls: (!message.tool_calls.is_empty())
+                .then(|| message.tool_calls.iter().map(ToolCall::from).collect()),
+            ..Message::new(message.role.into(), content.join("\n\n"))
+        });
+    }
+
+    messages
+}
+
+impl From<&ChatRequest> for MessageBody {
+    fn from(request: &ChatRequest) -> Self {
+        let system = request
+            .system
+            .iter()
+            .map(|system| Message::new(Role::System, system.clone()));
+        let messages = system
+            .chain(request.messages.iter().flat_map(messages))
+            .collect();
+
+        Self {
+            options: Some(Options {
+                temperature: request.temperature,
+                top_p: request.top_p,
+                top_k: request.top_k,
+                num_predict: request.max_tokens,
+                stop: request.stop_sequences.clone(),
+                ..Default::default()
+            }),
+            format: request.response_schema.clone(),
+            ..Self::new(&request.model, messages)
+        }
+    }
+}
+
+impl ChatProvider for Client {
+    fn stream<'a>(&'a self, request: &ChatRequest) -> Result<EventStream<'a>, Error> {
+        Ok(self.delta(&MessageBody::from(request))?.boxed_local())
+    }
+}
@@ -2,26 +2,161 @@ use eventsource_client as es;
 use futures::stream::{Stream, StreamExt};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
-use std::time::Duration;
 
-use crate::error::Error;
+use crate::cassette::Track;
+use crate::error::{ApiErrorDetails, Error};
+use crate::event::{map_sse, StreamEvent, TokenUsage, DONE};
+use crate::options::{ClientOptions, WithOptions};
+use crate::provider::{ChatProvider, ChatRequest, EventStream};
 use crate::requests::{Json, Requests};
+use crate::secret::Secret;
+use crate::tool::{self, ToolCallBuffer};
 
 // Chat Completions Api
 const CHAT_API: &str = "/chat/completions";
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
 #[serde(rename_all = "lowercase")]
 pub enum Role {
     System,
     Assistant,
+    #[default]
     User,
+    Tool,
+}
+
+/// An image referenced by URL or as a base64 encoded data URL.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct ImageUrl {
+    /// Either a URL of the image or the base64 encoded image data.
+    pub url: String,
+}
+
+/// A file sent as a base64 encoded data URL.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct FileData {
+    /// The name of the file.
+    pub filename: String,
+    /// The base64 encoded file data.
+    pub file_data: String,
 }
 
+/// A part of a message content.
 #[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ContentPart {
+    Text { text: String },
+    ImageUrl { image_url: ImageUrl },
+    File { file: FileData },
+}
+
+/// The content of a message, either a single string or a list of content parts.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(untagged)]
+pub enum MessageContent {
+    Text(String),
+    Parts(Vec<ContentPart>),
+}
+
+impl Default for MessageContent {
+    fn default() -> Self {
+        Self::Text(String::new())
+    }
+}
+
+impl From<String> for MessageContent {
+    fn from(text: String) -> Self {
+        Self::Text(text)
+    }
+}
+
+impl From<&str> for MessageContent {
+    fn from(text: &str) -> Self {
+        Self::Text(text.to_string())
+    }
+}
+
+impl From<&crate::provider::Attachment> for ContentPart {
+    fn from(attachment: &crate::provider::Attachment) -> Self {
+        match attachment.text() {
+            Some(text) => Self::Text { text },
+            None if attachment.is_image() => Self::ImageUrl {
+                image_url: ImageUrl {
+                    url: attachment.data_url(),
+                },
+            },
+            None => Self::File {
+                file: FileData {
+                    filename: attachment.name.clone(),
+                    file_data: attachment.data_url(),
+                },
+            },
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
 pub struct Message {
     pub role: Role,
-    pub content: String,
+    #[serde(default)]
+    pub content: MessageContent,
+    /// The tool calls generated by the model.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tool_calls: Option<Vec<ToolCall>>,
+    /// Tool call that this message is responding to.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tool_call_id: Option<String>,
+}
+
+impl Message {
+    /// Creates a new `Message`
+    #[must_use]
+    pub fn new(role: Role, content: impl Into<MessageContent>) -> Self {
+        Self {
+            role,
+            content: content.into(),
+            ..Default::default()
+        }
+    }
+}
+
+/// A function the model may call.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct FunctionDefinition {
+    /// The name of the function to be called.
+    pub name: String,
+    /// A description of what the function does, used by the model to choose when and how to call the function.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// The parameters the functions accepts, described as a JSON Schema object.
+    pub parameters: Json,
+}
+
+/// A tool the model may call. Currently, only functions are supported as a tool.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct Tool {
+    /// The type of the tool. Currently, only `function` is supported.
+    pub r#type: String,
+    pub function: FunctionDefinition,
+}
+
+/// The function that the model called.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct FunctionCall {
+    /// The name of the function to call.
+    pub name: String,
+    /// The arguments to call the function with, as generated by the model in JSON format.
+    pub arguments: String,
+}
+
+/// A tool call generated by the model.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct ToolCall {
+    /// The ID of the tool call.
+    pub id: String,
+    /// The type of the tool. Currently, only `function` is supported.
+    pub r#type: String,
+    pub function: FunctionCall,
 }
 
 #[derive(Debug, Serialize, Deserialize, Default)]
@@ -70,13 +205,17 @@ pub struct MessageBody {
     pub seed: Option<u32>,
 
     /// Up to 4 sequences where the API will stop generating further tokens.
-    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(rename = "stop", skip_serializing_if = "Option::is_none")]
     pub stop_sequences: Option<Vec<String>>,
 
     /// If set, partial message deltas will be sent, like in ChatGPT. Tokens will be sent as data-only server-sent events as they become available, with the stream terminated by a data: [DONE] message.
     #[serde(skip_serializing_if = "Option::is_none")]
     pub stream: Option<bool>,
 
+    /// Options for streaming response. Only set this when you set `stream: true`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stream_options: Option<StreamOptions>,
+
     /// What sampling temperature to use, between 0 and 2. Higher values like 0.8 will make the output more random, while lower values like 0.2 will make it more focused and deterministic.
     ///
     /// We generally recommend altering this or top_p but not both.
@@ -92,6 +231,47 @@ pub struct MessageBody {
     /// A unique identifier representing your end-user, which can help OpenAI to monitor and detect abuse.
     #[serde(skip_serializing_if = "Option::is_none")]
     pub user: Option<String>,
+
+    /// A list of tools the model may call.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tools: Option<Vec<Tool>>,
+
+    /// An object specifying the format that the model must output.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub response_format: Option<ResponseFormat>,
+}
+
+/// The format that the model must output.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ResponseFormat {
+    Text,
+    JsonObject,
+    JsonSchema { json_schema: JsonSchema },
+}
+
+/// A JSON schema used for structured outputs.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct JsonSchema {
+    /// The name of the response format.
+    pub name: String,
+    /// The schema for the response format, described as a JSON Schema object.
+    pub schema: Json,
+    /// Whether to enable strict schema adherence when generating the output.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub strict: Option<bool>,
+}
+
+impl From<&Json> for ResponseFormat {
+    fn from(schema: &Json) -> Self {
+        Self::JsonSchema {
+            json_schema: JsonSchema {
+                name: "response".to_string(),
+                schema: schema.clone(),
+                strict: None,
+            },
+        }
+    }
 }
 
 impl MessageBody {
@@ -107,11 +287,39 @@ impl MessageBody {
     }
 }
 
+/// Options for streaming response.
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct StreamOptions {
+    /// If set, an additional chunk will be streamed before the `data: [DONE]` message with the token usage statistics for the entire request.
+    pub include_usage: bool,
+}
+
+/// A fragment of the function called by the model.
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct FunctionCallChunk {
+    /// The name of the function, only sent on the first fragment.
+    name: Option<String>,
+    /// A fragment of the function arguments.
+    arguments: Option<String>,
+}
+
+/// A fragment of a tool call generated by the model.
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct ToolCallChunk {
+    /// The index of the tool call the fragment belongs to.
+    index: usize,
+    /// The ID of the tool call, only sent on the first fragment.
+    id: Option<String>,
+    function: Option<FunctionCallChunk>,
+}
+
 /// A chat completion delta generated by the streamed model responses.
 #[derive(Debug, Serialize, Deserialize, Default)]
 pub struct ChatCompletionChunkChoiceDelta {
     /// The contents of the chunk message.
     content: Option<String>,
+    /// Fragments of the tool calls generated by the model.
+    tool_calls: Option<Vec<ToolCallChunk>>,
 }
 
 /// Represents a content choice of a streamed chunk of a chat completion response returned by model, based on the provided input.
@@ -119,24 +327,161 @@ pub struct ChatCompletionChunkChoiceDelta {
 pub struct ChatCompletionChunkChoice {
     /// A chat completion delta generated by the streamed model responses.
     delta: ChatCompletionChunkChoiceDelta,
+    /// The reason the model stopped generating tokens.
+    finish_reason: Option<String>,
+}
+
+/// Usage statistics for the completion request.
+#[derive(Debug, Serialize, Deserialize, Default, Clone, Copy)]
+pub struct ChatCompletionUsage {
+    /// Number of tokens in the prompt.
+    pub prompt_tokens: u32,
+    /// Number of tokens in the generated completion.
+    pub completion_tokens: u32,
+    /// Total number of tokens used in the request (prompt + completion).
+    pub total_tokens: u32,
+}
+
+impl From<ChatCompletionUsage> for TokenUsage {
+    fn from(usage: ChatCompletionUsage) -> Self {
+        Self {
+            input_tokens: Some(usage.prompt_tokens),
+            output_tokens: Some(usage.completion_tokens),
+        }
+    }
+}
+
+/// An error returned by the API in the middle of the stream.
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct ChatCompletionError {
+    /// Human readable error message.
+    pub message: String,
+    /// Error type.
+    pub r#type: Option<String>,
 }
 
 /// Represents a streamed chunk of a chat completion response returned by model, based on the provided input.
 #[derive(Debug, Serialize, Deserialize, Default)]
 pub struct ChatCompletionChunk {
+    /// A unique identifier for the chat completion. Each chunk has the same ID.
+    pub id: Option<String>,
+    /// The model to generate the completion.
+    pub model: Option<String>,
     /// A list of chat completion choices. Can contain more than one elements if n is greater than 1. Can also be empty for the last chunk if you set stream_options: {"include_usage": true}.
+    #[serde(default)]
     pub choices: Vec<ChatCompletionChunkChoice>,
+    /// Usage statistics for the completion request. Only present on the last chunk when `stream_options.include_usage` is set.
+    pub usage: Option<ChatCompletionUsage>,
+    /// Error details, if the API failed in the middle of the stream.
+    pub error: Option<ChatCompletionError>,
+}
+
+/// A chat completion message generated by the model.
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct ChatCompletionMessage {
+    /// The role of the author of this message.
+    pub role: String,
+    /// The contents of the message.
+    pub content: Option<String>,
+    /// The tool calls generated by the model.
+    pub tool_calls: Option<Vec<ToolCall>>,
+}
+
+/// A chat completion choice.
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct ChatCompletionChoice {
+    /// The index of the choice in the list of choices.
+    pub index: u32,
+    /// A chat completion message generated by the model.
+    pub message: ChatCompletionMessage,
+    /// The reason the model stopped generating tokens.
+    pub finish_reason: Option<String>,
+}
+
+/// Represents a chat completion response returned by model, based on the provided input.
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct ChatCompletion {
+    /// A unique identifier for the chat completion.
+    pub id: String,
+    /// The object type, which is always `chat.completion`.
+    pub object: String,
+    /// The Unix timestamp (in seconds) of when the chat completion was created.
+    pub created: u64,
+    /// The model used for the chat completion.
+    pub model: String,
+    /// A list of chat completion choices. Can be more than one if n is greater than 1.
+    pub choices: Vec<ChatCompletionChoice>,
+    /// Usage statistics for the completion request.
+    pub usage: Option<ChatCompletionUsage>,
+}
+
+/// Creates a parser that maps OpenAI SSE events into `StreamEvent`s.
+pub(crate) fn chunk_parser() -> impl FnMut(&es::Event) -> Result<Vec<StreamEvent>, Error> {
+    let mut started = false;
+    let mut tool_calls = ToolCallBuffer::default();
+
+    move |ev| {
+        if ev.data == DONE {
+            let mut events = tool_calls.events()?;
+            events.push(StreamEvent::Done);
+            return Ok(events);
+        }
+
+        let chunk = serde_json::from_str::<ChatCompletionChunk>(&ev.data)?;
+
+        if let Some(error) = chunk.error {
+            return Err(Error::ApiError(ApiErrorDetails::new(
+                error.r#type,
+                error.message,
+            )));
+        }
+
+        let mut events = Vec::new();
+
+        if !started {
+            started = true;
+            events.push(StreamEvent::MessageStart {
+                id: chunk.id,
+                model: chunk.model,
+            });
+        }
+
+        if let Some(choice) = chunk.choices.into_iter().next() {
+            if let Some(content) = choice.delta.content.filter(|c| !c.is_empty()) {
+                events.push(StreamEvent::TextDelta(content));
+            }
+            for call in choice.delta.tool_calls.unwrap_or_default() {
+                let (name, arguments) = match call.function {
+                    Some(function) => (function.name, function.arguments),
+                    None => (None, None),
+                };
+                tool_calls.push(call.index, call.id, name, arguments.as_deref());
+            }
+            if let Some(finish_reason) = choice.finish_reason {
+                events.extend(tool_calls.events()?);
+                events.push(StreamEvent::StopReason(finish_reason));
+            }
+        }
+
+        if let Some(usage) = chunk.usage {
+            events.push(StreamEvent::Usage(usage.into()));
+        }
+
+        Ok(events)
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Auth {
-    pub api_key: String,
+    pub api_key: Secret,
 }
 
 impl Auth {
     #[must_use]
-    pub fn new(api_key: String) -> Self {
-        Self { api_key }
+    pub fn new(api_key: impl Into<Secret>) -> Self {
+        Self {
+            api_key: api_key.into(),
+        }
     }
 
     pub fn from_env() -> Result<Self, Error> {
@@ -144,7 +489,7 @@ impl Auth {
             Ok(key) => key,
             Err(_) => return Err(Error::AuthError("OPENAI_API_KEY not found".to_string())),
         };
-        Ok(Self { api_key })
+        Ok(Self::new(api_key))
     }
 }
 
@@ -152,6 +497,7 @@ impl Auth {
 pub struct Client {
     pub auth: Auth,
     pub api_url: String,
+    pub options: ClientOptions,
 }
 
 impl Client {
@@ -159,15 +505,22 @@ impl Client {
         Self {
             auth,
             api_url: api_url.into(),
+            options: ClientOptions::default(),
         }
     }
 }
 
+impl WithOptions for Client {
+    fn options_mut(&mut self) -> &mut ClientOptions {
+        &mut self.options
+    }
+}
+
 impl Client {
     pub fn delta<'a>(
         &'a self,
-        message_body: &'a MessageBody,
-    ) -> Result<impl Stream<Item = Result<String, Error>> + 'a, Error> {
+        message_body: &MessageBody,
+    ) -> Result<impl Stream<Item = Result<StreamEvent, Error>> + 'a, Error> {
         log::debug!("message_body: {:#?}", message_body);
 
         let request_body = match serde_json::to_value(message_body) {
@@ -176,36 +529,146 @@ impl Client {
         };
         log::debug!("request_body: {:#?}", request_body);
 
-        let original_stream = match self.post_stream(CHAT_API.to_string(), request_body) {
-            Ok(stream) => stream,
-            Err(e) => return Err(Error::EventsourceClient(e)),
-        };
+        let track = Track::new(self.options.cassette.as_ref());
 
-        let mapped_stream = original_stream.map(|item| {
-            if item.is_err() {
-                return Err(Error::EventsourceClient(item.err().unwrap()));
-            }
-            item.map(|event| match event {
-                es::SSE::Connected(_) => String::default(),
-                es::SSE::Event(ev) => match serde_json::from_str::<ChatCompletionChunk>(&ev.data) {
-                    Ok(mut chunk) => {
-                        if chunk.choices.is_empty() {
-                            String::default()
-                        } else {
-                            chunk.choices[0].delta.content.take().unwrap_or_default()
-                        }
-                    }
-                    Err(_) => String::default(),
-                },
-                es::SSE::Comment(comment) => {
-                    log::debug!("Comment: {:#?}", comment);
-                    String::default()
-                }
-            })
-            .map_err(Error::from)
+        Ok(crate::retry::stream(self.options.retry, move || {
+            let stream = track.sse(&request_body, || {
+                self.post_stream(CHAT_API.to_string(), request_body.clone())
+            })?;
+            Ok(map_sse(stream, chunk_parser()))
+        }))
+    }
+
+    /// Sends the request without streaming and returns the complete chat completion.
+    pub fn complete(&self, message_body: &MessageBody) -> Result<ChatCompletion, Error> {
+        log::debug!("message_body: {:#?}", message_body);
+
+        let mut request_body = serde_json::to_value(message_body)?;
+        request_body["stream"] = false.into();
+        if let Some(body) = request_body.as_object_mut() {
+            body.remove("stream_options");
+        }
+        log::debug!("request_body: {:#?}", request_body);
+
+        let response = self.post(CHAT_API.to_string(), request_body)?;
+        log::debug!("response: {:#?}", response);
+
+        Ok(serde_json::from_value(response)?)
+    }
+}
+
+impl From<crate::provider::Role> for Role {
+    fn from(role: crate::provider::Role) -> Self {
+        match role {
+            crate::provider::Role::Assistant => Role::Assistant,
+            crate::provider::Role::User => Role::User,
+        }
+    }
+}
+
+impl From<&tool::Tool> for Tool {
+    fn from(tool: &tool::Tool) -> Self {
+        Self {
+            r#type: "function".to_string(),
+            function: FunctionDefinition {
+                name: tool.name.clone(),
+                description: tool.description.clone(),
+                parameters: tool.parameters.clone(),
+            },
+        }
+    }
+}
+
+impl From<&tool::ToolCall> for ToolCall {
+    fn from(call: &tool::ToolCall) -> Self {
+        Self {
+            id: call.id.clone(),
+            r#type: "function".to_string(),
+            function: FunctionCall {
+                name: call.name.clone(),
+                arguments: call.arguments.to_string(),
+            },
+        }
+    }
+}
+
+/// Maps a provider agnostic message into OpenAI messages. Each tool result is sent as a separate
+/// `tool` message.
+fn messages(message: &crate::provider::Message) -> Vec<Message> {
+    let mut messages: Vec<Message> = message
+        .tool_results
+        .iter()
+        .map(|result| Message {
+            role: Role::Tool,
+            content: result.content.clone().into(),
+            tool_call_id: Some(result.tool_call_id.clone()),
+            ..Default::default()
+        })
+        .collect();
+
+    let content = if message.attachments.is_empty() {
+        MessageContent::Text(message.content.clone())
+    } else {
+        let text = (!message.content.is_empty()).then(|| ContentPart::Text {
+            text: message.content.clone(),
+        });
+        MessageContent::Parts(
+            message
+                .attachments
+                .iter()
+                .map(ContentPart::from)
+                .chain(text)
+                .collect(),
+        )
+    };
+
+    if message.tool_results.is_empty()
+        || !message.content.is_empty()
+        || !message.attachments.is_empty()
+    {
+        messages.push(Message {
+            role: message.role.into(),
+            content,
+            tool_calls: (!message.tool_calls.is_empty())
+                .then(|| message.tool_calls.iter().map(ToolCall::from).collect()),
+            ..Default::default()
         });
+    }
+
+    messages
+}
 
-        Ok(mapped_stream)
+impl From<&ChatRequest> for MessageBody {
+    fn from(request: &ChatRequest) -> Self {
+        let system = request
+            .system
+            .iter()
+            .map(|system| Message::new(Role::System, system.clone()));
+        let messages = system
+            .chain(request.messages.iter().flat_map(messages))
+            .collect();
+
+        Self {
+            stream_options: Some(StreamOptions {
+                include_usage: true,
+            }),
+            max_tokens: request.max_tokens,
+            stop_sequences: request.stop_sequences.clone(),
+            temperature: request.temperature,
+            top_p: request.top_p,
+            tools: request
+                .tools
+                .as_ref()
+                .map(|tools| tools.iter().map(Tool::from).collect()),
+            response_format: request.response_schema.as_ref().map(ResponseFormat::from),
+            ..Self::new(&request.model, messages)
+        }
+    }
+}
+
+impl ChatProvider for Client {
+    fn stream<'a>(&'a self, request: &ChatRequest) -> Result<EventStream<'a>, Error> {
+        Ok(self.delta(&MessageBody::from(request))?.boxed_local())
     }
 }
 
@@ -215,23 +678,31 @@ impl Requests for Client {
         sub_url: String,
         body: Json,
     ) -> Result<impl Stream<Item = Result<es::SSE, es::Error>>, es::Error> {
-        let authorization: &str = &format!("Bearer {}", self.auth.api_key);
+        let authorization: &str = &format!("Bearer {}", self.auth.api_key.expose());
 
-        let client = es::ClientBuilder::for_url(&(self.api_url.clone() + &sub_url))?
+        let builder = es::ClientBuilder::for_url(&(self.api_url.clone() + &sub_url))?
             .header("content-type", "application/json")?
             .header("authorization", authorization)?
             .method("POST".into())
             .body(body.to_string())
-            .reconnect(
-                es::ReconnectOptions::reconnect(true)
-                    .retry_initial(false)
-                    .delay(Duration::from_secs(1))
-                    .backoff_factor(2)
-                    .delay_max(Duration::from_secs(60))
-                    .build(),
-            )
-            .build();
-
-        Ok(crate::requests::tail(&client))
+            .reconnect(crate::requests::no_reconnect());
+        let client = self.options.http.sse_client(builder)?;
+
+        Ok(crate::requests::tail(client))
+    }
+
+    fn post(&self, sub_url: String, body: Json) -> Result<Json, Error> {
+        let authorization: &str = &format!("Bearer {}", self.auth.api_key.expose());
+
+        let request = self
+            .options
+            .http
+            .post(&(self.api_url.clone() + &sub_url))?
+            .set("content-type", "application/json")
+            .set("authorization", authorization);
+
+        crate::retry::blocking(self.options.retry, || {
+            crate::requests::send(request.clone(), body.clone())
+        })
     }
 }
@@ -0,0 +1,49 @@
+use crate::cassette::Cassette;
+use crate::http::HttpOptions;
+use crate::retry::RetryPolicy;
+
+/// Settings shared by every client: how failed requests are retried, how the requests reach the
+/// provider, and the cassette they are recorded on.
+#[derive(Debug, Clone)]
+pub struct ClientOptions {
+    pub retry: RetryPolicy,
+    pub http: HttpOptions,
+    pub cassette: Option<Cassette>,
+}
+
+impl Default for ClientOptions {
+    fn default() -> Self {
+        Self {
+            retry: RetryPolicy::default(),
+            http: HttpOptions::from_env(),
+            cassette: None,
+        }
+    }
+}
+
+/// Builder methods for the `ClientOptions` of a client.
+pub trait WithOptions: Sized {
+    /// Returns the options of the client.
+    fn options_mut(&mut self) -> &mut ClientOptions;
+
+    /// Sets the policy used to retry requests refused by the provider.
+    #[must_use]
+    fn with_retry_policy(mut self, retry: RetryPolicy) -> Self {
+        self.options_mut().retry = retry;
+        self
+    }
+
+    /// Sets the proxy, CA certificates and timeouts used by the requests.
+    #[must_use]
+    fn with_http_options(mut self, http: HttpOptions) -> Self {
+        self.options_mut().http = http;
+        self
+    }
+
+    /// Records the requests and responses on a cassette, or replays them from it.
+    #[must_use]
+    fn with_cassette(mut self, cassette: impl Into<Option<Cassette>>) -> Self {
+        self.options_mut().cassette = cassette.into();
+        self
+    }
+}
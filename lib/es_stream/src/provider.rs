@@ -0,0 +1,191 @@
+use base64::Engine;
+use futures::stream::LocalBoxStream;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+use crate::error::Error;
+use crate::event::StreamEvent;
+use crate::tool::{Tool, ToolCall, ToolResult};
+
+/// Stream of events returned by a `ChatProvider`.
+pub type EventStream<'a> = LocalBoxStream<'a, Result<StreamEvent, Error>>;
+
+/// The author of a message in a provider agnostic conversation.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    Assistant,
+    #[default]
+    User,
+}
+
+/// A file attached to a message, such as an image or a PDF document.
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
+pub struct Attachment {
+    /// File name, used to reference the file on the conversation.
+    pub name: String,
+    /// MIME type of the file.
+    pub mime_type: String,
+    /// Base64 encoded file contents.
+    pub data: String,
+}
+
+impl Attachment {
+    /// Creates a new `Attachment` from raw bytes.
+    #[must_use]
+    pub fn new(name: impl Into<String>, mime_type: impl Into<String>, bytes: &[u8]) -> Self {
+        Self {
+            name: name.into(),
+            mime_type: mime_type.into(),
+            data: base64::engine::general_purpose::STANDARD.encode(bytes),
+        }
+    }
+
+    /// Reads and encodes the file on `path`, guessing its MIME type from the extension. Files
+    /// with an unknown extension are sent as plain text when they hold UTF-8 text, and rejected
+    /// otherwise.
+    pub fn from_path(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let bytes = std::fs::read(path)?;
+        let mime_type = match mime_guess::from_path(path).first() {
+            Some(mime_type) => mime_type,
+            None if std::str::from_utf8(&bytes).is_ok_and(|text| !text.contains('\0')) => {
+                mime_guess::mime::TEXT_PLAIN
+            }
+            None => {
+                return Err(Error::RequestError(format!(
+                    "{} is application/octet-stream: only files with a known type or UTF-8 text can be attached",
+                    path.display()
+                )))
+            }
+        };
+        let name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default();
+
+        Ok(Self::new(name, mime_type.essence_str(), &bytes))
+    }
+
+    /// Checks if the attachment is an image.
+    #[must_use]
+    pub fn is_image(&self) -> bool {
+        self.mime_type.starts_with("image/")
+    }
+
+    /// Returns the attachment as a data URL.
+    #[must_use]
+    pub fn data_url(&self) -> String {
+        format!("data:{};base64,{}", self.mime_type, self.data)
+    }
+
+    /// Returns the contents of text attachments, prefixed by the file name, so they can be sent
+    /// as regular text to providers that don't accept text files.
+    #[must_use]
+    pub fn text(&self) -> Option<String> {
+        if !self.mime_type.starts_with("text/") {
+            return None;
+        }
+
+        let bytes = base64::engine::general_purpose::STANDARD
+            .decode(&self.data)
+            .ok()?;
+        let text = String::from_utf8(bytes).ok()?;
+
+        Some(format!("File: {}\n\n{}", self.name, text))
+    }
+}
+
+/// A provider agnostic conversation message.
+///
+/// Assistant messages carry the tool calls made by the model, and the results of running those
+/// tools are sent back on the following user message.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct Message {
+    pub role: Role,
+    pub content: String,
+    /// Files attached to the message.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub attachments: Vec<Attachment>,
+    /// Tool calls requested by the model.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tool_calls: Vec<ToolCall>,
+    /// Results of the tool calls made on the previous assistant message.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tool_results: Vec<ToolResult>,
+}
+
+impl Message {
+    /// Creates a new `Message`
+    #[must_use]
+    pub fn new(role: Role, content: impl Into<String>) -> Self {
+        Self {
+            role,
+            content: content.into(),
+            ..Default::default()
+        }
+    }
+
+    /// Creates a user message with the results of the previous tool calls.
+    #[must_use]
+    pub fn tool_results(tool_results: Vec<ToolResult>) -> Self {
+        Self {
+            role: Role::User,
+            tool_results,
+            ..Default::default()
+        }
+    }
+}
+
+/// Provider agnostic chat request.
+///
+/// Every `ChatProvider` maps this request into its own `MessageBody`, ignoring the options the
+/// provider doesn't support.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct ChatRequest {
+    /// The model that will complete the conversation.
+    pub model: String,
+    /// Conversation messages.
+    pub messages: Vec<Message>,
+    /// System prompt.
+    pub system: Option<String>,
+    /// The maximum number of tokens to generate before stopping.
+    pub max_tokens: Option<u32>,
+    /// The minimum number of tokens to generate before stopping.
+    pub min_tokens: Option<u32>,
+    /// Custom text sequences that will cause the model to stop generating.
+    pub stop_sequences: Option<Vec<String>>,
+    /// Amount of randomness injected into the response.
+    pub temperature: Option<f32>,
+    /// Use nucleus sampling.
+    pub top_p: Option<f32>,
+    /// Only sample from the top K options for each subsequent token.
+    pub top_k: Option<u32>,
+    /// Text that follows the completion. Only used by fill-in-the-middle providers.
+    pub suffix: Option<String>,
+    /// Tools the model may call.
+    pub tools: Option<Vec<Tool>>,
+    /// JSON schema the response must follow. Providers enforce it through their structured
+    /// output mode, but the response should still be validated by the caller.
+    pub response_schema: Option<serde_json::Value>,
+}
+
+impl ChatRequest {
+    /// Creates a new `ChatRequest`
+    #[must_use]
+    pub fn new(model: impl Into<String>, messages: Vec<Message>) -> Self {
+        Self {
+            model: model.into(),
+            messages,
+            ..Default::default()
+        }
+    }
+}
+
+/// A provider that can stream the response to a `ChatRequest`.
+pub trait ChatProvider {
+    /// # Errors
+    ///
+    /// Will return `Err` if the request can't be serialized or the stream can't be created.
+    fn stream<'a>(&'a self, request: &ChatRequest) -> Result<EventStream<'a>, Error>;
+}
@@ -1,6 +1,12 @@
 use eventsource_client as es;
+use futures::channel::mpsc;
 use futures::stream::Stream;
 
+use std::io::{BufRead, BufReader};
+
+use crate::cassette::Track;
+use crate::error::Error;
+
 pub type Json = serde_json::Value;
 
 pub trait Requests {
@@ -18,8 +24,75 @@ pub trait Requests {
         sub_url: String,
         body: Json,
     ) -> Result<impl Stream<Item = Result<es::SSE, es::Error>>, es::Error>;
+
+    /// # Errors
+    ///
+    /// Will return `Err` if:
+    ///
+    /// - The POST request fails to connect.
+    /// - The API responds with an error status.
+    /// - The response body is not valid JSON.
+    fn post(&self, sub_url: String, body: Json) -> Result<Json, Error>;
 }
 
-pub(crate) fn tail(client: &impl es::Client) -> impl Stream<Item = Result<es::SSE, es::Error>> {
+pub(crate) fn tail(client: Box<dyn es::Client>) -> impl Stream<Item = Result<es::SSE, es::Error>> {
     client.stream()
 }
+
+/// Sends `body` with `request` and parses the JSON response.
+pub(crate) fn send(request: ureq::Request, body: Json) -> Result<Json, Error> {
+    Ok(request.send_json(body)?.into_json()?)
+}
+
+/// Sends `body` with `request` and returns an iterator over the lines of the response.
+///
+/// The iterator reads from a blocking reader, so each call to `next` blocks the current thread
+/// until the next line arrives.
+pub(crate) fn send_lines(
+    request: ureq::Request,
+    body: Json,
+) -> Result<impl Iterator<Item = std::io::Result<String>>, ureq::Error> {
+    let response = request.send_json(body)?;
+
+    Ok(BufReader::new(response.into_reader()).lines())
+}
+
+/// Sends `body` with `request` on its own thread and streams the lines of the response, so
+/// neither the request nor the blocking reads stall the async runtime.
+///
+/// A failed request is the first and only item of the stream. The thread stops reading once the
+/// stream is dropped, and it's detached, so an interrupted stream doesn't keep the runtime from
+/// shutting down while a read is still blocked.
+pub(crate) fn stream_lines(
+    track: Track,
+    request: ureq::Request,
+    body: Json,
+) -> impl Stream<Item = Result<String, Error>> {
+    let (sender, receiver) = mpsc::unbounded();
+
+    std::thread::spawn(move || {
+        let lines = match track.lines(&body, || send_lines(request, body.clone())) {
+            Ok(lines) => lines,
+            Err(e) => {
+                let _ = sender.unbounded_send(Err(e));
+                return;
+            }
+        };
+
+        for line in lines {
+            if sender.unbounded_send(line.map_err(Error::IO)).is_err() {
+                break;
+            }
+        }
+    });
+
+    receiver
+}
+
+/// Reconnect options for chat streams.
+///
+/// Reconnecting would send the whole request again and duplicate the output, so failed
+/// connections are retried with a `RetryPolicy` instead, and only before the first event.
+pub(crate) fn no_reconnect() -> es::ReconnectOptions {
+    es::ReconnectOptions::reconnect(false).build()
+}
@@ -0,0 +1,206 @@
+use futures::stream::{self, LocalBoxStream, Stream, StreamExt};
+use std::time::Duration;
+
+use crate::error::Error;
+use crate::event::StreamEvent;
+
+/// HTTP status codes that are worth retrying. `529` is used by Anthropic when it's overloaded.
+const RETRYABLE_STATUS: [u16; 7] = [408, 429, 500, 502, 503, 504, 529];
+
+/// Error types that providers report inside a successful response when they are rate limited or
+/// overloaded.
+const RETRYABLE_KINDS: [&str; 4] = [
+    "overloaded_error",
+    "rate_limit_error",
+    "RESOURCE_EXHAUSTED",
+    "UNAVAILABLE",
+];
+
+/// Policy used to retry the initial request of a stream.
+///
+/// Only the connection is retried. Once the provider sends the first event the stream is never
+/// re-sent, since chat requests are not idempotent and a new request would duplicate the output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Maximum number of retries after the first attempt.
+    pub max_retries: u32,
+    /// Delay before the first retry.
+    pub initial_delay: Duration,
+    /// Factor applied to the delay after each retry.
+    pub backoff_factor: u32,
+    /// Maximum delay between retries. If the provider asks to wait longer than this, the error is
+    /// returned instead.
+    pub max_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 3,
+            initial_delay: Duration::from_secs(1),
+            backoff_factor: 2,
+            max_delay: Duration::from_secs(60),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// A policy that never retries.
+    #[must_use]
+    pub fn none() -> Self {
+        Self {
+            max_retries: 0,
+            ..Self::default()
+        }
+    }
+
+    /// Returns how long to wait before retrying after `error`, or `None` if the request shouldn't
+    /// be retried. `attempt` is the number of retries done so far.
+    #[must_use]
+    pub fn delay(&self, attempt: u32, error: &Error) -> Option<Duration> {
+        if attempt >= self.max_retries || !is_retryable(error) {
+            return None;
+        }
+
+        let backoff = self
+            .initial_delay
+            .saturating_mul(self.backoff_factor.saturating_pow(attempt));
+
+        let delay = match error {
+            Error::ApiError(details) => details.retry_after.unwrap_or(backoff),
+            _ => backoff,
+        };
+
+        (delay <= self.max_delay).then_some(delay)
+    }
+}
+
+/// Checks if `error` is a rate limit, an overloaded provider, or a connection failure.
+fn is_retryable(error: &Error) -> bool {
+    match error {
+        Error::ApiError(details) => {
+            details
+                .status
+                .is_some_and(|status| RETRYABLE_STATUS.contains(&status))
+                || details
+                    .kind
+                    .as_deref()
+                    .is_some_and(|kind| RETRYABLE_KINDS.contains(&kind))
+        }
+        Error::EventsourceClient(
+            eventsource_client::Error::TimedOut
+            | eventsource_client::Error::HttpStream(_)
+            | eventsource_client::Error::UnexpectedEof,
+        ) => true,
+        Error::RequestError(_) => true,
+        _ => false,
+    }
+}
+
+/// Reads how long the provider asked to wait before retrying from the response headers.
+///
+/// `Retry-After` (and OpenAI's `retry-after-ms`) take precedence over the `x-ratelimit-reset-*`
+/// headers, which hold durations like `1s` or `6m0s`.
+pub(crate) fn retry_after<'a>(header: impl Fn(&str) -> Option<&'a str>) -> Option<Duration> {
+    if let Some(ms) = header("retry-after-ms").and_then(|ms| ms.trim().parse::<f64>().ok()) {
+        return Some(Duration::from_secs_f64(ms.max(0.0) / 1000.0));
+    }
+
+    if let Some(secs) = header("retry-after").and_then(|secs| secs.trim().parse::<f64>().ok()) {
+        return Some(Duration::from_secs_f64(secs.max(0.0)));
+    }
+
+    ["x-ratelimit-reset-requests", "x-ratelimit-reset-tokens"]
+        .into_iter()
+        .filter_map(|name| header(name).and_then(parse_duration))
+        .max()
+}
+
+/// Parses durations like `20ms`, `1.5s` or `6m0s`.
+fn parse_duration(value: &str) -> Option<Duration> {
+    let mut total = 0.0;
+    let mut rest = value.trim();
+
+    while !rest.is_empty() {
+        let split = rest
+            .find(|c: char| !c.is_ascii_digit() && c != '.')
+            .unwrap_or(rest.len());
+        let number: f64 = rest[..split].parse().ok()?;
+        rest = &rest[split..];
+
+        let unit_len = rest
+            .find(|c: char| c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        let seconds = match &rest[..unit_len] {
+            "ms" => number / 1000.0,
+            "s" => number,
+            "m" => number * 60.0,
+            "h" => number * 3600.0,
+            _ => return None,
+        };
+        rest = &rest[unit_len..];
+
+        total += seconds;
+    }
+
+    Some(Duration::from_secs_f64(total))
+}
+
+/// Opens a stream with `connect`, opening it again when it fails before emitting any event and
+/// `policy` allows it.
+pub(crate) fn stream<'a, S>(
+    policy: RetryPolicy,
+    mut connect: impl FnMut() -> Result<S, Error> + 'a,
+) -> LocalBoxStream<'a, Result<StreamEvent, Error>>
+where
+    S: Stream<Item = Result<StreamEvent, Error>> + 'a,
+{
+    stream::once(async move {
+        let mut attempt = 0;
+
+        loop {
+            let mut events: LocalBoxStream<'a, _> = match connect() {
+                Ok(events) => events.boxed_local(),
+                Err(e) => return stream::once(async { Err(e) }).boxed_local(),
+            };
+
+            match events.next().await {
+                Some(Err(e)) => match policy.delay(attempt, &e) {
+                    Some(delay) => {
+                        log::warn!("retrying in {:?} after error: {}", delay, e);
+                        tokio::time::sleep(delay).await;
+                        attempt += 1;
+                    }
+                    None => return stream::once(async { Err(e) }).boxed_local(),
+                },
+                Some(event) => return stream::once(async { event }).chain(events).boxed_local(),
+                None => return stream::empty().boxed_local(),
+            }
+        }
+    })
+    .flatten()
+    .boxed_local()
+}
+
+/// Calls `send` until it succeeds or `policy` gives up, blocking the current thread between
+/// attempts.
+pub(crate) fn blocking<T>(
+    policy: RetryPolicy,
+    mut send: impl FnMut() -> Result<T, Error>,
+) -> Result<T, Error> {
+    let mut attempt = 0;
+
+    loop {
+        match send() {
+            Err(e) => match policy.delay(attempt, &e) {
+                Some(delay) => {
+                    log::warn!("retrying in {:?} after error: {}", delay, e);
+                    std::thread::sleep(delay);
+                    attempt += 1;
+                }
+                None => return Err(e),
+            },
+            result => return result,
+        }
+    }
+}
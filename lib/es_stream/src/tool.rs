@@ -0,0 +1,138 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use crate::error::Error;
+use crate::event::StreamEvent;
+use crate::requests::Json;
+
+/// A tool the model may call.
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct Tool {
+    /// The name of the tool.
+    pub name: String,
+    /// What the tool does, used by the model to decide when to call it.
+    pub description: Option<String>,
+    /// JSON schema of the tool arguments.
+    pub parameters: Json,
+}
+
+impl Tool {
+    /// Creates a new `Tool`
+    #[must_use]
+    pub fn new(name: impl Into<String>, description: Option<String>, parameters: Json) -> Self {
+        Self {
+            name: name.into(),
+            description,
+            parameters,
+        }
+    }
+}
+
+/// A tool call requested by the model.
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct ToolCall {
+    /// Identifier used to match the call with its result.
+    pub id: String,
+    /// The name of the tool to call.
+    pub name: String,
+    /// Arguments of the call, as generated by the model.
+    pub arguments: Json,
+}
+
+/// The result of running a tool, sent back to the model on a user message.
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct ToolResult {
+    /// Identifier of the `ToolCall` this result answers.
+    pub tool_call_id: String,
+    /// The name of the tool that was called.
+    pub name: String,
+    /// The output of the tool.
+    pub content: String,
+    /// Whether the tool failed.
+    #[serde(default)]
+    pub is_error: bool,
+}
+
+/// A tool call whose arguments are still being streamed.
+#[derive(Debug, Default)]
+struct PartialToolCall {
+    id: String,
+    name: String,
+    arguments: String,
+}
+
+/// Reassembles tool calls streamed in fragments, keyed by the index the provider assigns to each
+/// call.
+#[derive(Debug, Default)]
+pub(crate) struct ToolCallBuffer {
+    calls: BTreeMap<usize, PartialToolCall>,
+}
+
+impl ToolCallBuffer {
+    /// Adds a fragment to the call at `index`. The id and name are usually sent on the first
+    /// fragment only, while the arguments are appended as they arrive.
+    pub(crate) fn push(
+        &mut self,
+        index: usize,
+        id: Option<String>,
+        name: Option<String>,
+        arguments: Option<&str>,
+    ) {
+        let call = self.calls.entry(index).or_default();
+
+        if let Some(id) = id.filter(|id| !id.is_empty()) {
+            call.id = id;
+        }
+        if let Some(name) = name.filter(|name| !name.is_empty()) {
+            call.name = name;
+        }
+        if let Some(arguments) = arguments {
+            call.arguments.push_str(arguments);
+        }
+    }
+
+    /// Returns the index of the buffered call with `id`, or a new index if there is none, for
+    /// providers that don't index their calls. Fragments without an id belong to the last call.
+    pub(crate) fn index_of(&self, id: &str) -> usize {
+        let last = self.calls.keys().next_back().copied();
+
+        if id.is_empty() {
+            return last.unwrap_or_default();
+        }
+
+        self.calls
+            .iter()
+            .find(|(_, call)| call.id == id)
+            .map(|(index, _)| *index)
+            .unwrap_or_else(|| last.map_or(0, |last| last + 1))
+    }
+
+    /// Returns every buffered call with its arguments parsed, leaving the buffer empty.
+    fn take(&mut self) -> Result<Vec<ToolCall>, Error> {
+        std::mem::take(&mut self.calls)
+            .into_values()
+            .map(|call| {
+                let arguments = if call.arguments.trim().is_empty() {
+                    Json::Object(Default::default())
+                } else {
+                    serde_json::from_str(&call.arguments)?
+                };
+
+                Ok(ToolCall {
+                    id: call.id,
+                    name: call.name,
+                    arguments,
+                })
+            })
+            .collect()
+    }
+
+    /// Flushes the buffered calls as `StreamEvent::ToolCall` events.
+    pub(crate) fn events(&mut self) -> Result<Vec<StreamEvent>, Error> {
+        Ok(self
+            .take()?
+            .into_iter()
+            .map(StreamEvent::ToolCall)
+            .collect())
+    }
+}
@@ -0,0 +1,125 @@
+use es_stream::cassette::Cassette;
+use es_stream::error::Error;
+use es_stream::event::StreamEvent;
+use es_stream::http::HttpOptions;
+use es_stream::openai;
+use es_stream::options::WithOptions;
+use es_stream::retry::RetryPolicy;
+use futures::stream::TryStreamExt;
+use mock_provider::{fixtures, MockServer, Response};
+
+fn body(prompt: &str) -> openai::MessageBody {
+    openai::MessageBody::new(
+        "gpt-4o",
+        vec![openai::Message::new(openai::Role::User, prompt)],
+    )
+}
+
+#[tokio::test]
+async fn record_and_replay() {
+    let dir = std::env::temp_dir().join(format!("es-stream-cassette-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+
+    let server = MockServer::start();
+    server.mock("/chat/completions", Response::sse(fixtures::OPENAI));
+
+    let client = openai::Client::new(openai::Auth::new("test-key"), server.url())
+        .with_http_options(HttpOptions::default())
+        .with_cassette(Cassette::record(&dir));
+    let recorded: Vec<StreamEvent> = client
+        .delta(&body("Hi"))
+        .unwrap()
+        .try_collect()
+        .await
+        .unwrap();
+    drop(server);
+
+    let request = std::fs::read_to_string(dir.join("001.request.json")).unwrap();
+    assert_eq!(
+        serde_json::from_str::<serde_json::Value>(&request).unwrap(),
+        serde_json::to_value(body("Hi")).unwrap()
+    );
+
+    // Nothing is listening on the url anymore, so the events must come from the cassette.
+    let client = openai::Client::new(openai::Auth::new(""), "http://127.0.0.1:1")
+        .with_cassette(Cassette::replay(&dir));
+    let replayed: Vec<StreamEvent> = client
+        .delta(&body("Hi"))
+        .unwrap()
+        .try_collect()
+        .await
+        .unwrap();
+
+    assert_eq!(replayed, recorded);
+}
+
+#[tokio::test]
+async fn record_and_replay_errors() {
+    let dir = std::env::temp_dir().join(format!("es-stream-cassette-error-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+
+    let server = MockServer::start();
+    server.mock(
+        "/chat/completions",
+        Response::json(429, r#"{"error":{"message":"Rate limit reached"}}"#),
+    );
+
+    let client = openai::Client::new(openai::Auth::new("test-key"), server.url())
+        .with_http_options(HttpOptions::default())
+        .with_retry_policy(RetryPolicy::none())
+        .with_cassette(Cassette::record(&dir));
+    let recorded = client
+        .delta(&body("Hi"))
+        .unwrap()
+        .try_collect::<Vec<_>>()
+        .await;
+    drop(server);
+    assert!(matches!(recorded, Err(Error::ApiError(ref e)) if e.status == Some(429)));
+    assert!(dir.join("001.error.json").exists());
+
+    let client = openai::Client::new(openai::Auth::new(""), "http://127.0.0.1:1")
+        .with_retry_policy(RetryPolicy::none())
+        .with_cassette(Cassette::replay(&dir));
+    let replayed = client
+        .delta(&body("Hi"))
+        .unwrap()
+        .try_collect::<Vec<_>>()
+        .await;
+
+    match replayed {
+        Err(Error::ApiError(e)) => {
+            assert_eq!(e.status, Some(429));
+            assert!(e.message.contains("Rate limit reached"), "{}", e.message);
+        }
+        other => panic!("expected the recorded error, got {other:?}"),
+    }
+}
+
+#[tokio::test]
+async fn replay_rejects_a_different_request() {
+    let dir = std::env::temp_dir().join(format!("es-stream-cassette-diff-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+
+    let server = MockServer::start();
+    server.mock("/chat/completions", Response::sse(fixtures::OPENAI));
+
+    let client = openai::Client::new(openai::Auth::new("test-key"), server.url())
+        .with_http_options(HttpOptions::default())
+        .with_cassette(Cassette::record(&dir));
+    let _: Vec<StreamEvent> = client
+        .delta(&body("Hi"))
+        .unwrap()
+        .try_collect()
+        .await
+        .unwrap();
+
+    let client = openai::Client::new(openai::Auth::new(""), "http://127.0.0.1:1")
+        .with_cassette(Cassette::replay(&dir));
+    let replayed = client
+        .delta(&body("Bye"))
+        .unwrap()
+        .try_collect::<Vec<_>>()
+        .await;
+
+    assert!(matches!(replayed, Err(Error::Cassette(_))), "{replayed:?}");
+}
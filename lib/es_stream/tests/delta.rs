@@ -0,0 +1,415 @@
+use es_stream::error::Error;
+use es_stream::event::{StreamEvent, TokenUsage};
+use es_stream::http::HttpOptions;
+use es_stream::options::WithOptions;
+use es_stream::provider::{Attachment, ChatRequest};
+use es_stream::retry::RetryPolicy;
+use es_stream::tool::Tool;
+use es_stream::{anthropic, copilot, google, mistral, mistral_fim, ollama, openai};
+use futures::stream::{Stream, StreamExt, TryStreamExt};
+use mock_provider::{fixtures, MockServer, Response};
+
+async fn collect(stream: impl Stream<Item = Result<StreamEvent, Error>>) -> Vec<StreamEvent> {
+    stream.try_collect().await.expect("the stream failed")
+}
+
+fn text(events: &[StreamEvent]) -> String {
+    events
+        .iter()
+        .filter_map(|event| match event {
+            StreamEvent::TextDelta(text) => Some(text.as_str()),
+            _ => None,
+        })
+        .collect()
+}
+
+fn usage(events: &[StreamEvent]) -> TokenUsage {
+    let mut total = TokenUsage::default();
+    for event in events {
+        if let StreamEvent::Usage(usage) = event {
+            total.merge(*usage);
+        }
+    }
+    total
+}
+
+fn stop_reason(events: &[StreamEvent]) -> Option<&str> {
+    events.iter().find_map(|event| match event {
+        StreamEvent::StopReason(reason) => Some(reason.as_str()),
+        _ => None,
+    })
+}
+
+#[tokio::test]
+async fn openai_delta() {
+    let server = MockServer::start();
+    server.mock("/chat/completions", Response::sse(fixtures::OPENAI));
+
+    let client = openai::Client::new(openai::Auth::new("test-key"), server.url())
+        .with_http_options(HttpOptions::default());
+    let body = openai::MessageBody::new(
+        "gpt-4o",
+        vec![openai::Message::new(openai::Role::User, "Hi")],
+    );
+    let events = collect(client.delta(&body).unwrap()).await;
+
+    assert_eq!(text(&events), "Hello world");
+    assert_eq!(stop_reason(&events), Some("stop"));
+    assert_eq!(usage(&events).input_tokens, Some(9));
+    assert_eq!(usage(&events).output_tokens, Some(2));
+    assert_eq!(events.last(), Some(&StreamEvent::Done));
+
+    let requests = server.requests();
+    assert_eq!(requests.len(), 1);
+    assert_eq!(requests[0].header("authorization"), Some("Bearer test-key"));
+    assert_eq!(requests[0].json()["model"], "gpt-4o");
+}
+
+#[tokio::test]
+async fn anthropic_delta() {
+    let server = MockServer::start();
+    server.mock("/messages", Response::sse(fixtures::ANTHROPIC));
+
+    let client = anthropic::Client::new(anthropic::Auth::new("test-key", None), server.url())
+        .with_http_options(HttpOptions::default());
+    let body = anthropic::MessageBody::new(
+        "claude-3-5-sonnet-20240620",
+        vec![anthropic::Message {
+            role: anthropic::Role::User,
+            content: "Hi".into(),
+        }],
+        100,
+    );
+    let events = collect(client.delta(&body).unwrap()).await;
+
+    assert_eq!(text(&events), "Hello world");
+    assert_eq!(stop_reason(&events), Some("end_turn"));
+    assert_eq!(usage(&events).input_tokens, Some(9));
+    assert_eq!(usage(&events).output_tokens, Some(2));
+    assert_eq!(events.last(), Some(&StreamEvent::Done));
+
+    let requests = server.requests();
+    assert_eq!(requests[0].header("x-api-key"), Some("test-key"));
+    assert_eq!(requests[0].json()["max_tokens"], 100);
+}
+
+#[tokio::test]
+async fn google_delta() {
+    let server = MockServer::start();
+    server.mock(
+        "/models/gemini-1.5-pro:streamGenerateContent",
+        Response::sse(fixtures::GOOGLE),
+    );
+
+    let client = google::Client::new(google::Auth::new("test-key"), server.url())
+        .with_http_options(HttpOptions::default());
+    let body = google::MessageBody::new(
+        "gemini-1.5-pro",
+        vec![google::Content {
+            parts: vec![google::Part::text("Hi")],
+            role: google::Role::User,
+        }],
+    );
+    let events = collect(client.delta(&body).unwrap()).await;
+
+    assert_eq!(text(&events), "Hello world");
+    assert_eq!(stop_reason(&events), Some("STOP"));
+    assert_eq!(usage(&events).output_tokens, Some(2));
+    assert_eq!(events.last(), Some(&StreamEvent::Done));
+
+    let requests = server.requests();
+    assert_eq!(requests[0].header("x-goog-api-key"), Some("test-key"));
+    assert!(!requests[0].path.contains("test-key"));
+}
+
+#[tokio::test]
+async fn mistral_delta() {
+    let server = MockServer::start();
+    server.mock("/chat/completions", Response::sse(fixtures::MISTRAL));
+
+    let client = mistral::Client::new(mistral::Auth::new("test-key"), server.url())
+        .with_http_options(HttpOptions::default());
+    let body = mistral::MessageBody::new(
+        "mistral-large-latest",
+        vec![mistral::Message::new(mistral::Role::User, "Hi")],
+    );
+    let events = collect(client.delta(&body).unwrap()).await;
+
+    assert_eq!(text(&events), "Hello world");
+    assert_eq!(stop_reason(&events), Some("stop"));
+    assert_eq!(usage(&events).output_tokens, Some(2));
+    assert_eq!(events.last(), Some(&StreamEvent::Done));
+
+    assert_eq!(
+        server.requests()[0].header("authorization"),
+        Some("Bearer test-key")
+    );
+}
+
+#[tokio::test]
+async fn mistral_tool_calls_without_index() {
+    let call = |id: &str, city: &str| {
+        format!(
+            r#"data: {{"id":"cmpl-e5c","object":"chat.completion.chunk","created":1727000000,"model":"mistral-large-latest","choices":[{{"index":0,"delta":{{"tool_calls":[{{"id":"{id}","function":{{"name":"weather","arguments":"{{\"city\":\"{city}\"}}"}}}}]}},"finish_reason":null}}]}}"#
+        )
+    };
+    let body = format!(
+        "{}\n\n{}\n\ndata: [DONE]\n\n",
+        call("call-1", "Paris"),
+        call("call-2", "Rome")
+    );
+
+    let server = MockServer::start();
+    server.mock("/chat/completions", Response::sse(&body));
+
+    let client = mistral::Client::new(mistral::Auth::new("test-key"), server.url())
+        .with_http_options(HttpOptions::default());
+    let body = mistral::MessageBody::new(
+        "mistral-large-latest",
+        vec![mistral::Message::new(mistral::Role::User, "Hi")],
+    );
+    let events = collect(client.delta(&body).unwrap()).await;
+
+    let calls: Vec<_> = events
+        .iter()
+        .filter_map(|event| match event {
+            StreamEvent::ToolCall(call) => Some((call.id.as_str(), call.arguments["city"].clone())),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(
+        calls,
+        vec![("call-1", "Paris".into()), ("call-2", "Rome".into())]
+    );
+}
+
+#[tokio::test]
+async fn mistral_fim_delta() {
+    let server = MockServer::start();
+    server.mock("/fim/completions", Response::sse(fixtures::MISTRAL_FIM));
+
+    let client = mistral_fim::Client::new(mistral_fim::Auth::new("test-key"), server.url())
+        .with_http_options(HttpOptions::default());
+    let body = mistral_fim::MessageBody::new(
+        "codestral-latest",
+        "fn main() {".to_string(),
+        Some("}".to_string()),
+    );
+    let events = collect(client.delta(&body).unwrap()).await;
+
+    assert_eq!(text(&events), "Hello world");
+    assert_eq!(stop_reason(&events), Some("stop"));
+    assert_eq!(events.last(), Some(&StreamEvent::Done));
+
+    let request = server.requests()[0].json();
+    assert_eq!(request["prompt"], "fn main() {");
+    assert_eq!(request["suffix"], "}");
+}
+
+#[tokio::test]
+async fn copilot_delta() {
+    let server = MockServer::start();
+    server
+        .mock("/token", Response::json(200, fixtures::COPILOT_TOKEN))
+        .mock("/chat/completions", Response::sse(fixtures::OPENAI));
+
+    let mut client = copilot::Client::new(copilot::Auth::new("oauth-token"), server.url())
+        .with_http_options(HttpOptions::default());
+    client.token_url = format!("{}/token", server.url());
+    let body = copilot::MessageBody::new(
+        "gpt-4o",
+        vec![copilot::Message::new(copilot::Role::User, "Hi")],
+    );
+    let events = collect(client.delta(&body).unwrap()).await;
+
+    assert_eq!(text(&events), "Hello world");
+
+    let requests = server.requests();
+    assert_eq!(
+        requests[0].header("authorization"),
+        Some("token oauth-token")
+    );
+    assert_eq!(
+        requests[1].header("authorization"),
+        Some("Bearer tid=mock;exp=4102444800")
+    );
+}
+
+#[test]
+fn copilot_token_cache_follows_the_oauth_token() {
+    let cache = std::env::temp_dir().join(format!("es-stream-copilot-{}", std::process::id()));
+    let _ = std::fs::remove_file(&cache);
+
+    let server = MockServer::start();
+    server.mock("/token", Response::json(200, fixtures::COPILOT_TOKEN));
+
+    let client = |oauth_token: &str| {
+        let mut client = copilot::Client::new(copilot::Auth::new(oauth_token), server.url())
+            .with_http_options(HttpOptions::default())
+            .with_token_cache(&cache);
+        client.token_url = format!("{}/token", server.url());
+        client
+    };
+    let exchanges = || {
+        server
+            .requests()
+            .iter()
+            .filter(|request| request.path == "/token")
+            .count()
+    };
+
+    client("oauth-token").session_token().unwrap();
+    client("oauth-token").session_token().unwrap();
+    assert_eq!(exchanges(), 1);
+
+    // A session token cached for another account is not reused.
+    client("other-token").session_token().unwrap();
+    assert_eq!(exchanges(), 2);
+
+    let _ = std::fs::remove_file(&cache);
+}
+
+#[tokio::test]
+async fn ollama_delta() {
+    let server = MockServer::start();
+    server.mock("/api/chat", Response::json(200, fixtures::OLLAMA));
+
+    let client = ollama::Client::new(ollama::Auth::default(), server.url())
+        .with_http_options(HttpOptions::default());
+    let body = ollama::MessageBody::new(
+        "llama3.2",
+        vec![ollama::Message::new(ollama::Role::User, "Hi")],
+    );
+    let events = collect(client.delta(&body).unwrap()).await;
+
+    assert_eq!(text(&events), "Hello world");
+    assert_eq!(stop_reason(&events), Some("stop"));
+    assert_eq!(usage(&events).input_tokens, Some(9));
+    assert_eq!(usage(&events).output_tokens, Some(2));
+    assert_eq!(events.last(), Some(&StreamEvent::Done));
+
+    assert_eq!(server.requests()[0].json()["model"], "llama3.2");
+}
+
+#[tokio::test]
+async fn api_errors_are_reported() {
+    let server = MockServer::start();
+    server.mock(
+        "/chat/completions",
+        Response::json(
+            429,
+            r#"{"error":{"message":"Rate limit reached","type":"requests","code":"rate_limit_exceeded"}}"#,
+        ),
+    );
+
+    let client = openai::Client::new(openai::Auth::new("test-key"), server.url())
+        .with_http_options(HttpOptions::default())
+        .with_retry_policy(RetryPolicy::none());
+    let body = openai::MessageBody::new(
+        "gpt-4o",
+        vec![openai::Message::new(openai::Role::User, "Hi")],
+    );
+    let error = client
+        .delta(&body)
+        .unwrap()
+        .try_collect::<Vec<_>>()
+        .await
+        .unwrap_err();
+
+    match error {
+        Error::ApiError(details) => {
+            assert_eq!(details.status, Some(429));
+            assert_eq!(details.message, "Rate limit reached");
+        }
+        e => panic!("unexpected error: {e}"),
+    }
+}
+
+#[tokio::test]
+async fn truncated_streams_are_reported() {
+    let server = MockServer::start();
+    server.mock(
+        "/chat/completions",
+        Response::sse(&fixtures::OPENAI.replace("data: [DONE]\n", "")),
+    );
+    server.mock(
+        "/api/chat",
+        Response::json(200, fixtures::OLLAMA.lines().next().unwrap()),
+    );
+
+    let client = openai::Client::new(openai::Auth::new("test-key"), server.url())
+        .with_http_options(HttpOptions::default())
+        .with_retry_policy(RetryPolicy::none());
+    let body = openai::MessageBody::new(
+        "gpt-4o",
+        vec![openai::Message::new(openai::Role::User, "Hi")],
+    );
+    let events: Vec<_> = client.delta(&body).unwrap().collect().await;
+
+    assert!(events
+        .iter()
+        .all(|event| !matches!(event, Ok(StreamEvent::Done))));
+    assert!(matches!(
+        events.last(),
+        Some(Err(Error::EventsourceClient(
+            eventsource_client::Error::UnexpectedEof
+        )))
+    ));
+
+    let client = ollama::Client::new(ollama::Auth::default(), server.url())
+        .with_http_options(HttpOptions::default())
+        .with_retry_policy(RetryPolicy::none());
+    let body = ollama::MessageBody::new(
+        "llama3.2",
+        vec![ollama::Message::new(ollama::Role::User, "Hi")],
+    );
+    let events: Vec<_> = client.delta(&body).unwrap().collect().await;
+
+    assert!(matches!(
+        events.first(),
+        Some(Ok(StreamEvent::MessageStart { .. }))
+    ));
+    assert!(matches!(
+        events.last(),
+        Some(Err(Error::EventsourceClient(
+            eventsource_client::Error::UnexpectedEof
+        )))
+    ));
+}
+
+#[test]
+fn anthropic_response_schema_keeps_user_tools_available() {
+    let schema = serde_json::json!({"type": "object"});
+    let mut request = ChatRequest {
+        response_schema: Some(schema.clone()),
+        ..Default::default()
+    };
+
+    let body = serde_json::to_value(anthropic::MessageBody::from(&request)).unwrap();
+    assert_eq!(body["tool_choice"]["type"], "tool");
+    assert_eq!(body["tool_choice"]["name"], "json_response");
+
+    request.tools = Some(vec![Tool::new("search", None, schema)]);
+    let body = serde_json::to_value(anthropic::MessageBody::from(&request)).unwrap();
+    assert_eq!(body["tool_choice"]["type"], "any");
+    assert_eq!(body["tools"].as_array().unwrap().len(), 2);
+}
+
+#[test]
+fn attachments_of_unknown_type() {
+    let dir = std::env::temp_dir().join(format!("es-stream-attachments-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("notes.unknown"), "Some notes").unwrap();
+    std::fs::write(dir.join("blob.unknown"), [0xff, 0x00, 0xfe]).unwrap();
+
+    let notes = Attachment::from_path(dir.join("notes.unknown")).unwrap();
+    assert_eq!(notes.mime_type, "text/plain");
+
+    let error = Attachment::from_path(dir.join("blob.unknown")).unwrap_err();
+    assert!(
+        error.to_string().contains("application/octet-stream"),
+        "{error}"
+    );
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
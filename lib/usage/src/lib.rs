@@ -0,0 +1,265 @@
+use std::collections::{BTreeMap, HashMap};
+use std::io::{BufRead, Write};
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+/// Ledger shared by every CLI, unless they are configured to use another file.
+pub const DEFAULT_LEDGER: &str = "~/.e/usage.jsonl";
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("io error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("unable to serialize the usage record: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("unable to parse the pricing of {0:?}: {1}")]
+    Pricing(PathBuf, toml::de::Error),
+    #[error("HOME not found")]
+    Home,
+}
+
+/// Price of a model in USD per million tokens.
+#[derive(Debug, Default, Clone, Copy, Serialize, Deserialize)]
+pub struct Price {
+    pub input: f64,
+    pub output: f64,
+}
+
+/// Columns used to group the usage report.
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GroupBy {
+    Day,
+    Model,
+    /// The provider the request was sent to.
+    #[value(alias = "vendor")]
+    Api,
+    Preset,
+}
+
+/// A request stored on the usage ledger.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Record {
+    pub timestamp: DateTime<Utc>,
+    /// The provider the request was sent to, e.g. `openai`.
+    #[serde(alias = "vendor")]
+    pub api: String,
+    pub model: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub preset: Option<String>,
+    pub input_tokens: Option<u32>,
+    pub output_tokens: Option<u32>,
+    /// Estimated cost in USD. Only set when the model has a price on the pricing table.
+    pub cost: Option<f64>,
+}
+
+impl Record {
+    /// Creates a record for a request sent now, estimating its cost from the `pricing` table.
+    pub fn new(
+        api: impl Into<String>,
+        model: impl Into<String>,
+        preset: Option<String>,
+        input_tokens: Option<u32>,
+        output_tokens: Option<u32>,
+        pricing: &HashMap<String, Price>,
+    ) -> Self {
+        let model = model.into();
+        let cost = price(pricing, &model).map(|price| {
+            (f64::from(input_tokens.unwrap_or_default()) * price.input
+                + f64::from(output_tokens.unwrap_or_default()) * price.output)
+                / 1_000_000.0
+        });
+
+        Self {
+            timestamp: Utc::now(),
+            api: api.into(),
+            model,
+            preset,
+            input_tokens,
+            output_tokens,
+            cost,
+        }
+    }
+
+    /// One line summary printed after the response.
+    pub fn summary(&self) -> String {
+        format!(
+            "model: {}, input tokens: {}, output tokens: {}, cost: {}",
+            self.model,
+            tokens(self.input_tokens),
+            tokens(self.output_tokens),
+            cost(self.cost)
+        )
+    }
+}
+
+/// Finds the price of `model`. Providers usually answer with a dated version of the model
+/// (e.g. `gpt-4o-2024-08-06`), so the longest configured prefix is used when there is no exact
+/// match.
+pub fn price(pricing: &HashMap<String, Price>, model: &str) -> Option<Price> {
+    pricing.get(model).copied().or_else(|| {
+        pricing
+            .iter()
+            .filter(|(name, _)| model.starts_with(name.as_str()))
+            .max_by_key(|(name, _)| name.len())
+            .map(|(_, price)| *price)
+    })
+}
+
+/// Reads the `[pricing]` table of a TOML config file, keyed by model name. A missing file or
+/// table has no prices.
+pub fn pricing(config_file: &Path) -> Result<HashMap<String, Price>, Error> {
+    #[derive(Deserialize)]
+    struct Config {
+        pricing: Option<HashMap<String, Price>>,
+    }
+
+    if !config_file.is_file() {
+        return Ok(HashMap::new());
+    }
+
+    let content = std::fs::read_to_string(config_file)?;
+    let config: Config =
+        toml::from_str(&content).map_err(|e| Error::Pricing(config_file.to_path_buf(), e))?;
+
+    Ok(config.pricing.unwrap_or_default())
+}
+
+/// Path of the usage ledger, `usage_file` or the `DEFAULT_LEDGER`.
+pub fn ledger_path(usage_file: Option<&str>) -> Result<PathBuf, Error> {
+    let home = std::env::var("HOME").map_err(|_| Error::Home)?;
+
+    Ok(PathBuf::from(
+        usage_file.unwrap_or(DEFAULT_LEDGER).replace('~', &home),
+    ))
+}
+
+/// Appends `record` to the ledger stored on `path`.
+pub fn append(path: &Path, record: &Record) -> Result<(), Error> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)?;
+    writeln!(file, "{}", serde_json::to_string(record)?)?;
+
+    Ok(())
+}
+
+/// Reads every record of the ledger stored on `path`. Lines that can't be parsed are skipped.
+pub fn load(path: &Path) -> Result<Vec<Record>, Error> {
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+
+    let mut records = Vec::new();
+
+    for line in std::io::BufReader::new(std::fs::File::open(path)?).lines() {
+        let line = line?;
+
+        match serde_json::from_str(&line) {
+            Ok(record) => records.push(record),
+            Err(e) => log::warn!("skipping invalid usage record {:?}: {}", line, e),
+        }
+    }
+
+    Ok(records)
+}
+
+#[derive(Debug, Default)]
+struct Total {
+    requests: usize,
+    input_tokens: u64,
+    output_tokens: u64,
+    cost: Option<f64>,
+}
+
+impl Total {
+    fn add(&mut self, record: &Record) {
+        self.requests += 1;
+        self.input_tokens += u64::from(record.input_tokens.unwrap_or_default());
+        self.output_tokens += u64::from(record.output_tokens.unwrap_or_default());
+        if let Some(cost) = record.cost {
+            self.cost = Some(self.cost.unwrap_or_default() + cost);
+        }
+    }
+
+    fn row(&self, mut key: Vec<String>) -> Vec<String> {
+        key.extend([
+            self.requests.to_string(),
+            self.input_tokens.to_string(),
+            self.output_tokens.to_string(),
+            cost(self.cost),
+        ]);
+        key
+    }
+}
+
+/// Prints the totals of `records` grouped by the `group_by` columns.
+pub fn report(records: &[Record], group_by: &[GroupBy]) {
+    let mut groups: BTreeMap<Vec<String>, Total> = BTreeMap::new();
+    let mut total = Total::default();
+
+    for record in records {
+        let key = group_by
+            .iter()
+            .map(|column| match column {
+                GroupBy::Day => record.timestamp.format("%Y-%m-%d").to_string(),
+                GroupBy::Model => record.model.clone(),
+                GroupBy::Api => record.api.to_lowercase(),
+                GroupBy::Preset => record.preset.clone().unwrap_or_else(|| "-".to_string()),
+            })
+            .collect::<Vec<_>>();
+
+        groups.entry(key).or_default().add(record);
+        total.add(record);
+    }
+
+    let mut header = group_by
+        .iter()
+        .map(|column| format!("{column:?}").to_uppercase())
+        .collect::<Vec<_>>();
+    header.extend(["REQUESTS", "INPUT", "OUTPUT", "COST"].map(String::from));
+
+    let mut rows = vec![header];
+    rows.extend(groups.iter().map(|(key, group)| group.row(key.clone())));
+    rows.push(
+        total.row(
+            std::iter::once("TOTAL".to_string())
+                .chain(std::iter::repeat(String::new()))
+                .take(group_by.len())
+                .collect(),
+        ),
+    );
+
+    let widths = (0..rows[0].len())
+        .map(|i| {
+            rows.iter()
+                .map(|row| row[i].len())
+                .max()
+                .unwrap_or_default()
+        })
+        .collect::<Vec<_>>();
+
+    for row in rows {
+        let line = row
+            .iter()
+            .zip(&widths)
+            .map(|(cell, width)| format!("{cell:<width$}"))
+            .collect::<Vec<_>>()
+            .join("  ");
+        println!("{}", line.trim_end());
+    }
+}
+
+fn tokens(tokens: Option<u32>) -> String {
+    tokens.map_or_else(|| "-".to_string(), |tokens| tokens.to_string())
+}
+
+fn cost(cost: Option<f64>) -> String {
+    cost.map_or_else(|| "-".to_string(), |cost| format!("${cost:.4}"))
+}